@@ -0,0 +1,682 @@
+/*
+ *  Owned Rust tree built from the cmark parse tree.
+ *
+ *  The C tree is walked exactly once, every node is copied over into a
+ *  `Tree`, and the C memory is freed right after. Everything afterwards is
+ *  plain Rust: converters can walk the tree the same way they used to walk
+ *  `Iter`, or look at whole subtrees at once, and transforms can rearrange
+ *  the tree before anything gets rendered.
+ */
+
+use ::bind;
+
+use super::*;
+
+use std::ffi;
+
+/// An owned node of a `Document`, along with all of its children.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Tree {
+    pub node: Node,
+    pub span: SourceSpan,
+    pub children: Vec<Tree>,
+    /// Extra HTML attributes for this node, as `(name, value)` pairs. An empty
+    /// value stands for a boolean attribute. cmark has nowhere to keep these,
+    /// so they are lost when the tree goes back through cmark.
+    pub attributes: Vec<(String, String)>,
+}
+
+impl Tree {
+    /// Creates a tree with a single node and no children. The span is left
+    /// unknown.
+    pub fn new(node: Node) -> Self {
+        Self {
+            node: node,
+            span: SourceSpan::default(),
+            children: Vec::new(),
+            attributes: Vec::new(),
+        }
+    }
+
+    /// Creates a tree with the given children, checking that this node can
+    /// hold each one of them.
+    pub fn with_children(node: Node, children: Vec<Tree>) -> Result<Self, TreeErr> {
+        let mut res = Self::new(node);
+
+        for child in children {
+            res.append_child(child)?;
+        }
+
+        Ok(res)
+    }
+
+    /*
+     *  Mutation. These mirror `cmark_node_append_child` and friends, and
+     *  refuse to build trees that cmark would refuse to build.
+     */
+
+    fn check_child(&self, child: &Tree) -> Result<(), TreeErr> {
+        match self.node.can_contain(&child.node) {
+            true => Ok(()),
+            false => Err(TreeErr::CannotContain(
+                self.node.clone(),
+                child.node.clone()
+            )),
+        }
+    }
+
+    fn check_index(&self, idx: usize) -> Result<(), TreeErr> {
+        match idx < self.children.len() {
+            true => Ok(()),
+            false => Err(TreeErr::OutOfBounds(idx)),
+        }
+    }
+
+    /// Adds `child` to the end of the children of this tree.
+    pub fn append_child(&mut self, child: Tree) -> Result<(), TreeErr> {
+        self.check_child(&child)?;
+        self.children.push(child);
+        Ok(())
+    }
+
+    /// Adds `child` to the beginning of the children of this tree.
+    pub fn prepend_child(&mut self, child: Tree) -> Result<(), TreeErr> {
+        self.check_child(&child)?;
+        self.children.insert(0, child);
+        Ok(())
+    }
+
+    /// Inserts `sibling` right before the child at `idx`.
+    pub fn insert_before(&mut self, idx: usize, sibling: Tree) -> Result<(), TreeErr> {
+        self.check_index(idx)?;
+        self.check_child(&sibling)?;
+        self.children.insert(idx, sibling);
+        Ok(())
+    }
+
+    /// Inserts `sibling` right after the child at `idx`.
+    pub fn insert_after(&mut self, idx: usize, sibling: Tree) -> Result<(), TreeErr> {
+        self.check_index(idx)?;
+        self.check_child(&sibling)?;
+        self.children.insert(idx + 1, sibling);
+        Ok(())
+    }
+
+    /// Removes the child at `idx` and hands it back, along with its own
+    /// children.
+    pub fn unlink(&mut self, idx: usize) -> Result<Tree, TreeErr> {
+        self.check_index(idx)?;
+        Ok(self.children.remove(idx))
+    }
+
+    /// Puts `new` in place of the child at `idx`, and hands back the old one.
+    pub fn replace(&mut self, idx: usize, new: Tree) -> Result<Tree, TreeErr> {
+        self.check_index(idx)?;
+        self.check_child(&new)?;
+        Ok(::std::mem::replace(&mut self.children[idx], new))
+    }
+
+    /// Moves the child at `from` so that it ends up at position `to`, shifting
+    /// the children in between.
+    pub fn move_child(&mut self, from: usize, to: usize) -> Result<(), TreeErr> {
+        self.check_index(from)?;
+        self.check_index(to)?;
+        let child = self.children.remove(from);
+        self.children.insert(to, child);
+        Ok(())
+    }
+
+    /// Swaps the children at `a` and `b`.
+    pub fn swap_children(&mut self, a: usize, b: usize) -> Result<(), TreeErr> {
+        self.check_index(a)?;
+        self.check_index(b)?;
+        self.children.swap(a, b);
+        Ok(())
+    }
+
+    /// Removes all children for which `pred` returns `false`. Only the direct
+    /// children are looked at.
+    pub fn retain_children<P>(&mut self, pred: P)
+        where P: FnMut(&Tree) -> bool
+    {
+        self.children.retain(pred);
+    }
+
+    /// Calls `f` on this tree and every one of its descendants, parents
+    /// before children. `f` may freely rearrange the children of the tree it
+    /// is given; the rearranged children are the ones visited next.
+    pub fn for_each_mut<F>(&mut self, mut f: F)
+        where F: FnMut(&mut Tree)
+    {
+        let mut stack: Vec<&mut Tree> = vec![self];
+
+        while let Some(tree) = stack.pop() {
+            f(tree);
+            stack.extend(tree.children.iter_mut().rev());
+        }
+    }
+
+    /// Walks this tree in document order. See `Walk`.
+    pub fn walk(&self) -> Walk<'_> {
+        Walk {
+            start: Some(self),
+            stack: Vec::new(),
+        }
+    }
+
+    /// Iterates over this tree and all of its descendants in document order.
+    pub fn descendants(&self) -> Descendants<'_> {
+        Descendants {
+            walk: self.walk(),
+        }
+    }
+
+    /// Returns the first subtree, in document order, satisfying `pred`. This
+    /// tree itself is included in the search.
+    pub fn find<P>(&self, mut pred: P) -> Option<&Tree>
+        where P: FnMut(&Tree) -> bool
+    {
+        self.descendants().find(|tree| pred(tree))
+    }
+
+    /// Returns all subtrees, in document order, satisfying `pred`. This tree
+    /// itself is included in the search.
+    pub fn find_all<P>(&self, mut pred: P) -> Vec<&Tree>
+        where P: FnMut(&Tree) -> bool
+    {
+        self.descendants().filter(|tree| pred(tree)).collect()
+    }
+
+    /// Returns the plain text contained in this tree, with all markup
+    /// flattened away. Breaks become single spaces.
+    pub fn text(&self) -> String {
+        let mut res = String::new();
+
+        for tree in self.descendants() {
+            match tree.node {
+                Node::Inline(Inline::Text(ref lit)) |
+                Node::Inline(Inline::Code(ref lit)) => res.push_str(lit),
+
+                Node::Inline(Inline::SoftBreak) |
+                Node::Inline(Inline::LineBreak) => res.push(' '),
+
+                _ => (),
+            }
+        }
+
+        res
+    }
+}
+
+/// Iterator over a `Tree` yielding the same `(node, event)` sequence as
+/// `Iter` does: every non-leaf node is entered and then exited, while leaf
+/// nodes are only entered.
+pub struct Walk<'a> {
+    start: Option<&'a Tree>,
+    stack: Vec<(&'a Tree, usize)>,
+}
+
+impl<'a> Iterator for Walk<'a> {
+    type Item = (&'a Tree, Event);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(tree) = self.start.take() {
+            if !tree.node.is_leaf() {
+                self.stack.push((tree, 0));
+            }
+
+            return Some((tree, Event::Enter));
+        }
+
+        let (tree, idx) = match self.stack.last_mut() {
+            Some(&mut (tree, ref mut idx)) => {
+                *idx += 1;
+                (tree, *idx - 1)
+            },
+
+            None => return None,
+        };
+
+        match tree.children.get(idx) {
+            Some(child) => {
+                if !child.node.is_leaf() {
+                    self.stack.push((child, 0));
+                }
+
+                Some((child, Event::Enter))
+            },
+
+            None => {
+                self.stack.pop();
+                Some((tree, Event::Exit))
+            },
+        }
+    }
+}
+
+/// Iterator over a `Tree` and all of its descendants in document order.
+pub struct Descendants<'a> {
+    walk: Walk<'a>,
+}
+
+impl<'a> Iterator for Descendants<'a> {
+    type Item = &'a Tree;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.walk.next() {
+                Some((tree, Event::Enter)) => return Some(tree),
+                Some((_, Event::Exit)) => continue,
+                None => return None,
+            }
+        }
+    }
+}
+
+/// Errors that can occur when rearranging a `Tree`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TreeErr {
+    /// The first node cannot hold the second one as a child, e.g. a list
+    /// holding a paragraph directly.
+    CannotContain(Node, Node),
+
+    /// There is no child at the given index.
+    OutOfBounds(usize),
+}
+
+/// A whole parsed document, owned by Rust. The root of the tree is always a
+/// `Block::Document`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Document {
+    root: Tree,
+}
+
+impl Document {
+    /// Creates an empty document, to be filled in by hand.
+    pub fn new() -> Self {
+        Self {
+            root: Tree::new(Node::Block(Block::Document)),
+        }
+    }
+
+    /// Consumes the parser, copies the parsed C tree over and frees it.
+    pub fn from_parser(parser: Parser) -> Result<Self, Error> {
+        let raw_root = parser.finish();
+        let root = tree_from_raw(raw_root);
+        unsafe { bind::cmark_node_free(raw_root); }
+
+        Ok(Self {
+            root: root?,
+        })
+    }
+
+    pub fn root(&self) -> &Tree {
+        &self.root
+    }
+
+    /// The root may be rearranged at will, but always stays a
+    /// `Block::Document`.
+    pub fn root_mut(&mut self) -> &mut Tree {
+        &mut self.root
+    }
+
+    /// Walks the whole document. See `Walk`.
+    pub fn walk(&self) -> Walk<'_> {
+        self.root.walk()
+    }
+
+    /// Builds a C tree out of this document, for cmark's renderers to chew on.
+    pub(super) fn to_raw(&self) -> Result<RawTree, Error> {
+        let root = RawTree::from_node(&self.root.node)?;
+        let mut stack: Vec<(*mut bind::cmark_node, &Tree)> =
+            vec![(root.as_ptr(), &self.root)];
+
+        while let Some((raw_parent, parent)) = stack.pop() {
+            for child in parent.children.iter() {
+                //  until it is appended, the child is ours to free
+                let raw_child = RawTree::from_node(&child.node)?;
+
+                match unsafe {
+                    bind::cmark_node_append_child(raw_parent, raw_child.as_ptr())
+                } {
+                    1 => (),
+                    _ => return Err(Error::Tree(TreeErr::CannotContain(
+                        parent.node.clone(),
+                        child.node.clone()
+                    ))),
+                };
+
+                stack.push((raw_child.into_ptr(), child));
+            }
+        }
+
+        Ok(root)
+    }
+}
+
+/// A C tree owned by Rust, freed when dropped.
+pub(super) struct RawTree {
+    raw: *mut bind::cmark_node,
+}
+
+impl RawTree {
+    /// Creates a childless C node with the same contents as `node`.
+    fn from_node(node: &Node) -> Result<Self, Error> {
+        use bind::cmark_node_type::*;
+
+        let res = Self {
+            raw: unsafe { bind::cmark_node_new(match node {
+                &Node::Block(Block::Document) => CMARK_NODE_DOCUMENT,
+                &Node::Block(Block::Blockquote) => CMARK_NODE_BLOCK_QUOTE,
+                &Node::Block(Block::List(..)) => CMARK_NODE_LIST,
+                &Node::Block(Block::Item) => CMARK_NODE_ITEM,
+                &Node::Block(Block::CodeBlock(..)) => CMARK_NODE_CODE_BLOCK,
+                &Node::Block(Block::HtmlBlock(..)) => CMARK_NODE_HTML_BLOCK,
+                &Node::Block(Block::CustomBlock) => CMARK_NODE_CUSTOM_BLOCK,
+                &Node::Block(Block::Paragraph) => CMARK_NODE_PARAGRAPH,
+                &Node::Block(Block::Heading(..)) => CMARK_NODE_HEADING,
+                &Node::Block(Block::ThematicBreak) => CMARK_NODE_THEMATIC_BREAK,
+                &Node::Inline(Inline::Text(..)) => CMARK_NODE_TEXT,
+                &Node::Inline(Inline::SoftBreak) => CMARK_NODE_SOFTBREAK,
+                &Node::Inline(Inline::LineBreak) => CMARK_NODE_LINEBREAK,
+                &Node::Inline(Inline::Code(..)) => CMARK_NODE_CODE,
+                &Node::Inline(Inline::HtmlInline(..)) => CMARK_NODE_HTML_INLINE,
+                &Node::Inline(Inline::CustomInline) => CMARK_NODE_CUSTOM_INLINE,
+                &Node::Inline(Inline::Emph) => CMARK_NODE_EMPH,
+                &Node::Inline(Inline::Strong) => CMARK_NODE_STRONG,
+                &Node::Inline(Inline::Link(..)) => CMARK_NODE_LINK,
+                &Node::Inline(Inline::Image(..)) => CMARK_NODE_IMAGE,
+            }) },
+        };
+
+        let raw = res.raw;
+
+        match node {
+            &Node::Block(Block::List(ref ty, ref delim, ref start, ref tightness)) => unsafe {
+                bind::cmark_node_set_list_type(raw, match ty {
+                    &ListType::Bullet => bind::cmark_list_type::CMARK_BULLET_LIST,
+                    &ListType::Ordered => bind::cmark_list_type::CMARK_ORDERED_LIST,
+                });
+
+                bind::cmark_node_set_list_delim(raw, match delim {
+                    &DelimType::No => bind::cmark_delim_type::CMARK_NO_DELIM,
+                    &DelimType::Period => bind::cmark_delim_type::CMARK_PERIOD_DELIM,
+                    &DelimType::Paren => bind::cmark_delim_type::CMARK_PAREN_DELIM,
+                });
+
+                bind::cmark_node_set_list_start(raw, **start as raw::c_int);
+                bind::cmark_node_set_list_tight(raw, **tightness as raw::c_int);
+            },
+
+            &Node::Block(Block::CodeBlock(ref info, ref lit)) => {
+                let info = to_cstring(info)?;
+                let lit = to_cstring(lit)?;
+
+                unsafe {
+                    bind::cmark_node_set_fence_info(raw, info.as_ptr());
+                    bind::cmark_node_set_literal(raw, lit.as_ptr());
+                }
+            },
+
+            &Node::Block(Block::HtmlBlock(ref lit)) |
+            &Node::Inline(Inline::Text(ref lit)) |
+            &Node::Inline(Inline::Code(ref lit)) |
+            &Node::Inline(Inline::HtmlInline(ref lit)) => {
+                let lit = to_cstring(lit)?;
+                unsafe { bind::cmark_node_set_literal(raw, lit.as_ptr()); }
+            },
+
+            &Node::Block(Block::Heading(ref lvl)) => unsafe {
+                bind::cmark_node_set_heading_level(raw, lvl.as_usize() as raw::c_int);
+            },
+
+            &Node::Inline(Inline::Link(ref url, ref title)) |
+            &Node::Inline(Inline::Image(ref url, ref title)) => {
+                let url = to_cstring(url)?;
+                let title = to_cstring(title)?;
+
+                unsafe {
+                    bind::cmark_node_set_url(raw, url.as_ptr());
+                    bind::cmark_node_set_title(raw, title.as_ptr());
+                }
+            },
+
+            _ => (),
+        };
+
+        Ok(res)
+    }
+
+    pub(super) fn as_ptr(&self) -> *mut bind::cmark_node {
+        self.raw
+    }
+
+    /// Gives up ownership, e.g. once the node has been handed to a parent.
+    fn into_ptr(self) -> *mut bind::cmark_node {
+        let raw = self.raw;
+        ::std::mem::forget(self);
+        raw
+    }
+}
+
+impl Drop for RawTree {
+    fn drop(&mut self) {
+        unsafe { bind::cmark_node_free(self.raw); }
+    }
+}
+
+fn to_cstring(s: &str) -> Result<ffi::CString, Error> {
+    match ffi::CString::new(s) {
+        Ok(res) => Ok(res),
+        Err(e) => Err(Error::Nul(e)),
+    }
+}
+
+impl Default for Document {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Copies the C tree rooted at `raw_root` into a `Tree`. Uses a `cmark_iter`
+/// rather than recursion, since documents can be nested arbitrarily deep.
+fn tree_from_raw(raw_root: *mut bind::cmark_node) -> Result<Tree, Error> {
+    let raw_iter = unsafe { bind::cmark_iter_new(raw_root) };
+    let mut stack: Vec<Tree> = Vec::new();
+    let mut root = None;
+
+    let res = loop {
+        let event = unsafe { bind::cmark_iter_next(raw_iter) };
+
+        let tree = match event {
+            bind::cmark_event_type::CMARK_EVENT_ENTER => {
+                let raw_node = unsafe { bind::cmark_iter_get_node(raw_iter) };
+
+                let mut tree = match node_from_raw(raw_node) {
+                    Ok(node) => Tree::new(node),
+                    Err(e) => break Err(e),
+                };
+                tree.span = span_from_raw(raw_node);
+
+                match tree.node.is_leaf() {
+                    true => tree,
+
+                    false => {
+                        stack.push(tree);
+                        continue;
+                    },
+                }
+            },
+
+            bind::cmark_event_type::CMARK_EVENT_EXIT => match stack.pop() {
+                Some(tree) => tree,
+                None => break Err(Error::InvalidNode(
+                    span_from_raw(unsafe { bind::cmark_iter_get_node(raw_iter) }),
+                    "exiting a node that was never entered"
+                )),
+            },
+
+            bind::cmark_event_type::CMARK_EVENT_DONE => match root.take() {
+                Some(tree) => break Ok(tree),
+                None => break Err(Error::InvalidNode(
+                    SourceSpan::default(),
+                    "parser did not produce a document"
+                )),
+            },
+
+            bind::cmark_event_type::CMARK_EVENT_NONE =>
+                break Err(Error::InvalidNode(
+                    SourceSpan::default(),
+                    "iterator reported no event"
+                )),
+        };
+
+        match stack.last_mut() {
+            Some(parent) => parent.children.push(tree),
+            None => root = Some(tree),
+        }
+    };
+
+    unsafe { bind::cmark_iter_free(raw_iter); }
+
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(s: &str) -> Tree {
+        Tree::new(Node::Inline(Inline::Text(Literal::from(s.to_owned()))))
+    }
+
+    fn texts(tree: &Tree) -> Vec<String> {
+        tree.children.iter().map(Tree::text).collect()
+    }
+
+    #[test]
+    fn invalid_children() {
+        let mut emph = Tree::new(Node::Inline(Inline::Emph));
+        let paragraph = Tree::new(Node::Block(Block::Paragraph));
+
+        assert_eq!(
+            emph.append_child(paragraph.clone()),
+            Err(TreeErr::CannotContain(emph.node.clone(), paragraph.node.clone()))
+        );
+        assert!(emph.children.is_empty());
+
+        let list = Node::Block(Block::List(
+            ListType::Bullet,
+            DelimType::No,
+            StartingNumber::from(0),
+            Tightness::from(true)
+        ));
+        assert!(Tree::with_children(list, vec![paragraph.clone()]).is_err());
+        assert!(paragraph.clone().append_child(Tree::new(Node::Block(Block::Document))).is_err());
+
+        //  trees built by hand past the checks are still refused by cmark
+        let mut document = Document::new();
+        document.root_mut().children.push(text("loose"));
+        match render_html(&document, Options::DEFAULT) {
+            Err(Error::Tree(TreeErr::CannotContain(..))) => (),
+            res => panic!("expected a tree error, got {:?}", res),
+        };
+    }
+
+    #[test]
+    fn indices() {
+        let mut paragraph = Tree::with_children(
+            Node::Block(Block::Paragraph),
+            vec![text("a"), text("b"), text("c")]
+        ).unwrap();
+
+        paragraph.move_child(0, 2).unwrap();
+        assert_eq!(texts(&paragraph), vec!["b", "c", "a"]);
+        paragraph.move_child(2, 0).unwrap();
+        assert_eq!(texts(&paragraph), vec!["a", "b", "c"]);
+        paragraph.move_child(1, 1).unwrap();
+        assert_eq!(texts(&paragraph), vec!["a", "b", "c"]);
+        assert_eq!(paragraph.move_child(3, 0), Err(TreeErr::OutOfBounds(3)));
+        assert_eq!(paragraph.move_child(0, 3), Err(TreeErr::OutOfBounds(3)));
+
+        paragraph.insert_after(2, text("d")).unwrap();
+        assert_eq!(texts(&paragraph), vec!["a", "b", "c", "d"]);
+        assert_eq!(paragraph.insert_after(4, text("e")), Err(TreeErr::OutOfBounds(4)));
+        assert_eq!(texts(&paragraph), vec!["a", "b", "c", "d"]);
+
+        let mut empty = Tree::new(Node::Block(Block::Paragraph));
+        assert_eq!(empty.insert_after(0, text("a")), Err(TreeErr::OutOfBounds(0)));
+        assert_eq!(empty.insert_before(0, text("a")), Err(TreeErr::OutOfBounds(0)));
+    }
+
+    #[test]
+    fn mutators() {
+        let mut paragraph = Tree::with_children(
+            Node::Block(Block::Paragraph),
+            vec![text("b"), text("c")]
+        ).unwrap();
+
+        paragraph.prepend_child(text("a")).unwrap();
+        assert_eq!(texts(&paragraph), vec!["a", "b", "c"]);
+        assert!(paragraph.prepend_child(Tree::new(Node::Block(Block::Paragraph))).is_err());
+
+        assert_eq!(paragraph.unlink(1).unwrap().text(), "b");
+        assert_eq!(texts(&paragraph), vec!["a", "c"]);
+        assert_eq!(paragraph.unlink(2), Err(TreeErr::OutOfBounds(2)));
+
+        assert_eq!(paragraph.replace(0, text("z")).unwrap().text(), "a");
+        assert_eq!(texts(&paragraph), vec!["z", "c"]);
+        assert!(paragraph.replace(0, Tree::new(Node::Block(Block::ThematicBreak))).is_err());
+        assert_eq!(paragraph.replace(2, text("y")), Err(TreeErr::OutOfBounds(2)));
+
+        paragraph.swap_children(0, 1).unwrap();
+        assert_eq!(texts(&paragraph), vec!["c", "z"]);
+        assert_eq!(paragraph.swap_children(0, 2), Err(TreeErr::OutOfBounds(2)));
+
+        paragraph.append_child(text("zz")).unwrap();
+        let found: Vec<String> = paragraph.find_all(|tree| tree.text().starts_with('z'))
+            .into_iter()
+            .map(Tree::text)
+            .collect();
+        assert_eq!(found, vec!["z", "zz"]);
+
+        paragraph.retain_children(|tree| tree.text() != "z");
+        assert_eq!(texts(&paragraph), vec!["c", "zz"]);
+    }
+
+    #[test]
+    fn round_trip() {
+        let markdown = "\
+# Heading *with* `code`
+
+Text with a [link](/url \"title\"), an ![image](/img.png), <b>html</b>,\n\
+a soft break and a hard one  \n\
+here.
+
+3. three
+4. four
+
+- loose
+
+- list
+  > quote
+
+```rust
+fn main() {}
+```
+
+<div>block</div>
+
+***
+";
+
+        let mut parser = Parser::new(Options::DEFAULT);
+        parser.feed(markdown.as_bytes());
+        let document = Document::from_parser(parser).unwrap();
+
+        assert_eq!(
+            render_html(&document, Options::DEFAULT).unwrap(),
+            markdown_to_html(markdown, markdown.len(), Options::DEFAULT).unwrap()
+        );
+    }
+}
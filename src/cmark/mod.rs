@@ -17,14 +17,100 @@
  *      iterator should tell you what node you are at and whether you are
  *      entering or exiting the node. (We will have to study the behavior of
  *      events)
+ *  4.  Alternatively, turn the parser into a `Document`, an owned Rust tree
+ *      that can be walked, queried and matched against at leisure.
  */
 
 use ::bind;
 
+mod document;
+pub use self::document::*;
+
+use std::error;
 use std::ffi;
+use std::fmt;
+use std::io;
+use std::io::Read;
 use std::os::raw;
 use std::str;
 
+/*
+ *  Errors, shared by everything in this module.
+ */
+
+/// Everything that can go wrong when talking to cmark, or when converting
+/// what cmark hands back.
+#[derive(Debug)]
+pub enum Error {
+    /// A string handed to cmark contains a NUL byte.
+    Nul(ffi::NulError),
+
+    /// A string handed back by cmark is not valid UTF-8.
+    Utf8(str::Utf8Error),
+
+    /// cmark handed back a node in a state it should never be in, e.g. a
+    /// heading of level 7. Comes with the position of the node, if known.
+    InvalidNode(SourceSpan, &'static str),
+
+    /// A tree was rearranged into a shape cmark would not accept.
+    Tree(TreeErr),
+
+    /// Reading the markdown or writing the output failed.
+    Io(io::Error),
+}
+
+impl Error {
+    /// Returns where in the source the error happened, if known.
+    pub fn span(&self) -> Option<SourceSpan> {
+        match self {
+            &Error::InvalidNode(span, _) if !span.is_unknown() => Some(span),
+            _ => None,
+        }
+    }
+}
+
+/// Does not include the span; see `Error::span`.
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &Error::Nul(ref e) => write!(f, "unexpected NUL byte: {}", e),
+            &Error::Utf8(ref e) => write!(f, "invalid UTF-8: {}", e),
+            &Error::InvalidNode(_, what) => write!(f, "invalid node: {}", what),
+            &Error::Tree(TreeErr::CannotContain(ref parent, ref child)) =>
+                write!(f, "{:?} cannot contain {:?}", parent, child),
+            &Error::Tree(TreeErr::OutOfBounds(idx)) =>
+                write!(f, "no child at index {}", idx),
+            &Error::Io(ref e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+impl From<ffi::NulError> for Error {
+    fn from(e: ffi::NulError) -> Self {
+        Error::Nul(e)
+    }
+}
+
+impl From<str::Utf8Error> for Error {
+    fn from(e: str::Utf8Error) -> Self {
+        Error::Utf8(e)
+    }
+}
+
+impl From<TreeErr> for Error {
+    fn from(e: TreeErr) -> Self {
+        Error::Tree(e)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
 /*
  *  Converter from C-style string to Rust string.
  *  Used a bunch in this module.
@@ -58,22 +144,15 @@ fn raw_to_string(
  *  The naive Markdown-to-HTML converter supplied by cmark.
  */
 
-/// Errors that can occur when calling `markdown_to_html`.
-#[derive(Debug)]
-pub enum MarkdownToHtmlErr {
-    Nul(ffi::NulError),
-    Utf8(str::Utf8Error),
-}
-
 /// Wrapper around `cmark_markdown_to_html`.
 pub fn markdown_to_html(
     text: &str,
     len: usize,
     options: Options
-) -> Result<String, MarkdownToHtmlErr> {
+) -> Result<String, Error> {
     let cstring = match ffi::CString::new(text.as_bytes()) {
         Ok(x) => x,
-        Err(e) => return Err(MarkdownToHtmlErr::Nul(e)),
+        Err(e) => return Err(Error::Nul(e)),
     };
     let cstr = cstring.as_c_str();
 
@@ -85,7 +164,7 @@ pub fn markdown_to_html(
 
     match raw_to_string(res, true) {
         Ok(x) => Ok(x),
-        Err(e) => Err(MarkdownToHtmlErr::Utf8(e)),
+        Err(e) => Err(Error::Utf8(e)),
     }
 }
 
@@ -103,6 +182,137 @@ pub enum Node {
     Inline(Inline),
 }
 
+impl Node {
+    /// Returns `true` if this node can never have children. Leaf nodes are
+    /// only ever entered, and never exited.
+    pub fn is_leaf(&self) -> bool {
+        match self {
+            &Node::Block(Block::CodeBlock(..)) |
+            &Node::Block(Block::HtmlBlock(..)) |
+            &Node::Block(Block::ThematicBreak) |
+            &Node::Inline(Inline::Text(..)) |
+            &Node::Inline(Inline::SoftBreak) |
+            &Node::Inline(Inline::LineBreak) |
+            &Node::Inline(Inline::Code(..)) |
+            &Node::Inline(Inline::HtmlInline(..)) => true,
+
+            _ => false,
+        }
+    }
+
+    /// Returns `true` if `child` may be a child of this node. Follows the
+    /// rules cmark itself enforces when nodes are moved around.
+    pub fn can_contain(&self, child: &Node) -> bool {
+        match (self, child) {
+            (_, &Node::Block(Block::Document)) => false,
+
+            (&Node::Block(Block::Document), &Node::Block(ref block)) |
+            (&Node::Block(Block::Blockquote), &Node::Block(ref block)) |
+            (&Node::Block(Block::Item), &Node::Block(ref block)) =>
+                *block != Block::Item,
+
+            (&Node::Block(Block::List(..)), &Node::Block(Block::Item)) => true,
+
+            (&Node::Block(Block::CustomBlock), _) => true,
+
+            (&Node::Block(Block::Paragraph), &Node::Inline(_)) |
+            (&Node::Block(Block::Heading(_)), &Node::Inline(_)) |
+            (&Node::Inline(Inline::Emph), &Node::Inline(_)) |
+            (&Node::Inline(Inline::Strong), &Node::Inline(_)) |
+            (&Node::Inline(Inline::Link(..)), &Node::Inline(_)) |
+            (&Node::Inline(Inline::Image(..)), &Node::Inline(_)) |
+            (&Node::Inline(Inline::CustomInline), &Node::Inline(_)) => true,
+
+            _ => false,
+        }
+    }
+
+    /// Returns the literal of a code block, HTML block, text, code or inline
+    /// HTML node, and `None` for everything else.
+    pub fn literal(&self) -> Option<&Literal> {
+        match self {
+            &Node::Block(Block::CodeBlock(_, ref lit)) |
+            &Node::Block(Block::HtmlBlock(ref lit)) |
+            &Node::Inline(Inline::Text(ref lit)) |
+            &Node::Inline(Inline::Code(ref lit)) |
+            &Node::Inline(Inline::HtmlInline(ref lit)) => Some(lit),
+
+            _ => None,
+        }
+    }
+
+    /// Counterpart of `cmark_node_set_literal`. Returns `false` and leaves
+    /// the node alone if it has no literal.
+    pub fn set_literal(&mut self, content: &str) -> bool {
+        match self {
+            &mut Node::Block(Block::CodeBlock(_, ref mut lit)) |
+            &mut Node::Block(Block::HtmlBlock(ref mut lit)) |
+            &mut Node::Inline(Inline::Text(ref mut lit)) |
+            &mut Node::Inline(Inline::Code(ref mut lit)) |
+            &mut Node::Inline(Inline::HtmlInline(ref mut lit)) => {
+                *lit = Literal::from(content.to_owned());
+                true
+            },
+
+            _ => false,
+        }
+    }
+
+    /// Counterpart of `cmark_node_set_url`. Returns `false` and leaves the
+    /// node alone if it is neither a link nor an image.
+    pub fn set_url(&mut self, content: &str) -> bool {
+        match self {
+            &mut Node::Inline(Inline::Link(ref mut url, _)) |
+            &mut Node::Inline(Inline::Image(ref mut url, _)) => {
+                *url = Url::from(content.to_owned());
+                true
+            },
+
+            _ => false,
+        }
+    }
+
+    /// Counterpart of `cmark_node_set_title`. Returns `false` and leaves the
+    /// node alone if it is neither a link nor an image.
+    pub fn set_title(&mut self, content: &str) -> bool {
+        match self {
+            &mut Node::Inline(Inline::Link(_, ref mut title)) |
+            &mut Node::Inline(Inline::Image(_, ref mut title)) => {
+                *title = Title::from(content.to_owned());
+                true
+            },
+
+            _ => false,
+        }
+    }
+
+    /// Counterpart of `cmark_node_set_fence_info`. Returns `false` and
+    /// leaves the node alone if it is not a code block.
+    pub fn set_fence_info(&mut self, content: &str) -> bool {
+        match self {
+            &mut Node::Block(Block::CodeBlock(ref mut info, _)) => {
+                *info = InfoString::from(content.to_owned());
+                true
+            },
+
+            _ => false,
+        }
+    }
+
+    /// Counterpart of `cmark_node_set_heading_level`. Returns `false` and
+    /// leaves the node alone if it is not a heading.
+    pub fn set_heading_level(&mut self, level: HeadingLevel) -> bool {
+        match self {
+            &mut Node::Block(Block::Heading(ref mut lvl)) => {
+                *lvl = level;
+                true
+            },
+
+            _ => false,
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Block {
     Document,
@@ -168,6 +378,33 @@ pub enum HeadingLevel {
     One, Two, Three, Four, Five, Six,
 }
 
+impl HeadingLevel {
+    /// Returns the level as a number from 1 to 6.
+    pub fn as_usize(&self) -> usize {
+        match self {
+            &HeadingLevel::One => 1,
+            &HeadingLevel::Two => 2,
+            &HeadingLevel::Three => 3,
+            &HeadingLevel::Four => 4,
+            &HeadingLevel::Five => 5,
+            &HeadingLevel::Six => 6,
+        }
+    }
+
+    /// Returns the level numbered `n`, if `n` is from 1 to 6.
+    pub fn from_usize(n: usize) -> Option<Self> {
+        match n {
+            1 => Some(HeadingLevel::One),
+            2 => Some(HeadingLevel::Two),
+            3 => Some(HeadingLevel::Three),
+            4 => Some(HeadingLevel::Four),
+            5 => Some(HeadingLevel::Five),
+            6 => Some(HeadingLevel::Six),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Inline {
     Text(Literal), // leaf!
@@ -209,12 +446,195 @@ pub enum DelimType {
     Paren,
 }
 
+/*
+ *  Source positions.
+ */
+
+/// Where a node sits in the source markdown, as reported by cmark. Lines and
+/// columns are 1-based; a span of all zeroes means the position is unknown,
+/// e.g. for nodes created by hand rather than by the parser.
+///
+/// Positions of inline nodes are only as precise as cmark makes them, which
+/// is usually good enough to find the right spot.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct SourceSpan {
+    pub start_line: usize,
+    pub start_column: usize,
+    pub end_line: usize,
+    pub end_column: usize,
+}
+
+impl SourceSpan {
+    pub fn is_unknown(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Displays the start of the span as `line:column`, which can be prefixed with
+/// a file name to make the usual `post.md:42:7`.
+impl fmt::Display for SourceSpan {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.start_line, self.start_column)
+    }
+}
+
+/// Reads the source position of a raw `cmark_node`.
+fn span_from_raw(raw_node: *mut bind::cmark_node) -> SourceSpan {
+    unsafe {
+        SourceSpan {
+            start_line: bind::cmark_node_get_start_line(raw_node) as usize,
+            start_column: bind::cmark_node_get_start_column(raw_node) as usize,
+            end_line: bind::cmark_node_get_end_line(raw_node) as usize,
+            end_column: bind::cmark_node_get_end_column(raw_node) as usize,
+        }
+    }
+}
+
+/*
+ *  Conversion from raw nodes, shared by `Iter` and `Document`.
+ */
+
+/// Converts a raw `cmark_node` into its `Node` counterpart. Children are not
+/// examined.
+fn node_from_raw(raw_node: *mut bind::cmark_node) -> Result<Node, Error> {
+    use bind::cmark_node_type::*;
+
+    let invalid = |what: &'static str| {
+        Err(Error::InvalidNode(span_from_raw(raw_node), what))
+    };
+
+    let literal = || -> Result<Literal, Error> {
+        Ok(Literal::from(raw_to_string(unsafe {
+            bind::cmark_node_get_literal(raw_node)
+        }, false)?))
+    };
+
+    let url = || -> Result<Url, Error> {
+        Ok(Url::from(raw_to_string(unsafe {
+            bind::cmark_node_get_url(raw_node)
+        }, false)?))
+    };
+
+    let title = || -> Result<Title, Error> {
+        Ok(Title::from(raw_to_string(unsafe {
+            bind::cmark_node_get_title(raw_node)
+        }, false)?))
+    };
+
+    Ok(match unsafe {
+        bind::cmark_node_get_type(raw_node)
+    } {
+        CMARK_NODE_NONE =>
+            return invalid("node has no type"),
+
+        CMARK_NODE_DOCUMENT =>
+            Node::Block(Block::Document),
+        CMARK_NODE_BLOCK_QUOTE =>
+            Node::Block(Block::Blockquote),
+
+        CMARK_NODE_LIST =>
+            Node::Block(Block::List(
+                match unsafe {
+                    bind::cmark_node_get_list_type(raw_node)
+                } {
+                    bind::cmark_list_type::CMARK_NO_LIST =>
+                        return invalid("list has no list type"),
+                    bind::cmark_list_type::CMARK_BULLET_LIST =>
+                        ListType::Bullet,
+                    bind::cmark_list_type::CMARK_ORDERED_LIST =>
+                        ListType::Ordered,
+                },
+
+                match unsafe {
+                    bind::cmark_node_get_list_delim(raw_node)
+                } {
+                    bind::cmark_delim_type::CMARK_NO_DELIM =>
+                        DelimType::No,
+                    bind::cmark_delim_type::CMARK_PERIOD_DELIM =>
+                        DelimType::Period,
+                    bind::cmark_delim_type::CMARK_PAREN_DELIM =>
+                        DelimType::Paren,
+                },
+
+                StartingNumber(unsafe {
+                    bind::cmark_node_get_list_start(raw_node)
+                } as isize),
+
+                Tightness(match unsafe {
+                    bind::cmark_node_get_list_tight(raw_node)
+                } {
+                    0 => false,
+                    1 => true,
+                    _ => return invalid("list tightness is neither 0 nor 1"),
+                })
+            )),
+
+        CMARK_NODE_ITEM =>
+            Node::Block(Block::Item),
+
+        CMARK_NODE_CODE_BLOCK =>
+            Node::Block(Block::CodeBlock(
+                InfoString::from(raw_to_string(unsafe {
+                    bind::cmark_node_get_fence_info(raw_node)
+                }, false)?),
+
+                literal()?,
+            )),
+
+        CMARK_NODE_HTML_BLOCK =>
+            Node::Block(Block::HtmlBlock(literal()?)),
+
+        CMARK_NODE_CUSTOM_BLOCK =>
+            Node::Block(Block::CustomBlock),
+        CMARK_NODE_PARAGRAPH =>
+            Node::Block(Block::Paragraph),
+
+        CMARK_NODE_HEADING =>
+            Node::Block(Block::Heading(match HeadingLevel::from_usize(unsafe {
+                bind::cmark_node_get_heading_level(raw_node)
+            } as usize) {
+                Some(lvl) => lvl,
+                None => return invalid("heading level is not from 1 to 6"),
+            })),
+
+        CMARK_NODE_THEMATIC_BREAK =>
+            Node::Block(Block::ThematicBreak),
+
+        CMARK_NODE_TEXT =>
+            Node::Inline(Inline::Text(literal()?)),
+
+        CMARK_NODE_SOFTBREAK =>
+            Node::Inline(Inline::SoftBreak),
+        CMARK_NODE_LINEBREAK =>
+            Node::Inline(Inline::LineBreak),
+
+        CMARK_NODE_CODE =>
+            Node::Inline(Inline::Code(literal()?)),
+
+        CMARK_NODE_HTML_INLINE =>
+            Node::Inline(Inline::HtmlInline(literal()?)),
+
+        CMARK_NODE_CUSTOM_INLINE =>
+            Node::Inline(Inline::CustomInline),
+        CMARK_NODE_EMPH =>
+            Node::Inline(Inline::Emph),
+        CMARK_NODE_STRONG =>
+            Node::Inline(Inline::Strong),
+
+        CMARK_NODE_LINK =>
+            Node::Inline(Inline::Link(url()?, title()?)),
+
+        CMARK_NODE_IMAGE =>
+            Node::Inline(Inline::Image(url()?, title()?)),
+    })
+}
+
 /*
  *  Iterator that gets exported.
  */
 
 /// Wrapper around `cmark_event_type`.
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Event {
     Enter,
     Exit,
@@ -242,14 +662,14 @@ impl Iter {
 
 impl Drop for Iter {
     fn drop(&mut self) {
-        //  the program stalls if we uncomment this; this probably needs a fix
-        //  unsafe { bind::cmark_node_free(self.raw_root); }
+        //  the iterator must go before the tree it walks
         unsafe { bind::cmark_iter_free(self.raw_iter); }
+        unsafe { bind::cmark_node_free(self.raw_root); }
     }
 }
 
 impl Iterator for Iter {
-    type Item = (Node, Event);
+    type Item = Result<(Node, Event), Error>;
 
     fn next(&mut self) -> Option<Self::Item> {
         if self.done {
@@ -260,7 +680,13 @@ impl Iterator for Iter {
             bind::cmark_iter_next(self.raw_iter)
         } {
             bind::cmark_event_type::CMARK_EVENT_NONE =>
-                panic!("getting CMARK_EVENT_NONE"),
+                {
+                    self.done = true;
+                    return Some(Err(Error::InvalidNode(
+                        SourceSpan::default(),
+                        "iterator reported no event"
+                    )));
+                },
             bind::cmark_event_type::CMARK_EVENT_DONE =>
                 {
                     self.done = true;
@@ -276,150 +702,12 @@ impl Iterator for Iter {
             bind::cmark_iter_get_node(self.raw_iter)
         };
 
-        let node = match unsafe {
-            bind::cmark_node_get_type(raw_node)
-        } {
-            bind::cmark_node_type::CMARK_NODE_NONE =>
-                panic!("getting CMARK_NODE_NONE"),
-
-            bind::cmark_node_type::CMARK_NODE_DOCUMENT =>
-                Node::Block(Block::Document),
-            bind::cmark_node_type::CMARK_NODE_BLOCK_QUOTE =>
-                Node::Block(Block::Blockquote),
-
-            bind::cmark_node_type::CMARK_NODE_LIST =>
-                Node::Block(Block::List(
-                    match unsafe {
-                        bind::cmark_node_get_list_type(raw_node)
-                    } {
-                        bind::cmark_list_type::CMARK_NO_LIST =>
-                            panic!("no list no list type"),
-                        bind::cmark_list_type::CMARK_BULLET_LIST =>
-                            ListType::Bullet,
-                        bind::cmark_list_type::CMARK_ORDERED_LIST =>
-                            ListType::Ordered,
-                    },
-
-                    match unsafe {
-                        bind::cmark_node_get_list_delim(raw_node)
-                    } {
-                        bind::cmark_delim_type::CMARK_NO_DELIM =>
-                            DelimType::No,
-                        bind::cmark_delim_type::CMARK_PERIOD_DELIM =>
-                            DelimType::Period,
-                        bind::cmark_delim_type::CMARK_PAREN_DELIM =>
-                            DelimType::Paren,
-                    },
-
-                    StartingNumber(unsafe {
-                        bind::cmark_node_get_list_start(raw_node)
-                    } as isize),
-
-                    Tightness(match unsafe {
-                        bind::cmark_node_get_list_tight(raw_node)
-                    } {
-                        0 => false,
-                        1 => true,
-                        _ => panic!("not 0 or 1"),
-                    })
-                )),
-
-            bind::cmark_node_type::CMARK_NODE_ITEM =>
-                Node::Block(Block::Item),
-
-            bind::cmark_node_type::CMARK_NODE_CODE_BLOCK =>
-                Node::Block(Block::CodeBlock(
-                    InfoString::from(raw_to_string(unsafe {
-                        bind::cmark_node_get_fence_info(raw_node)
-                    }, false).expect("bad info string")),
-
-                    Literal::from(raw_to_string(unsafe {
-                        bind::cmark_node_get_literal(raw_node)
-                    }, false).expect("bad literal")),
-                )),
-
-            bind::cmark_node_type::CMARK_NODE_HTML_BLOCK =>
-                Node::Block(Block::HtmlBlock(
-                    Literal::from(raw_to_string(unsafe {
-                        bind::cmark_node_get_literal(raw_node)
-                    }, false).expect("bad literal"))
-                )),
-
-            bind::cmark_node_type::CMARK_NODE_CUSTOM_BLOCK =>
-                Node::Block(Block::CustomBlock),
-            bind::cmark_node_type::CMARK_NODE_PARAGRAPH =>
-                Node::Block(Block::Paragraph),
-
-            bind::cmark_node_type::CMARK_NODE_HEADING =>
-                Node::Block(Block::Heading(match unsafe {
-                    bind::cmark_node_get_heading_level(raw_node)
-                } {
-                    1 => HeadingLevel::One,
-                    2 => HeadingLevel::Two,
-                    3 => HeadingLevel::Three,
-                    4 => HeadingLevel::Four,
-                    5 => HeadingLevel::Five,
-                    6 => HeadingLevel::Six,
-                    _ => panic!("not a valid heading level"),
-                })),
-
-            bind::cmark_node_type::CMARK_NODE_THEMATIC_BREAK =>
-                Node::Block(Block::ThematicBreak),
-
-            bind::cmark_node_type::CMARK_NODE_TEXT =>
-                Node::Inline(Inline::Text(
-                    Literal::from(raw_to_string(unsafe {
-                        bind::cmark_node_get_literal(raw_node)
-                    }, false).expect("bad literal"))
-                )),
-
-            bind::cmark_node_type::CMARK_NODE_SOFTBREAK =>
-                Node::Inline(Inline::SoftBreak),
-            bind::cmark_node_type::CMARK_NODE_LINEBREAK =>
-                Node::Inline(Inline::LineBreak),
-
-            bind::cmark_node_type::CMARK_NODE_CODE =>
-                Node::Inline(Inline::Code(
-                    Literal::from(raw_to_string(unsafe {
-                        bind::cmark_node_get_literal(raw_node)
-                    }, false).expect("bad literal"))
-                )),
-
-            bind::cmark_node_type::CMARK_NODE_HTML_INLINE =>
-                Node::Inline(Inline::HtmlInline(
-                    Literal::from(raw_to_string(unsafe {
-                        bind::cmark_node_get_literal(raw_node)
-                    }, false).expect("bad literal"))
-                )),
-
-            bind::cmark_node_type::CMARK_NODE_CUSTOM_INLINE =>
-                Node::Inline(Inline::CustomInline),
-            bind::cmark_node_type::CMARK_NODE_EMPH =>
-                Node::Inline(Inline::Emph),
-            bind::cmark_node_type::CMARK_NODE_STRONG =>
-                Node::Inline(Inline::Strong),
-
-            bind::cmark_node_type::CMARK_NODE_LINK =>
-                Node::Inline(Inline::Link(
-                    Url::from(raw_to_string(unsafe {
-                        bind::cmark_node_get_url(raw_node)
-                    }, false).expect("bad url")),
-
-                    Title::from(raw_to_string(unsafe {
-                        bind::cmark_node_get_title(raw_node)
-                    }, false).expect("bad title"))
-                )),
-
-            bind::cmark_node_type::CMARK_NODE_IMAGE =>
-                Node::Inline(Inline::Image(
-                    Url::from(raw_to_string(unsafe {
-                        bind::cmark_node_get_url(raw_node)
-                    }, false).expect("bad url")),
-
-                    Title::from(raw_to_string(unsafe {
-                        bind::cmark_node_get_title(raw_node)
-                    }, false).expect("bad title"))
-                )),
+        let node = match node_from_raw(raw_node) {
+            Ok(res) => res,
+            Err(e) => {
+                self.done = true;
+                return Some(Err(e));
+            },
         };
 
         //  we are done if we get an Exit to the Document
@@ -427,7 +715,7 @@ impl Iterator for Iter {
             self.done = true;
         }
 
-        Some((node, event))
+        Some(Ok((node, event)))
     }
 }
 
@@ -435,19 +723,13 @@ impl Iterator for Iter {
  *  Parser that gets exported.
  */
 
-/// Errors that can occur when calling any of the parsing functions.
-#[derive(Debug)]
-pub enum ParserErr {
-    Nul(ffi::NulError),
-}
-
 /*  doesn't work now
 /// Wrapper around `cmark_parse_document`.
 fn parse_document(
     buffer: &str,
     len: usize,
     options: Options
-) -> Result<Node, ParserErr> {
+) -> Result<Node, Error> {
     match ffi::CString::new(buffer) {
         Ok(cstring) => Ok(Node::from_raw_undropped(
             unsafe { bind::cmark_parse_document(
@@ -457,10 +739,13 @@ fn parse_document(
             ) }
         )),
 
-        Err(x) => Err(ParserErr::Nul(x)),
+        Err(x) => Err(Error::Nul(x)),
     }
 }*/
 
+/// Size of the chunks read by `Parser::feed_reader`.
+const FEED_CHUNK_SIZE: usize = 8 * 1024;
+
 /// Wrapper around `cmark_parser`.
 pub struct Parser {
     raw: *mut bind::cmark_parser,
@@ -473,14 +758,38 @@ impl Parser {
         }
     }
 
-    pub fn feed(&mut self, buffer: &str, len: usize) -> Result<(), ParserErr> {
-        match ffi::CString::new(buffer) {
-            Ok(cstring) => unsafe {
-                bind::cmark_parser_feed(self.raw, cstring.as_ptr(), len);
-                Ok(())
-            },
+    /// Feeds a chunk of markdown to the parser. Chunks may be split anywhere,
+    /// even in the middle of a line or of a UTF-8 sequence; cmark keeps the
+    /// unfinished line around until the next chunk comes in. NUL bytes are
+    /// replaced by cmark with U+FFFD.
+    pub fn feed(&mut self, buffer: &[u8]) {
+        //  cmark looks at the first byte when the last chunk ended in '\r',
+        //  and an empty slice has none
+        if buffer.is_empty() {
+            return;
+        }
 
-            Err(x) => Err(ParserErr::Nul(x)),
+        unsafe {
+            bind::cmark_parser_feed(
+                self.raw,
+                buffer.as_ptr() as *const raw::c_char,
+                buffer.len()
+            );
+        }
+    }
+
+    /// Feeds everything `reader` has to offer to the parser, one chunk at a
+    /// time, so that the markdown itself is never held in memory as a whole.
+    pub fn feed_reader<R: Read>(&mut self, reader: &mut R) -> Result<(), Error> {
+        let mut buffer = [0u8; FEED_CHUNK_SIZE];
+
+        loop {
+            match reader.read(&mut buffer) {
+                Ok(0) => return Ok(()),
+                Ok(n) => self.feed(&buffer[..n]),
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(Error::Io(e)),
+            };
         }
     }
 
@@ -500,25 +809,145 @@ impl Drop for Parser {
  *  Rendering functions that get exported.
  */
 
-/// Errors that can occur when calling any of the rendering functions.
-#[derive(Debug)]
-enum RenderErr {
-    Utf8(str::Utf8Error),
+/// Output formats supported by cmark's own renderers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Format {
+    Html,
+    Latex,
+    Man,
+    Xml,
+    CommonMark,
 }
 
-fn render_html(
-    raw_root: *mut bind::cmark_node,
+impl Format {
+    /// Returns the format named `name`, as spelled on the command line.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "html" => Some(Format::Html),
+            "latex" => Some(Format::Latex),
+            "man" => Some(Format::Man),
+            "xml" => Some(Format::Xml),
+            "commonmark" => Some(Format::CommonMark),
+            _ => None,
+        }
+    }
+
+    /// Extension of files written in this format.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            &Format::Html => "html",
+            &Format::Latex => "tex",
+            &Format::Man => "man",
+            &Format::Xml => "xml",
+            &Format::CommonMark => "md",
+        }
+    }
+}
+
+/// Renders `document` with the cmark renderer for `format`. `width` is the
+/// column at which lines are wrapped, with `0` meaning no wrapping; it is
+/// ignored by the HTML and XML renderers, which never wrap.
+pub fn render(
+    document: &Document,
+    format: Format,
+    options: Options,
+    width: usize
+) -> Result<String, Error> {
+    match format {
+        Format::Html => render_html(document, options),
+        Format::Latex => render_latex(document, options, width),
+        Format::Man => render_man(document, options, width),
+        Format::Xml => render_xml(document, options),
+        Format::CommonMark => render_commonmark(document, options, width),
+    }
+}
+
+/// Wrapper around `cmark_render_html`.
+pub fn render_html(
+    document: &Document,
+    options: Options
+) -> Result<String, Error> {
+    let raw = document.to_raw()?;
+    let res = unsafe {
+        bind::cmark_render_html(raw.as_ptr(), options.as_c_int())
+    };
+
+    match raw_to_string(res, true) {
+        Ok(x) => Ok(x),
+        Err(e) => Err(Error::Utf8(e)),
+    }
+}
+
+/// Wrapper around `cmark_render_xml`.
+pub fn render_xml(
+    document: &Document,
     options: Options
-) -> Result<String, RenderErr> {
+) -> Result<String, Error> {
+    let raw = document.to_raw()?;
+    let res = unsafe {
+        bind::cmark_render_xml(raw.as_ptr(), options.as_c_int())
+    };
+
+    match raw_to_string(res, true) {
+        Ok(x) => Ok(x),
+        Err(e) => Err(Error::Utf8(e)),
+    }
+}
+
+/// Wrapper around `cmark_render_man`.
+pub fn render_man(
+    document: &Document,
+    options: Options,
+    width: usize
+) -> Result<String, Error> {
+    let raw = document.to_raw()?;
     let res = unsafe {
-        bind::cmark_render_html(
-            raw_root, options.bits as raw::c_int
+        bind::cmark_render_man(
+            raw.as_ptr(), options.as_c_int(), width as raw::c_int
         )
     };
 
     match raw_to_string(res, true) {
         Ok(x) => Ok(x),
-        Err(e) => Err(RenderErr::Utf8(e)),
+        Err(e) => Err(Error::Utf8(e)),
+    }
+}
+
+/// Wrapper around `cmark_render_commonmark`.
+pub fn render_commonmark(
+    document: &Document,
+    options: Options,
+    width: usize
+) -> Result<String, Error> {
+    let raw = document.to_raw()?;
+    let res = unsafe {
+        bind::cmark_render_commonmark(
+            raw.as_ptr(), options.as_c_int(), width as raw::c_int
+        )
+    };
+
+    match raw_to_string(res, true) {
+        Ok(x) => Ok(x),
+        Err(e) => Err(Error::Utf8(e)),
+    }
+}
+
+/// Wrapper around `cmark_render_latex`.
+pub fn render_latex(
+    document: &Document,
+    options: Options,
+    width: usize
+) -> Result<String, Error> {
+    let raw = document.to_raw()?;
+    let res = unsafe {
+        bind::cmark_render_latex(
+            raw.as_ptr(), options.as_c_int(), width as raw::c_int
+        )
+    };
+
+    match raw_to_string(res, true) {
+        Ok(x) => Ok(x),
+        Err(e) => Err(Error::Utf8(e)),
     }
 }
 
@@ -555,7 +984,104 @@ bitflags! {
 
 /// Less typing
 impl Options {
+    /// Names of the options, as accepted by `Options::from_name`.
+    pub const NAMES: &'static [&'static str] = &[
+        "source-pos",
+        "hard-breaks",
+        "safe",
+        "no-breaks",
+        "normalize",
+        "validate-utf8",
+        "smart",
+    ];
+
+    /// Looks up a single option by its name in `Options::NAMES`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "source-pos" => Some(Options::SOURCE_POS),
+            "hard-breaks" => Some(Options::HARD_BREAKS),
+            "safe" => Some(Options::SAFE),
+            "no-breaks" => Some(Options::NO_BREAKS),
+            "normalize" => Some(Options::NORMALIZE),
+            "validate-utf8" => Some(Options::VALIDATE_UTF8),
+            "smart" => Some(Options::SMART),
+            _ => None,
+        }
+    }
+
     fn as_c_int(&self) -> raw::c_int {
         self.bits as raw::c_int
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parser(markdown: &str) -> Parser {
+        let mut res = Parser::new(Options::DEFAULT);
+        res.feed(markdown.as_bytes());
+        res
+    }
+
+    #[test]
+    fn walk_matches_iter() {
+        let markdown = "# A *b*\n\n> - c\n>   - `d`\n>\n>     e\n\n```\nf\n```\n";
+
+        let expected: Vec<(Node, Event)> = Iter::from_parser(parser(markdown))
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        let document = Document::from_parser(parser(markdown)).unwrap();
+        let walked: Vec<(Node, Event)> = document.walk()
+            .map(|(tree, event)| (tree.node.clone(), event))
+            .collect();
+
+        assert_eq!(walked, expected);
+
+        //  dropping an unfinished iterator frees the tree it walks
+        let mut iter = Iter::from_parser(parser(markdown));
+        assert_eq!(iter.next().unwrap().unwrap(), (Node::Block(Block::Document), Event::Enter));
+    }
+
+    #[test]
+    fn empty_chunk() {
+        let mut parser = Parser::new(Options::DEFAULT);
+        parser.feed(b"a\r");
+        parser.feed(b"");
+        parser.feed(b"\nb\n");
+
+        let document = Document::from_parser(parser).unwrap();
+        assert_eq!(document.root().text(), "a b");
+    }
+
+    #[test]
+    fn setters() {
+        let mut link = Node::Inline(Inline::Link(Url::from("/a".to_owned()), Title::from(String::new())));
+        assert!(link.set_url("/b"));
+        assert!(link.set_title("B"));
+        assert_eq!(link, Node::Inline(Inline::Link(Url::from("/b".to_owned()), Title::from("B".to_owned()))));
+        assert_eq!(link.literal(), None);
+        assert!(!link.set_literal("x"));
+        assert!(!link.set_fence_info("rust"));
+
+        let mut code = Node::Block(Block::CodeBlock(InfoString::from(String::new()), Literal::from("a\n".to_owned())));
+        assert!(code.set_fence_info("rust"));
+        assert!(code.set_literal("b\n"));
+        assert_eq!(code.literal().map(|lit| lit.as_str()), Some("b\n"));
+        assert_eq!(code, Node::Block(Block::CodeBlock(InfoString::from("rust".to_owned()), Literal::from("b\n".to_owned()))));
+        assert!(!code.set_title("x"));
+    }
+
+    #[test]
+    fn node_without_type() {
+        let raw = unsafe { bind::cmark_node_new(bind::cmark_node_type::CMARK_NODE_NONE) };
+        let res = node_from_raw(raw);
+        unsafe { bind::cmark_node_free(raw); }
+
+        match res {
+            Err(Error::InvalidNode(_, what)) => assert_eq!(what, "node has no type"),
+            res => panic!("expected an invalid node, got {:?}", res),
+        };
+    }
+}
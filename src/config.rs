@@ -0,0 +1,231 @@
+/*
+ *  Site configuration
+ *
+ *  `writer2.toml` in the input directory holds the settings of a site, so that
+ *  they need not be repeated on every build:
+ *
+ *      title = "Notes"
+ *      base_url = "https://example.org/notes/"
+ *      author = "Someone"
+ *      output_dir = "../site"
+ *      theme = "default"
+ *      markdown = ["smart"]
+ *      transforms = ["block-attributes"]
+ *
+ *      [dirs.drafts]
+ *      draft = true
+ *
+ *      [dirs."courses/phonology"]
+ *      layout = "course"
+ *      markdown = ["hard-breaks"]
+ *
+ *  Each table under `dirs` holds front matter defaults for the posts under
+ *  that directory, relative to the input directory. The deepest directory
+ *  wins, and the post's own front matter wins over all of them. Flags given
+ *  on the command line win over the file.
+ */
+
+use front_matter::{ FrontMatter, FrontMatterErr, Value, take_list, take_options, take_string };
+
+use cmark::Options;
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{ Path, PathBuf };
+
+/// Name of the configuration file, looked for in the input directory.
+pub const CONFIG_FILE: &str = "writer2.toml";
+
+/// Errors while reading the configuration file.
+#[derive(Debug)]
+pub enum ConfigErr {
+    /// The file exists but cannot be read.
+    Io(PathBuf, io::Error),
+
+    /// The file is not valid TOML, or a setting has the wrong type.
+    Invalid(PathBuf, FrontMatterErr),
+}
+
+impl fmt::Display for ConfigErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &ConfigErr::Io(ref path, ref e) => write!(f, "cannot read {}: {}", path.display(), e),
+            &ConfigErr::Invalid(ref path, ref e) => write!(f, "{}: {}", path.display(), e),
+        }
+    }
+}
+
+/// Settings read from `writer2.toml`. Everything is optional.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    pub title: Option<String>,
+    pub base_url: Option<String>,
+
+    /// Also the default author of every post.
+    pub author: Option<String>,
+
+    /// Relative to the input directory.
+    pub output_dir: Option<PathBuf>,
+
+    pub theme: Option<String>,
+    pub simple: Option<bool>,
+    pub custom_indent: Option<String>,
+    pub drafts: Option<bool>,
+    pub transforms: Vec<String>,
+
+    /// cmark options for every post, by their names in `Options::NAMES`.
+    pub options: Options,
+
+    /// Front matter defaults by directory, shallowest first.
+    pub dirs: Vec<(PathBuf, BTreeMap<String, Value>)>,
+
+    /// Every other setting, for the templates.
+    pub custom: BTreeMap<String, Value>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            title: None,
+            base_url: None,
+            author: None,
+            output_dir: None,
+            theme: None,
+            simple: None,
+            custom_indent: None,
+            drafts: None,
+            transforms: Vec::new(),
+            options: Options::DEFAULT,
+            dirs: Vec::new(),
+            custom: BTreeMap::new(),
+        }
+    }
+}
+
+impl Config {
+
+    /// Reads `CONFIG_FILE` from `site_dir`. A site without one gets the
+    /// default, empty configuration.
+    pub fn load(site_dir: &Path) -> Result<Self, ConfigErr> {
+        let path = site_dir.join(CONFIG_FILE);
+        if !path.is_file() {
+            return Ok(Self::default());
+        }
+
+        let text = fs::read_to_string(&path)
+            .map_err(|e| ConfigErr::Io(path.clone(), e))?;
+
+        Self::parse(&text).map_err(|e| ConfigErr::Invalid(path, e))
+    }
+
+    fn parse(text: &str) -> Result<Self, FrontMatterErr> {
+        let mut map = FrontMatter::parse_toml(text)?;
+
+        let take_bool = |map: &mut BTreeMap<String, Value>, field: &'static str| match map.remove(field) {
+            Some(Value::Bool(b)) => Ok(Some(b)),
+            Some(_) => Err(FrontMatterErr::BadField(field)),
+            None => Ok(None),
+        };
+
+        let mut dirs: Vec<(PathBuf, BTreeMap<String, Value>)> = Vec::new();
+        match map.remove("dirs") {
+            Some(Value::Map(tables)) => for (dir, table) in tables {
+                match table {
+                    Value::Map(defaults) => dirs.push((PathBuf::from(dir), defaults)),
+                    _ => return Err(FrontMatterErr::BadField("dirs")),
+                };
+            },
+
+            Some(_) => return Err(FrontMatterErr::BadField("dirs")),
+            None => (),
+        };
+        dirs.sort_by_key(|&(ref dir, _)| dir.components().count());
+
+        Ok(Self {
+            title: take_string(&mut map, "title")?,
+            base_url: take_string(&mut map, "base_url")?,
+            author: take_string(&mut map, "author")?,
+            output_dir: take_string(&mut map, "output_dir")?.map(PathBuf::from),
+            theme: take_string(&mut map, "theme")?,
+            simple: take_bool(&mut map, "simple")?,
+            custom_indent: take_string(&mut map, "custom_indent")?,
+            drafts: take_bool(&mut map, "drafts")?,
+            transforms: take_list(&mut map, "transforms")?,
+            options: take_options(&mut map, "markdown")?,
+            dirs: dirs,
+            custom: map,
+        })
+    }
+
+    /// The `site` variable of the templates: the title, base URL and author
+    /// along with every setting we do not know about.
+    pub fn site(&self) -> BTreeMap<String, Value> {
+        let mut res = self.custom.clone();
+
+        let strings = [
+            ("title", &self.title),
+            ("base_url", &self.base_url),
+            ("author", &self.author),
+        ];
+
+        for &(name, value) in strings.iter() {
+            if let &Some(ref value) = value {
+                res.insert(name.to_owned(), Value::String(value.clone()));
+            }
+        }
+
+        res
+    }
+
+    /// Front matter defaults for the post at `path`, relative to the input
+    /// directory.
+    pub fn defaults_for(&self, path: &Path) -> BTreeMap<String, Value> {
+        let mut res = BTreeMap::new();
+
+        if let Some(ref author) = self.author {
+            res.insert("author".to_owned(), Value::String(author.clone()));
+        }
+
+        for &(ref dir, ref defaults) in &self.dirs {
+            if path.starts_with(dir) {
+                res.extend(defaults.iter().map(|(k, v)| (k.clone(), v.clone())));
+            }
+        }
+
+        res
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn directory_defaults() {
+        let config = Config::parse("
+            author = \"A\"
+            markdown = [\"smart\"]
+
+            [dirs.notes]
+            layout = \"note\"
+            author = \"B\"
+
+            [dirs.\"notes/old\"]
+            draft = true
+        ").unwrap();
+
+        assert_eq!(config.options, Options::SMART);
+
+        let defaults = config.defaults_for(Path::new("notes/old/a.md"));
+        assert_eq!(defaults.get("author"), Some(&Value::String("B".to_owned())));
+        assert_eq!(defaults.get("layout"), Some(&Value::String("note".to_owned())));
+        assert_eq!(defaults.get("draft"), Some(&Value::Bool(true)));
+
+        let defaults = config.defaults_for(Path::new("a.md"));
+        assert_eq!(defaults.get("author"), Some(&Value::String("A".to_owned())));
+        assert_eq!(defaults.get("layout"), None);
+    }
+}
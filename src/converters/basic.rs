@@ -1,58 +1,53 @@
-use converters::Converter;
+use converters::{ Converter, parse };
+use converters::html::HtmlRenderer;
+use converters::special::SpecialVisitor;
+use converters::special::spans::{ Protect, SpecialSpans };
+use converters::template::{ Context, Templates };
+use converters::visitor::Visitor;
 
 use cmark::*;
 use asset::*;
+use front_matter::{ FrontMatter, Value };
+use transforms::{ Pipeline, Transform };
 
-use std::io;
-use std::io::{ Read, Write };
-
-use std::iter;
+use std::io::{ BufReader, Read, Write };
 
 use std::marker::PhantomData;
+use std::path::Path;
 
-lazy_static! {
-    static ref INDENT: String = String::from("    "); 
-
-    static ref HEADER_PRE_ASSETS: String = format!(
-        "<!DOCTYPE html>\n\
-<html>\n\
-{0}<head>\n\
-{0}{0}<meta charset=\"UTF-8\">\n\
-{0}{0}<title>Title</title>\n",
-        &*INDENT
-    );
-
-    static ref HEADER_POST_ASSETS: String = format!(
-        "{0}</head>\n\
-\n\
-{0}<body>\n\
-{0}{0}<div class=\"container u-full-width\">\n",
-        &*INDENT
-    );
-
-    static ref FOOTER: String = format!(
-        "{0}{0}</div>\n\
-{0}</body>\n\
-{0}<script>hljs.initHighlightingOnLoad();</script>\n\
-</html>",
-        &*INDENT
-    );
-}
+/// Longest description taken from the first paragraph, in characters.
+const DESCRIPTION_LEN: usize = 160;
 
 pub struct BasicConverter<'a> {
-    indent: usize,
-    tightness: bool,
+    html: HtmlRenderer,
+    visitor: Box<dyn Visitor>,
+
+    /// Site-wide variables, available to templates as `site`.
+    site: Context,
+
     phantom: PhantomData<&'a ()>,
 }
 
 pub struct BasicData<'a> {
+    source: &'a Path,
+    front_matter: &'a FrontMatter,
+    templates: &'a Templates,
     assets: &'a Vec<Asset>,
     dist: usize,
 }
 
 impl<'a> BasicData<'a> {
-    pub fn new(assets: &'a Vec<Asset>, dist: usize) -> Self {
+    pub fn new(
+        source: &'a Path,
+        front_matter: &'a FrontMatter,
+        templates: &'a Templates,
+        assets: &'a Vec<Asset>,
+        dist: usize
+    ) -> Self {
         Self {
+            source: source,
+            front_matter: front_matter,
+            templates: templates,
             assets: assets,
             dist: dist,
         }
@@ -60,366 +55,96 @@ impl<'a> BasicData<'a> {
 }
 
 /*
- *  Converting methods
- *
- *  A method needs &Event as a parameter iff the node is not a leaf node.
- *  Leaf nodes are HtmlBlock, ThematicBreak, CodeBlock, Text, SoftBreak,
- *  LineBreak, Code and HtmlInline.
+ *  Variables for the page template
  */
 
 impl<'a> BasicConverter<'a> {
 
-    fn write_header<W: Write>(
-        &mut self,
-        writer: &mut W,
-        assets: &Vec<Asset>,
-        dist: usize
-    ) -> io::Result<()> {
-        write!(writer, "{}", &*HEADER_PRE_ASSETS)?;
-
-        self.write_assets(writer, assets, dist)?;
-
-        write!(writer, "{}", &*HEADER_POST_ASSETS)?;
+    /// Replaces the visitor deciding how each node is rendered. Defaults to
+    /// `SpecialVisitor`, which typesets the special syntax.
+    #[allow(dead_code)]
+    pub fn set_visitor(&mut self, visitor: Box<dyn Visitor>) {
+        self.visitor = visitor;
+    }
+
+    /// Replaces the four spaces indenting the blocks of the post one level.
+    pub fn set_indent_str(&mut self, indent_str: &str) {
+        self.html.set_indent_str(indent_str);
+    }
+
+    /// Sets the site-wide variables, e.g. from the site configuration.
+    pub fn set_site(&mut self, site: Context) {
+        self.site = site;
+    }
+
+    /// Collects the variables of the page: every front matter field, plus
+    ///
+    /// *   `title` and `description`, which fall back on the first level 1
+    ///     heading and the first paragraph. A page with no heading at all is
+    ///     titled after its file.
+    /// *   `keywords`, the tags separated by commas.
+    /// *   `assets`, the `<link>` and `<script>` elements for the assets.
+    /// *   `root`, the relative path from the page to the output directory.
+    /// *   `site`, the site-wide variables.
+    fn context(&self, document: &Document, data: &BasicData) -> Context {
+        let front_matter = data.front_matter;
+        let mut res = front_matter.custom.clone();
+
+        let title = front_matter.title.clone()
+            .or_else(|| first_heading(document))
+            .or_else(|| data.source.file_stem().map(|s| s.to_string_lossy().into_owned()))
+            .unwrap_or_default();
+        let description = front_matter.description.clone()
+            .or_else(|| first_paragraph(document));
+
+        let strings = [
+            ("title", Some(title)),
+            ("description", description),
+            ("author", front_matter.author.clone()),
+            ("date", front_matter.date.clone()),
+            ("layout", front_matter.layout.clone()),
+            ("keywords", Some(front_matter.tags.join(", "))),
+            ("assets", Some(Self::assets(data.assets, data.dist))),
+            ("root", Some("../".repeat(data.dist))),
+        ];
+
+        for &(name, ref value) in strings.iter() {
+            if let &Some(ref value) = value {
+                res.insert(name.to_owned(), Value::String(value.clone()));
+            }
+        }
 
-        self.indent = 2;
+        res.insert("tags".to_owned(), Value::List(
+            front_matter.tags.iter().cloned().map(Value::String).collect()
+        ));
+        res.insert("draft".to_owned(), Value::Bool(front_matter.draft));
+        res.insert("site".to_owned(), Value::Map(self.site.clone()));
 
-        Ok(())
+        res
     }
 
-    fn write_assets<W: Write>(
-        &mut self,
-        writer: &mut W,
-        assets: &Vec<Asset>,
-        dist: usize
-    ) -> io::Result<()> {
+    fn assets(assets: &Vec<Asset>, dist: usize) -> String {
+        let mut res = String::new();
+
         for asset in assets {
             match asset.asset_type() {
-                &AssetType::Css => {
-                    write!(
-                        writer,
-                        "<link rel=\"stylesheet\" href=\"{}{}\" type=\"text/css\">\n",
-                        "../".repeat(dist),
-                        asset.path().display()
-                    )
-                },
-
-                &AssetType::Js => {
-                    write!(
-                        writer,
-                        "<script src=\"{}{}\" type=\"text/javascript\"></script>\n",
-                        "../".repeat(dist),
-                        asset.path().display()
-                    )
-                },
-
-                &AssetType::Other => Ok(()),
-            }?;
+                &AssetType::Css => res.push_str(&format!(
+                    "<link rel=\"stylesheet\" href=\"{}{}\" type=\"text/css\">\n",
+                    "../".repeat(dist),
+                    asset.path().display()
+                )),
+
+                &AssetType::Js => res.push_str(&format!(
+                    "<script src=\"{}{}\" type=\"text/javascript\"></script>\n",
+                    "../".repeat(dist),
+                    asset.path().display()
+                )),
+
+                &AssetType::Other => (),
+            };
         }
 
-        Ok(())
-    }
-
-    fn write_footer<W: Write>(
-        &mut self,
-        writer: &mut W,
-    ) -> io::Result<()> {
-        write!(writer, "{}", &*FOOTER)?;
-
-        Ok(())
-    }
-
-    fn repeat_indent(n: usize) -> String {
-        iter::repeat((*INDENT).clone()).take(n).collect::<String>()
-    }
-
-    fn make_indent(&self) -> String {
-        Self::repeat_indent(self.indent)
-    }
-
-    fn convert_blockquote<W: Write>(
-        &mut self,
-        event: &Event,
-        writer: &mut W
-    ) -> io::Result<()> {
-        match event {
-            &Event::Enter => {
-                write!(writer, "{}<blockquote>\n", self.make_indent())?;
-                self.indent += 1;
-            },
-
-            &Event::Exit => {
-                self.indent -= 1;
-                write!(writer, "{}</blockquote>\n", self.make_indent())?;
-            },
-        };
-
-        Ok(())
-    }
-
-    fn convert_list<W: Write>(
-        &mut self,
-        ty: &ListType,
-        delim: &DelimType,
-        start: &StartingNumber,
-        tightness: &Tightness,
-        event: &Event,
-        writer: &mut W
-    ) -> io::Result<()> {
-        self.tightness = (*tightness).into();
-
-        match event {
-            &Event::Enter => {
-                match ty {
-                    &ListType::Bullet => write!(writer, "{}<ul>\n", self.make_indent()),
-                    &ListType::Ordered => write!(writer, "{}<ol>\n", self.make_indent()),
-                }?;
-
-                self.indent += 1;
-            },
-
-            &Event::Exit => {
-                self.indent -= 1;
-
-                match ty {
-                    &ListType::Bullet => write!(writer, "{}</ul>\n", self.make_indent()),
-                    &ListType::Ordered => write!(writer, "{}</ol>\n", self.make_indent()),
-                }?;
-            },
-        };
-
-        Ok(())
-    }
-
-    fn convert_item<W: Write>(
-        &mut self,
-        event: &Event,
-        writer: &mut W
-    ) -> io::Result<()> {
-        match event {
-            &Event::Enter => {
-                match self.tightness {
-                    true => write!(writer, "{}<li>\n", self.make_indent()),
-                    false => write!(writer, "{}<li><p>\n", self.make_indent()),
-                }?;
-
-                self.indent += 1;
-            },
-
-            &Event::Exit => {
-                self.indent -= 1;
-                
-                match self.tightness {
-                    true => write!(writer, "{}</li>\n", self.make_indent()),
-                    false => write!(writer, "{}</p></li>\n", self.make_indent()),
-                }?;
-            },
-        };
-
-        Ok(())
-    }
-
-    fn convert_code_block<W: Write>(
-        &self,
-        info: &InfoString,
-        lit: &Literal,
-        writer: &mut W
-    ) -> io::Result<()> {
-        match info.is_empty() {
-            true => write!(
-                writer,
-                "{}<pre><code>\n{}\n</code></pre>\n",
-                self.make_indent(), lit
-            ),
-
-            false => write!(
-                writer,
-                "{}<pre><code class=\"{}\">\n{}\n</code></pre>\n",
-                self.make_indent(), info, lit
-            ),
-        }?;
-
-        Ok(())
-    }
-
-    fn convert_html_block<W: Write>(
-        &self,
-        lit: &Literal,
-        writer: &mut W
-    ) -> io::Result<()> {
-        write!(writer, "{}{}\n", self.make_indent(), lit)?;
-        Ok(())
-    }
-
-    fn convert_paragraph<W: Write>(
-        &mut self,
-        event: &Event,
-        writer: &mut W
-    ) -> io::Result<()> {
-        match event {
-            &Event::Enter => {
-                write!(writer, "{}<p>", self.make_indent())?;
-                self.indent += 1;
-            },
-
-            &Event::Exit => {
-                self.indent -= 1;
-                write!(writer, "</p>\n")?;
-            },
-        };
-
-        Ok(())
-    }
-
-    fn convert_heading<W: Write>(
-        &mut self,
-        lvl: &HeadingLevel,
-        event: &Event,
-        writer: &mut W
-    ) -> io::Result<()> {
-        match event {
-            &Event::Enter => {
-                match lvl {
-                    &HeadingLevel::One => write!(writer, "{}<h1>", self.make_indent()),
-                    &HeadingLevel::Two => write!(writer, "{}<h2>", self.make_indent()),
-                    &HeadingLevel::Three => write!(writer, "{}<h3>", self.make_indent()),
-                    &HeadingLevel::Four => write!(writer, "{}<h4>", self.make_indent()),
-                    &HeadingLevel::Five => write!(writer, "{}<h5>", self.make_indent()),
-                    &HeadingLevel::Six => write!(writer, "{}<h6>", self.make_indent()),
-                }?;
-
-                self.indent += 1;
-            },
-
-            &Event::Exit => {
-                self.indent -= 1;
-                
-                match lvl {
-                    &HeadingLevel::One => write!(writer, "</h1>\n"),
-                    &HeadingLevel::Two => write!(writer, "</h2>\n"),
-                    &HeadingLevel::Three => write!(writer, "</h3>\n"),
-                    &HeadingLevel::Four => write!(writer, "</h4>\n"),
-                    &HeadingLevel::Five => write!(writer, "</h5>\n"),
-                    &HeadingLevel::Six => write!(writer, "</h6>\n"),
-                }?;
-            },
-        };
-
-        Ok(())
-    }
-
-    fn convert_thematic_break<W: Write>(
-        &self,
-        writer: &mut W
-    ) -> io::Result<()> {
-        write!(writer, "{}<hr />\n", self.make_indent())?;
-        Ok(())
-    }
-
-    fn convert_text<W: Write>(
-        &self,
-        lit: &Literal,
-        writer: &mut W
-    ) -> io::Result<()> {
-        write!(writer, "{}", lit)?;
-        Ok(())
-    }
-
-    fn convert_soft_break<W: Write>(
-        &self,
-        writer: &mut W
-    ) -> io::Result<()> {
-        write!(writer, "\n")?;
-        Ok(())
-    }
-
-    fn convert_line_break<W: Write>(
-        &self,
-        writer: &mut W
-    ) -> io::Result<()> {
-        write!(writer, "<br />\n")?;
-        Ok(())
-    }
-
-    fn convert_code<W: Write>(
-        &self,
-        lit: &Literal,
-        writer: &mut W
-    ) -> io::Result<()> {
-        write!(writer, "<code>{}</code>", lit);
-        Ok(())
-    }
-
-    fn convert_html_inline<W: Write>(
-        &self,
-        lit: &Literal,
-        writer: &mut W
-    ) -> io::Result<()> {
-        write!(writer, "{}", lit);
-        Ok(())
-    }
-
-    fn convert_emph<W: Write>(
-        &self,
-        event: &Event,
-        writer: &mut W
-    ) -> io::Result<()> {
-        match event {
-            &Event::Enter => write!(writer, "<em>"),
-            &Event::Exit => write!(writer, "</em>"),
-        }?;
-
-        Ok(())
-    }
-
-    fn convert_strong<W: Write>(
-        &self,
-        event: &Event,
-        writer: &mut W
-    ) -> io::Result<()> {
-        match event {
-            &Event::Enter => write!(writer, "<strong>"),
-            &Event::Exit => write!(writer, "</strong>"),
-        }?;
-
-        Ok(())
-    }
-
-    fn convert_link<W: Write>(
-        &self,
-        url: &Url,
-        title: &Title,
-        event: &Event,
-        writer: &mut W
-    ) -> io::Result<()> {
-        match event {
-            &Event::Enter => match title.is_empty() {
-                true => write!(writer, "<a href=\"{}\">", url),
-                false => write!(writer, "<a href=\"{}\" title=\"{}\">", url, title),
-            },
-
-            &Event::Exit => write!(writer, "</a>"),
-        }?;
-
-        Ok(())
-    }
-
-    fn convert_image<W: Write>(
-        &self,
-        url: &Url,
-        title: &Title,
-        event: &Event,
-        writer: &mut W
-    ) -> io::Result<()> {
-        match event {
-            &Event::Enter => match title.is_empty() {
-                true => write!(writer, "<img src=\"{}\" alt=\"", url),
-                false => write!(writer, "<img src=\"{}\" title=\"{}\" alt=\"", url, title),
-            },
-
-            &Event::Exit => write!(writer, "\" />"),
-        }?;
-
-        Ok(())
+        res
     }
 
 }
@@ -428,113 +153,96 @@ impl<'a> Converter for BasicConverter<'a> {
 
     type MoreData = BasicData<'a>;
     
-    fn new() -> Self {
+    fn new(options: Options) -> Self {
         Self {
-            indent: 0,
-            tightness: false,
+            html: HtmlRenderer::new(options),
+            visitor: Box::new(SpecialVisitor::new()),
+            site: Context::new(),
             phantom: PhantomData,
         }
     }
 
+    fn options(&self) -> Options {
+        self.html.options()
+    }
+
+    /// Picks out the math and IPA around parsing, see `special::spans`.
     fn convert<R: Read, W: Write>(
         &mut self,
         reader: &mut R,
         writer: &mut W,
-        data: Self::MoreData,
-    ) -> io::Result<()> {
-        let assets = data.assets;
-        let dist = data.dist;
-
-        let mut read_buffer = String::new();
-        reader.read_to_string(&mut read_buffer).unwrap();
-
-        let iter = Iter::from_parser({
-            let mut parser = Parser::new(Options::DEFAULT);
-            parser.feed(read_buffer.as_str(), read_buffer.len()).expect(
-                "feeding failed"
-            );
-            parser
-        });
-
-        self.write_header(writer, assets, dist)?;
-
-        for (node, event) in iter {
-            match node {
-                Node::Block(Block::Document) => Ok(()),
-
-                Node::Block(Block::Blockquote) =>
-                    self.convert_blockquote(&event, writer),
-
-                Node::Block(Block::List(ty, delim, start, tightness)) =>
-                    self.convert_list(
-                        &ty,
-                        &delim,
-                        &start,
-                        &tightness,
-                        &event,
-                        writer
-                    ),
-
-                Node::Block(Block::Item) =>
-                    self.convert_item(&event, writer),
-
-                Node::Block(Block::CodeBlock(info, lit)) =>
-                    self.convert_code_block(&info, &lit, writer),
-
-                Node::Block(Block::HtmlBlock(lit)) =>
-                    self.convert_html_block(&lit, writer),
-
-                Node::Block(Block::CustomBlock) => {
-                    println!("custom blocks not implemented yet");
-                    Ok(())
-                },
-
-                Node::Block(Block::Paragraph) =>
-                    self.convert_paragraph(&event, writer),
-
-                Node::Block(Block::Heading(lvl)) =>
-                    self.convert_heading(&lvl, &event, writer),
-
-                Node::Block(Block::ThematicBreak) =>
-                    self.convert_thematic_break(writer),
-
-                Node::Inline(Inline::Text(lit)) =>
-                    self.convert_text(&lit, writer),
-
-                Node::Inline(Inline::SoftBreak) =>
-                    self.convert_soft_break(writer),
-
-                Node::Inline(Inline::LineBreak) =>
-                    self.convert_line_break(writer),
-
-                Node::Inline(Inline::Code(lit)) =>
-                    self.convert_code(&lit, writer),
-
-                Node::Inline(Inline::HtmlInline(lit)) =>
-                    self.convert_html_inline(&lit, writer),
+        pipeline: &mut Pipeline,
+        data: Self::MoreData
+    ) -> Result<(), Error> {
+        let mut document = parse(&mut Protect::new(BufReader::new(reader)), self.options())?;
+        SpecialSpans::new().transform(&mut document)?;
+        pipeline.run(&mut document)?;
+        self.render(&document, writer, data)
+    }
+
+    /// Renders the layout named in the front matter, or the default one. The
+    /// post itself is indented to the column of `{{{ content }}}`.
+    fn render<W: Write>(
+        &mut self,
+        document: &Document,
+        writer: &mut W,
+        data: Self::MoreData
+    ) -> Result<(), Error> {
+        let context = self.context(document, &data);
+        let layout = match data.front_matter.layout {
+            Some(ref layout) => layout.as_str(),
+            None => "default",
+        };
 
-                Node::Inline(Inline::CustomInline) => {
-                    println!("custom inlines not implemented yet");
-                    Ok(())
-                },
+        let html = &mut self.html;
+        let visitor = &mut self.visitor;
+        let page = data.templates.render(layout, &context, |indent| {
+            let mut body: Vec<u8> = Vec::new();
+            html.set_base_indent(indent);
+            html.render(document, &mut **visitor, &mut body, data.source)?;
 
-                Node::Inline(Inline::Emph) =>
-                    self.convert_emph(&event, writer),
+            String::from_utf8(body).map_err(|e| Error::Utf8(e.utf8_error()))
+        })?;
 
-                Node::Inline(Inline::Strong) =>
-                    self.convert_strong(&event, writer),
+        writer.write_all(page.as_bytes())?;
 
-                Node::Inline(Inline::Link(url, title)) =>
-                    self.convert_link(&url, &title, &event, writer),
+        Ok(())
+    }
 
-                Node::Inline(Inline::Image(url, title)) =>
-                    self.convert_image(&url, &title, &event, writer),
-            }?;
-        }
+}
 
-        self.write_footer(writer)?;
+/// Text of the first level 1 heading, with the markup flattened away.
+fn first_heading(document: &Document) -> Option<String> {
+    document.root()
+        .find(|tree| match tree.node {
+            Node::Block(Block::Heading(HeadingLevel::One)) => true,
+            _ => false,
+        })
+        .map(|tree| tree.text().trim().to_owned())
+        .filter(|text| !text.is_empty())
+}
 
-        Ok(())
+/// Text of the first paragraph, cut at a word boundary if it is longer than
+/// `DESCRIPTION_LEN` characters.
+fn first_paragraph(document: &Document) -> Option<String> {
+    let text = document.root()
+        .find(|tree| match tree.node {
+            Node::Block(Block::Paragraph) => true,
+            _ => false,
+        })
+        .map(|tree| tree.text().split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|text| !text.is_empty())?;
+
+    match text.chars().count() > DESCRIPTION_LEN {
+        true => {
+            let cut: String = text.chars().take(DESCRIPTION_LEN).collect();
+            let cut = match cut.rfind(' ') {
+                Some(idx) => &cut[.. idx],
+                None => &cut[..],
+            };
+            Some(format!("{}…", cut))
+        },
+
+        false => Some(text),
     }
-
 }
@@ -0,0 +1,70 @@
+/*
+ *  Escaping for the HTML converters
+ *
+ *  Mirrors what cmark's HTML renderer does with houdini, so that our output
+ *  agrees with cmark's on anything that ends up inside an attribute.
+ */
+
+/// Escapes `&`, `<`, `>` and `"`, which is enough for both text and
+/// double-quoted attribute values. Same as `houdini_escape_html0` with
+/// `secure` off.
+pub fn escape_html(text: &str) -> String {
+    let mut res = String::with_capacity(text.len());
+
+    for ch in text.chars() {
+        match ch {
+            '&' => res.push_str("&amp;"),
+            '<' => res.push_str("&lt;"),
+            '>' => res.push_str("&gt;"),
+            '"' => res.push_str("&quot;"),
+            _ => res.push(ch),
+        };
+    }
+
+    res
+}
+
+/// Whether a byte can appear in an `href` as is. These are the characters
+/// that are safe in a URL plus the reserved ones, which we assume are used for
+/// their reserved purpose. See `houdini_href_e.c`.
+fn is_href_safe(byte: u8) -> bool {
+    match byte {
+        b'a' ..= b'z' | b'A' ..= b'Z' | b'0' ..= b'9' => true,
+        b'-' | b'_' | b'.' | b'+' | b'!' | b'*' | b'(' | b')' | b',' |
+        b'%' | b'#' | b'@' | b'?' | b'=' | b';' | b':' | b'/' | b'$' => true,
+        _ => false,
+    }
+}
+
+/// Percent-encodes a URL for use in `href` and `src`, leaving alone anything
+/// that is already valid in a URL (including existing `%XX` escapes). `&` and
+/// `'` are valid but still need escaping as HTML entities.
+pub fn escape_href(url: &str) -> String {
+    let mut res = String::with_capacity(url.len());
+
+    for &byte in url.as_bytes() {
+        match byte {
+            b'&' => res.push_str("&amp;"),
+            b'\'' => res.push_str("&#x27;"),
+            _ if is_href_safe(byte) => res.push(byte as char),
+            _ => res.push_str(&format!("%{:02X}", byte)),
+        };
+    }
+
+    res
+}
+
+/// Whether a URL uses a scheme that `Options::SAFE` refuses to link to:
+/// `javascript:`, `vbscript:`, `file:`, and `data:` except for PNG, GIF, JPEG
+/// and WebP images.
+pub fn is_dangerous_url(url: &str) -> bool {
+    let url = url.to_ascii_lowercase();
+
+    match url.starts_with("data:image/") {
+        true => !["png", "gif", "jpeg", "webp"].iter()
+            .any(|ty| url["data:image/".len() ..].starts_with(ty)),
+
+        false => ["javascript:", "vbscript:", "file:", "data:"].iter()
+            .any(|scheme| url.starts_with(scheme)),
+    }
+}
@@ -0,0 +1,947 @@
+/*
+ *  The HTML renderer shared by the simple and the basic converter
+ *
+ *  HtmlRenderer knows how to write out every node, and keeps track of the
+ *  state that spans nodes (indentation, list tightness, image alt text).
+ *  A Visitor decides what to do with each node; by default it hands the node
+ *  right back to HtmlRenderer.
+ */
+
+use converters::escape::*;
+use converters::visitor::Visitor;
+
+use cmark::*;
+
+use std::io;
+use std::io::Write;
+
+use std::iter;
+
+use std::path::Path;
+
+/// Default string indenting blocks one level.
+pub const INDENT: &str = "    ";
+
+pub struct HtmlRenderer {
+    indent: usize,
+
+    /// Indentation of the outermost blocks, e.g. that of the template line
+    /// the body goes into.
+    base_indent: String,
+
+    /// String indenting blocks one level, `INDENT` unless customized.
+    indent_str: String,
+
+    /// Tightness of the enclosing lists, innermost last. Block quotes push
+    /// `false`, since paragraphs directly inside them are never tight.
+    tightness: Vec<bool>,
+
+    /// Number of images we are inside of. Their contents become the `alt`
+    /// attribute, so they are written as plain text.
+    plain: usize,
+
+    /// Extra attributes of the node being converted, from `Tree::attributes`.
+    attributes: Vec<(String, String)>,
+
+    compliant: bool,
+    options: Options,
+}
+
+/// Wraps the output so that the compliant mode can tell whether it is at the
+/// start of a line, like cmark's own `cr()`.
+pub struct Output<'w> {
+    inner: &'w mut dyn Write,
+    last: Option<u8>,
+}
+
+impl<'w> Output<'w> {
+    fn new(inner: &'w mut dyn Write) -> Self {
+        Self {
+            inner: inner,
+            last: None,
+        }
+    }
+
+    /// Starts a new line, unless we are at the start of one already.
+    fn cr(&mut self) -> io::Result<()> {
+        match self.last {
+            Some(b'\n') | None => Ok(()),
+            Some(_) => self.write_all(b"\n"),
+        }
+    }
+}
+
+impl<'w> Write for Output<'w> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        if n > 0 {
+            self.last = Some(buf[n - 1]);
+        }
+
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/*
+ *  Converting methods
+ *
+ *  A method needs &Event as a parameter iff the node is not a leaf node.
+ *  Leaf nodes are HtmlBlock, ThematicBreak, CodeBlock, Text, SoftBreak,
+ *  LineBreak, Code and HtmlInline.
+ */
+
+impl HtmlRenderer {
+
+    pub fn new(options: Options) -> Self {
+        Self {
+            indent: 0,
+            base_indent: String::new(),
+            indent_str: INDENT.to_owned(),
+            tightness: Vec::new(),
+            plain: 0,
+            attributes: Vec::new(),
+            compliant: false,
+            options: options,
+        }
+    }
+
+    pub fn options(&self) -> Options {
+        self.options
+    }
+
+    /// Switches between the default pretty-printed output and the compliant
+    /// output, which matches cmark's own HTML renderer byte for byte.
+    pub fn set_compliant(&mut self, compliant: bool) {
+        self.compliant = compliant;
+    }
+
+    /// Sets the indentation of the outermost blocks, for when the body goes
+    /// inside a page template. Deeper blocks add `indent_str` to it.
+    pub fn set_base_indent(&mut self, base_indent: &str) {
+        self.base_indent = base_indent.to_owned();
+    }
+
+    /// Replaces the four spaces indenting blocks one level, e.g. with a tab.
+    pub fn set_indent_str(&mut self, indent_str: &str) {
+        self.indent_str = indent_str.to_owned();
+    }
+
+    fn repeat_indent(&self, n: usize) -> String {
+        let mut indent = self.base_indent.clone();
+        indent.extend(iter::repeat(self.indent_str.as_str()).take(n));
+        indent
+    }
+
+    fn make_indent(&self) -> String {
+        match self.compliant {
+            true => String::new(),
+            false => self.repeat_indent(self.indent),
+        }
+    }
+
+    /// Indentation of a line `extra` levels inside the current block, for
+    /// visitors writing out blocks of their own. Empty in compliant mode.
+    pub fn indent(&self, extra: usize) -> String {
+        match self.compliant {
+            true => String::new(),
+            false => self.repeat_indent(self.indent + extra),
+        }
+    }
+
+    /// Starts a new line in compliant mode. Does nothing otherwise.
+    fn cr(&self, writer: &mut Output) -> io::Result<()> {
+        match self.compliant {
+            true => writer.cr(),
+            false => Ok(()),
+        }
+    }
+
+    /// Whether paragraphs at the current position are left unwrapped, i.e.
+    /// whether they sit directly in an item of a tight list.
+    fn is_tight(&self) -> bool {
+        self.tightness.last() == Some(&true)
+    }
+
+    /// Value of an extra attribute of the node being converted, see
+    /// `Tree::attributes`.
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes.iter()
+            .find(|&&(ref key, _)| key == name)
+            .map(|&(_, ref value)| value.as_str())
+    }
+
+    /// Returns the attributes of a block, each with a leading space: the
+    /// `data-sourcepos` attribute if `Options::SOURCE_POS` is set, followed by
+    /// the node's extra attributes.
+    fn attributes(&self, span: &SourceSpan) -> String {
+        let mut res = match self.options.contains(Options::SOURCE_POS) {
+            true => format!(
+                " data-sourcepos=\"{}:{}-{}:{}\"",
+                span.start_line, span.start_column,
+                span.end_line, span.end_column
+            ),
+            false => String::new(),
+        };
+
+        for &(ref name, ref value) in &self.attributes {
+            match value.is_empty() {
+                true => res.push_str(&format!(" {}", name)),
+                false => res.push_str(&format!(" {}=\"{}\"", name, escape_html(value))),
+            };
+        }
+
+        res
+    }
+
+    fn write_literal(
+        &self,
+        lit: &Literal,
+        writer: &mut Output
+    ) -> io::Result<()> {
+        write!(writer, "{}", escape_html(lit))
+    }
+
+    /// Escapes a link or image destination. With `Options::SAFE`, dangerous
+    /// URLs are dropped altogether, like cmark does.
+    fn href(&self, url: &Url) -> String {
+        match self.options.contains(Options::SAFE) && is_dangerous_url(url) {
+            true => String::new(),
+            false => escape_href(url),
+        }
+    }
+
+    pub fn convert_blockquote(
+        &mut self,
+        span: &SourceSpan,
+        event: &Event,
+        writer: &mut Output
+    ) -> io::Result<()> {
+        match event {
+            &Event::Enter => {
+                self.cr(writer)?;
+                write!(writer, "{}<blockquote{}>\n", self.make_indent(), self.attributes(span))?;
+                self.tightness.push(false);
+                self.indent += 1;
+            },
+
+            &Event::Exit => {
+                self.indent -= 1;
+                self.tightness.pop();
+                self.cr(writer)?;
+                write!(writer, "{}</blockquote>\n", self.make_indent())?;
+            },
+        };
+
+        Ok(())
+    }
+
+    pub fn convert_list(
+        &mut self,
+        ty: &ListType,
+        delim: &DelimType,
+        start: &StartingNumber,
+        tightness: &Tightness,
+        span: &SourceSpan,
+        event: &Event,
+        writer: &mut Output
+    ) -> io::Result<()> {
+        match event {
+            &Event::Enter => {
+                self.cr(writer)?;
+
+                //  the delimiter is only there for themes to pick up, so it
+                //  stays out of the compliant output
+                let start = match **start != 1 {
+                    true => format!(" start=\"{}\"", start),
+                    false => String::new(),
+                };
+                let delim = match (delim, self.compliant) {
+                    (&DelimType::Period, false) => " data-delimiter=\"period\"",
+                    (&DelimType::Paren, false) => " data-delimiter=\"paren\"",
+                    _ => "",
+                };
+
+                match ty {
+                    &ListType::Bullet => write!(writer, "{}<ul{}>\n", self.make_indent(), self.attributes(span)),
+
+                    &ListType::Ordered => write!(
+                        writer,
+                        "{}<ol{}{}{}>\n",
+                        self.make_indent(),
+                        self.attributes(span),
+                        start,
+                        delim
+                    ),
+                }?;
+
+                self.tightness.push((*tightness).into());
+                self.indent += 1;
+            },
+
+            &Event::Exit => {
+                self.indent -= 1;
+                self.tightness.pop();
+                self.cr(writer)?;
+
+                match ty {
+                    &ListType::Bullet => write!(writer, "{}</ul>\n", self.make_indent()),
+                    &ListType::Ordered => write!(writer, "{}</ol>\n", self.make_indent()),
+                }?;
+            },
+        };
+
+        Ok(())
+    }
+
+    pub fn convert_item(
+        &mut self,
+        span: &SourceSpan,
+        event: &Event,
+        writer: &mut Output
+    ) -> io::Result<()> {
+        //  whether the item is tight or loose is up to its paragraphs
+        match event {
+            &Event::Enter if self.compliant => {
+                self.cr(writer)?;
+                write!(writer, "<li{}>", self.attributes(span))?;
+            },
+
+            &Event::Enter => {
+                write!(writer, "{}<li{}>\n", self.make_indent(), self.attributes(span))?;
+                self.indent += 1;
+            },
+
+            &Event::Exit if self.compliant => {
+                write!(writer, "</li>\n")?;
+            },
+
+            &Event::Exit => {
+                self.indent -= 1;
+                write!(writer, "{}</li>\n", self.make_indent())?;
+            },
+        };
+
+        Ok(())
+    }
+
+    pub fn convert_code_block(
+        &self,
+        info: &InfoString,
+        lit: &Literal,
+        span: &SourceSpan,
+        writer: &mut Output
+    ) -> io::Result<()> {
+        self.cr(writer)?;
+
+        match info.is_empty() {
+            true => {
+                write!(writer, "{}<pre{}><code>", self.make_indent(), self.attributes(span))?;
+                self.write_literal(lit, writer)?;
+                write!(writer, "</code></pre>\n")?;
+            },
+
+            false => {
+                write!(
+                    writer,
+                    "{}<pre{}><code class=\"language-",
+                    self.make_indent(),
+                    self.attributes(span)
+                )?;
+                write!(writer, "{}\">", escape_html(&info.strip()))?;
+                self.write_literal(lit, writer)?;
+                write!(writer, "</code></pre>\n")?;
+            },
+        }
+
+        Ok(())
+    }
+
+    pub fn convert_html_block(
+        &self,
+        lit: &Literal,
+        writer: &mut Output
+    ) -> io::Result<()> {
+        self.cr(writer)?;
+        write!(writer, "{}", self.make_indent())?;
+        match self.options.contains(Options::SAFE) {
+            true => write!(writer, "<!-- raw HTML omitted -->"),
+            false => write!(writer, "{}", lit),
+        }?;
+
+        writer.cr()
+    }
+
+    pub fn convert_paragraph(
+        &mut self,
+        span: &SourceSpan,
+        event: &Event,
+        writer: &mut Output
+    ) -> io::Result<()> {
+        //  tight paragraphs still get a line of their own when pretty-printed
+        match (self.is_tight(), self.compliant) {
+            (true, true) => return Ok(()),
+            (true, false) => return match event {
+                &Event::Enter => write!(writer, "{}", self.make_indent()),
+                &Event::Exit => write!(writer, "\n"),
+            },
+            (false, _) => (),
+        };
+
+        match event {
+            &Event::Enter => {
+                self.cr(writer)?;
+                write!(writer, "{}<p{}>", self.make_indent(), self.attributes(span))?;
+                self.indent += 1;
+            },
+
+            &Event::Exit => {
+                self.indent -= 1;
+                write!(writer, "</p>\n")?;
+            },
+        };
+
+        Ok(())
+    }
+
+    pub fn convert_heading(
+        &mut self,
+        lvl: &HeadingLevel,
+        span: &SourceSpan,
+        event: &Event,
+        writer: &mut Output
+    ) -> io::Result<()> {
+        match event {
+            &Event::Enter => {
+                self.cr(writer)?;
+
+                match lvl {
+                    &HeadingLevel::One => write!(writer, "{}<h1{}>", self.make_indent(), self.attributes(span)),
+                    &HeadingLevel::Two => write!(writer, "{}<h2{}>", self.make_indent(), self.attributes(span)),
+                    &HeadingLevel::Three => write!(writer, "{}<h3{}>", self.make_indent(), self.attributes(span)),
+                    &HeadingLevel::Four => write!(writer, "{}<h4{}>", self.make_indent(), self.attributes(span)),
+                    &HeadingLevel::Five => write!(writer, "{}<h5{}>", self.make_indent(), self.attributes(span)),
+                    &HeadingLevel::Six => write!(writer, "{}<h6{}>", self.make_indent(), self.attributes(span)),
+                }?;
+
+                self.indent += 1;
+            },
+
+            &Event::Exit => {
+                self.indent -= 1;
+                
+                match lvl {
+                    &HeadingLevel::One => write!(writer, "</h1>\n"),
+                    &HeadingLevel::Two => write!(writer, "</h2>\n"),
+                    &HeadingLevel::Three => write!(writer, "</h3>\n"),
+                    &HeadingLevel::Four => write!(writer, "</h4>\n"),
+                    &HeadingLevel::Five => write!(writer, "</h5>\n"),
+                    &HeadingLevel::Six => write!(writer, "</h6>\n"),
+                }?;
+            },
+        };
+
+        Ok(())
+    }
+
+    pub fn convert_thematic_break(
+        &self,
+        span: &SourceSpan,
+        writer: &mut Output
+    ) -> io::Result<()> {
+        self.cr(writer)?;
+        write!(writer, "{}<hr{} />\n", self.make_indent(), self.attributes(span))?;
+        Ok(())
+    }
+
+    pub fn convert_text(
+        &self,
+        lit: &Literal,
+        writer: &mut Output
+    ) -> io::Result<()> {
+        self.write_literal(lit, writer)
+    }
+
+    pub fn convert_soft_break(
+        &self,
+        writer: &mut Output
+    ) -> io::Result<()> {
+        if self.options.contains(Options::HARD_BREAKS) {
+            write!(writer, "<br />\n")?;
+        } else if self.options.contains(Options::NO_BREAKS) {
+            write!(writer, " ")?;
+        } else {
+            write!(writer, "\n")?;
+        }
+
+        Ok(())
+    }
+
+    pub fn convert_line_break(
+        &self,
+        writer: &mut Output
+    ) -> io::Result<()> {
+        write!(writer, "<br />\n")?;
+        Ok(())
+    }
+
+    pub fn convert_code(
+        &self,
+        lit: &Literal,
+        writer: &mut Output
+    ) -> io::Result<()> {
+        write!(writer, "<code>")?;
+        self.write_literal(lit, writer)?;
+        write!(writer, "</code>")
+    }
+
+    pub fn convert_html_inline(
+        &self,
+        lit: &Literal,
+        writer: &mut Output
+    ) -> io::Result<()> {
+        match self.options.contains(Options::SAFE) {
+            true => write!(writer, "<!-- raw HTML omitted -->"),
+            false => write!(writer, "{}", lit),
+        }
+    }
+
+    pub fn convert_emph(
+        &self,
+        event: &Event,
+        writer: &mut Output
+    ) -> io::Result<()> {
+        match event {
+            &Event::Enter => write!(writer, "<em>"),
+            &Event::Exit => write!(writer, "</em>"),
+        }?;
+
+        Ok(())
+    }
+
+    pub fn convert_strong(
+        &self,
+        event: &Event,
+        writer: &mut Output
+    ) -> io::Result<()> {
+        match event {
+            &Event::Enter => write!(writer, "<strong>"),
+            &Event::Exit => write!(writer, "</strong>"),
+        }?;
+
+        Ok(())
+    }
+
+    pub fn convert_link(
+        &self,
+        url: &Url,
+        title: &Title,
+        event: &Event,
+        writer: &mut Output
+    ) -> io::Result<()> {
+        match event {
+            &Event::Enter => match title.is_empty() {
+                true => write!(writer, "<a href=\"{}\">", self.href(url)),
+                false => write!(
+                    writer,
+                    "<a href=\"{}\" title=\"{}\">",
+                    self.href(url), escape_html(title)
+                ),
+            },
+
+            &Event::Exit => write!(writer, "</a>"),
+        }?;
+
+        Ok(())
+    }
+
+    /// The title goes after the `alt` attribute, as cmark writes it, and only
+    /// the outermost of nested images produces a tag.
+    pub fn convert_image(
+        &mut self,
+        url: &Url,
+        title: &Title,
+        event: &Event,
+        writer: &mut Output
+    ) -> io::Result<()> {
+        match event {
+            &Event::Enter => {
+                if self.plain == 0 {
+                    write!(writer, "<img src=\"{}\" alt=\"", self.href(url))?;
+                }
+
+                self.plain += 1;
+            },
+
+            &Event::Exit => {
+                self.plain -= 1;
+
+                if self.plain == 0 {
+                    if !title.is_empty() {
+                        write!(writer, "\" title=\"{}", escape_html(title))?;
+                    }
+
+                    write!(writer, "\" />")?;
+                }
+            },
+        };
+
+        Ok(())
+    }
+
+    /// Writes the contents of an image as plain text, for its `alt`
+    /// attribute. Only text survives; breaks become spaces.
+    fn convert_plain(
+        &mut self,
+        node: &Node,
+        event: &Event,
+        writer: &mut Output
+    ) -> io::Result<()> {
+        match node {
+            &Node::Inline(Inline::Text(ref lit)) |
+            &Node::Inline(Inline::Code(ref lit)) |
+            &Node::Inline(Inline::HtmlInline(ref lit)) =>
+                self.write_literal(lit, writer),
+
+            &Node::Inline(Inline::SoftBreak) |
+            &Node::Inline(Inline::LineBreak) =>
+                write!(writer, " "),
+
+            &Node::Inline(Inline::Image(ref url, ref title)) =>
+                self.convert_image(url, title, event, writer),
+
+            _ => Ok(()),
+        }
+    }
+
+    /// Renders the body of a document, leaving each node to `visitor`.
+    /// `source` is only used in log messages.
+    pub fn render<W: Write>(
+        &mut self,
+        document: &Document,
+        visitor: &mut dyn Visitor,
+        writer: &mut W,
+        source: &Path
+    ) -> Result<(), Error> {
+        let mut writer = Output::new(writer);
+        let writer = &mut writer;
+
+        visitor.begin(document, source);
+
+        for (tree, event) in document.walk() {
+            if self.plain > 0 {
+                self.convert_plain(&tree.node, &event, writer)?;
+                continue;
+            }
+
+            self.attributes.clone_from(&tree.attributes);
+
+            match tree.node {
+                Node::Block(Block::Document) => Ok(()),
+
+                Node::Block(Block::Blockquote) =>
+                    visitor.visit_blockquote(self, &tree.span, &event, writer),
+
+                Node::Block(Block::List(ref ty, ref delim, ref start, ref tightness)) =>
+                    visitor.visit_list(
+                        self,
+                        ty,
+                        delim,
+                        start,
+                        tightness,
+                        &tree.span,
+                        &event,
+                        writer
+                    ),
+
+                Node::Block(Block::Item) =>
+                    visitor.visit_item(self, &tree.span, &event, writer),
+
+                Node::Block(Block::CodeBlock(ref info, ref lit)) =>
+                    visitor.visit_code_block(self, info, lit, &tree.span, writer),
+
+                Node::Block(Block::HtmlBlock(ref lit)) =>
+                    visitor.visit_html_block(self, lit, &tree.span, writer),
+
+                Node::Block(Block::CustomBlock) => {
+                    warn!(
+                        "{}:{}: Custom blocks are not implemented yet",
+                        source.display(), tree.span
+                    );
+                    Ok(())
+                },
+
+                Node::Block(Block::Paragraph) =>
+                    visitor.visit_paragraph(self, &tree.span, &event, writer),
+
+                Node::Block(Block::Heading(ref lvl)) =>
+                    visitor.visit_heading(self, lvl, &tree.span, &event, writer),
+
+                Node::Block(Block::ThematicBreak) =>
+                    visitor.visit_thematic_break(self, &tree.span, writer),
+
+                Node::Inline(Inline::Text(ref lit)) =>
+                    visitor.visit_text(self, lit, &tree.span, writer),
+
+                Node::Inline(Inline::SoftBreak) =>
+                    visitor.visit_soft_break(self, &tree.span, writer),
+
+                Node::Inline(Inline::LineBreak) =>
+                    visitor.visit_line_break(self, &tree.span, writer),
+
+                Node::Inline(Inline::Code(ref lit)) =>
+                    visitor.visit_code(self, lit, &tree.span, writer),
+
+                Node::Inline(Inline::HtmlInline(ref lit)) =>
+                    visitor.visit_html_inline(self, lit, &tree.span, writer),
+
+                Node::Inline(Inline::CustomInline) => {
+                    warn!(
+                        "{}:{}: Custom inlines are not implemented yet",
+                        source.display(), tree.span
+                    );
+                    Ok(())
+                },
+
+                Node::Inline(Inline::Emph) =>
+                    visitor.visit_emph(self, &tree.span, &event, writer),
+
+                Node::Inline(Inline::Strong) =>
+                    visitor.visit_strong(self, &tree.span, &event, writer),
+
+                Node::Inline(Inline::Link(ref url, ref title)) =>
+                    visitor.visit_link(self, url, title, &tree.span, &event, writer),
+
+                Node::Inline(Inline::Image(ref url, ref title)) =>
+                    visitor.visit_image(self, url, title, &tree.span, &event, writer),
+            }?;
+        }
+
+        Ok(())
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use converters::parse;
+    use converters::visitor::{ DefaultVisitor, Visitor };
+    use transforms::Transform;
+    use transforms::attributes::BlockAttributes;
+
+    fn render(markdown: &str, compliant: bool) -> String {
+        let document = parse(&mut markdown.as_bytes(), Options::DEFAULT).unwrap();
+        render_document(&document, compliant)
+    }
+
+    fn render_document(document: &Document, compliant: bool) -> String {
+        let mut html = HtmlRenderer::new(Options::DEFAULT);
+        html.set_compliant(compliant);
+
+        let mut output: Vec<u8> = Vec::new();
+        html.render(document, &mut DefaultVisitor, &mut output, Path::new("test.md")).unwrap();
+
+        String::from_utf8(output).unwrap()
+    }
+
+    /// The compliant output must match cmark's, whatever the nesting.
+    fn assert_compliant(markdown: &str) {
+        assert_eq!(
+            render(markdown, true),
+            markdown_to_html(markdown, markdown.len(), Options::DEFAULT).unwrap()
+        );
+    }
+
+    #[test]
+    fn loose_list_in_tight_list() {
+        let markdown = "- a\n  - b\n\n    c\n- d\n";
+
+        assert_eq!(render(markdown, false), "\
+<ul>
+    <li>
+        a
+        <ul>
+            <li>
+                <p>b</p>
+                <p>c</p>
+            </li>
+        </ul>
+    </li>
+    <li>
+        d
+    </li>
+</ul>
+");
+        assert_compliant(markdown);
+    }
+
+    #[test]
+    fn tight_list_in_loose_list() {
+        let markdown = "- a\n\n  - b\n  - c\n\n- d\n";
+
+        assert_eq!(render(markdown, false), "\
+<ul>
+    <li>
+        <p>a</p>
+        <ul>
+            <li>
+                b
+            </li>
+            <li>
+                c
+            </li>
+        </ul>
+    </li>
+    <li>
+        <p>d</p>
+    </li>
+</ul>
+");
+        assert_compliant(markdown);
+    }
+
+    #[test]
+    fn loose_items_not_starting_with_paragraphs() {
+        let markdown = "1. ```\n   code\n   ```\n\n2. - sub\n\n3. text\n";
+
+        assert_eq!(render(markdown, false), "\
+<ol data-delimiter=\"period\">
+    <li>
+        <pre><code>code
+</code></pre>
+    </li>
+    <li>
+        <ul>
+            <li>
+                sub
+            </li>
+        </ul>
+    </li>
+    <li>
+        <p>text</p>
+    </li>
+</ol>
+");
+        assert_compliant(markdown);
+    }
+
+    #[test]
+    fn paragraphs_in_block_quotes_in_tight_lists() {
+        let markdown = "- > a\n- b\n";
+
+        assert_eq!(render(markdown, false), "\
+<ul>
+    <li>
+        <blockquote>
+            <p>a</p>
+        </blockquote>
+    </li>
+    <li>
+        b
+    </li>
+</ul>
+");
+        assert_compliant(markdown);
+    }
+
+    #[test]
+    fn ordered_list_start_delimiter_and_attributes() {
+        let markdown = "{reversed .exercises}\n\n3) c\n2) b\n";
+        let mut document = parse(&mut markdown.as_bytes(), Options::DEFAULT).unwrap();
+        BlockAttributes::new().transform(&mut document).unwrap();
+
+        assert_eq!(render_document(&document, false), "\
+<ol reversed class=\"exercises\" start=\"3\" data-delimiter=\"paren\">
+    <li>
+        c
+    </li>
+    <li>
+        b
+    </li>
+</ol>
+");
+        assert_compliant("3) c\n2) b\n");
+    }
+    #[test]
+    fn base_indent_is_kept_as_is() {
+        let document = parse(&mut "- a\n".as_bytes(), Options::DEFAULT).unwrap();
+        let mut html = HtmlRenderer::new(Options::DEFAULT);
+        html.set_base_indent("\t  ");
+        html.set_indent_str("\t");
+
+        let mut output: Vec<u8> = Vec::new();
+        html.render(&document, &mut DefaultVisitor, &mut output, Path::new("test.md")).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "\t  <ul>\n\t  \t<li>\n\t  \t\ta\n\t  \t</li>\n\t  </ul>\n"
+        );
+    }
+
+    /// Marks where each inline starts, from the spans the hooks are given.
+    struct Positions;
+
+    impl Visitor for Positions {
+        fn visit_soft_break(
+            &mut self,
+            _html: &mut HtmlRenderer,
+            span: &SourceSpan,
+            writer: &mut Output
+        ) -> io::Result<()> {
+            match span.is_unknown() {
+                true => write!(writer, "[?]\n"),
+                false => write!(writer, "[{}]\n", span),
+            }
+        }
+
+        fn visit_image(
+            &mut self,
+            html: &mut HtmlRenderer,
+            url: &Url,
+            title: &Title,
+            span: &SourceSpan,
+            event: &Event,
+            writer: &mut Output
+        ) -> io::Result<()> {
+            if let &Event::Enter = event {
+                write!(writer, "[{}]", span)?;
+            }
+
+            html.convert_image(url, title, event, writer)
+        }
+
+        fn visit_link(
+            &mut self,
+            html: &mut HtmlRenderer,
+            url: &Url,
+            title: &Title,
+            span: &SourceSpan,
+            event: &Event,
+            writer: &mut Output
+        ) -> io::Result<()> {
+            if let &Event::Enter = event {
+                write!(writer, "[{}]", span)?;
+            }
+
+            html.convert_link(url, title, event, writer)
+        }
+    }
+
+    #[test]
+    fn spans_reach_inline_hooks() {
+        let document = parse(&mut "a ![b](/e)\n[c](/d)\n".as_bytes(), Options::DEFAULT).unwrap();
+        let mut html = HtmlRenderer::new(Options::DEFAULT);
+
+        let mut output: Vec<u8> = Vec::new();
+        html.render(&document, &mut Positions, &mut output, Path::new("test.md")).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "<p>a [1:3]<img src=\"/e\" alt=\"b\" />[?]\n[2:1]<a href=\"/d\">c</a></p>\n"
+        );
+    }
+}
@@ -1,18 +1,55 @@
 pub mod basic;
+pub mod html;
+pub mod native;
 pub mod simple;
+pub mod special;
+pub mod template;
+pub mod visitor;
+
+mod escape;
+
+use cmark::{ Document, Error, Options, Parser };
+use transforms::Pipeline;
 
-use std::io;
 use std::io::{ Read, Write };
 
+/// Turns a markdown into some output. The HTML converters leave each node to a
+/// `visitor::Visitor`, which is where to change how one kind of node renders.
 pub trait Converter {
     type MoreData;
 
-    fn new() -> Self where Self: Sized;
+    fn new(options: Options) -> Self where Self: Sized;
+
+    /// The options used for both parsing and rendering.
+    fn options(&self) -> Options;
+
+    /// Writes out a document that has already been parsed and transformed.
+    fn render<W: Write>(
+        &mut self,
+        document: &Document,
+        writer: &mut W,
+        data: Self::MoreData
+    ) -> Result<(), Error>;
 
+    /// Parses the markdown from `reader`, runs `pipeline` on it, and renders
+    /// the result to `writer`.
     fn convert<R: Read, W: Write>(
         &mut self,
         reader: &mut R,
         writer: &mut W,
+        pipeline: &mut Pipeline,
         data: Self::MoreData
-    ) -> io::Result<()>;
+    ) -> Result<(), Error> {
+        let mut document = parse(reader, self.options())?;
+        pipeline.run(&mut document)?;
+        self.render(&document, writer, data)
+    }
+}
+
+/// Parses everything from `reader` into a document, chunk by chunk.
+fn parse<R: Read>(reader: &mut R, options: Options) -> Result<Document, Error> {
+    let mut parser = Parser::new(options);
+    parser.feed_reader(reader)?;
+
+    Document::from_parser(parser)
 }
@@ -0,0 +1,53 @@
+use converters::Converter;
+
+use cmark::*;
+
+use std::io::Write;
+
+/// Converter handing the whole document over to one of cmark's own renderers.
+/// Used for every output format other than our own HTML.
+pub struct NativeConverter {
+    options: Options,
+}
+
+pub struct NativeData {
+    format: Format,
+    width: usize,
+}
+
+impl NativeData {
+    pub fn new(format: Format, width: usize) -> Self {
+        Self {
+            format: format,
+            width: width,
+        }
+    }
+}
+
+impl Converter for NativeConverter {
+
+    type MoreData = NativeData;
+
+    fn new(options: Options) -> Self {
+        Self {
+            options: options,
+        }
+    }
+
+    fn options(&self) -> Options {
+        self.options
+    }
+
+    fn render<W: Write>(
+        &mut self,
+        document: &Document,
+        writer: &mut W,
+        data: Self::MoreData
+    ) -> Result<(), Error> {
+        let res = render(document, data.format, self.options, data.width)?;
+        writer.write_all(res.as_bytes())?;
+
+        Ok(())
+    }
+
+}
@@ -1,451 +1,77 @@
 use converters::Converter;
+use converters::html::HtmlRenderer;
+use converters::visitor::{ DefaultVisitor, Visitor };
 
 use cmark::*;
 
-use std::io;
-use std::io::{ Read, Write };
+use std::io::Write;
 
-use std::iter;
+use std::marker::PhantomData;
+use std::path::Path;
 
-use std::collections::HashMap;
-
-lazy_static! {
-    static ref INDENT: String = String::from("    ");
-
-    static ref CHAR_MAP: HashMap<char, String> = {
-        let mut result: HashMap<char, String> = HashMap::new();
-        result.insert('\"', String::from("&quot;"));
-        result.insert('&', String::from("&amp;"));
-        result.insert('<', String::from("&lt;"));
-        result.insert('>', String::from("&gt;"));
-        result
-    };
+pub struct SimpleConverter<'a> {
+    html: HtmlRenderer,
+    visitor: Box<dyn Visitor>,
+    phantom: PhantomData<&'a ()>,
 }
 
-pub struct SimpleConverter {
-    indent: usize,
-    tightness: bool,
+pub struct SimpleData<'a> {
+    source: &'a Path,
 }
 
-impl SimpleConverter {
-
-    fn repeat_indent(n: usize) -> String {
-        iter::repeat((*INDENT).clone()).take(n).collect::<String>()
-    }
-
-    fn make_indent(&self) -> String {
-        Self::repeat_indent(self.indent)
-    }
-
-    fn write_literal<W: Write>(
-        &self,
-        lit: &Literal,
-        writer: &mut W
-    ) -> io::Result<()> {
-        for ch in lit.chars() {
-            match CHAR_MAP.get(&ch) {
-                Some(ref s) => write!(writer, "{}", s),
-                None => write!(writer, "{}", ch),
-            }?;
-        }
-
-        Ok(())
-    }
-
-    fn convert_blockquote<W: Write>(
-        &mut self,
-        event: &Event,
-        writer: &mut W
-    ) -> io::Result<()> {
-        match event {
-            &Event::Enter => {
-                write!(writer, "{}<blockquote>\n", self.make_indent())?;
-                self.indent += 1;
-            },
-
-            &Event::Exit => {
-                self.indent -= 1;
-                write!(writer, "{}</blockquote>\n", self.make_indent())?;
-            },
-        };
-
-        Ok(())
-    }
-
-    fn convert_list<W: Write>(
-        &mut self,
-        ty: &ListType,
-        delim: &DelimType,
-        start: &StartingNumber,
-        tightness: &Tightness,
-        event: &Event,
-        writer: &mut W
-    ) -> io::Result<()> {
-        self.tightness = (*tightness).into();
-
-        match event {
-            &Event::Enter => {
-                match ty {
-                    &ListType::Bullet => write!(writer, "{}<ul>\n", self.make_indent()),
-                    &ListType::Ordered => write!(writer, "{}<ol>\n", self.make_indent()),
-                }?;
-
-                self.indent += 1;
-            },
-
-            &Event::Exit => {
-                self.indent -= 1;
-
-                match ty {
-                    &ListType::Bullet => write!(writer, "{}</ul>\n", self.make_indent()),
-                    &ListType::Ordered => write!(writer, "{}</ol>\n", self.make_indent()),
-                }?;
-            },
-        };
-
-        Ok(())
-    }
-
-    fn convert_item<W: Write>(
-        &mut self,
-        event: &Event,
-        writer: &mut W
-    ) -> io::Result<()> {
-        match event {
-            &Event::Enter => {
-                match self.tightness {
-                    true => write!(writer, "{}<li>\n", self.make_indent()),
-                    false => write!(writer, "{}<li><p>\n", self.make_indent()),
-                }?;
-
-                self.indent += 1;
-            },
-
-            &Event::Exit => {
-                self.indent -= 1;
-                
-                match self.tightness {
-                    true => write!(writer, "{}</li>\n", self.make_indent()),
-                    false => write!(writer, "{}</p></li>\n", self.make_indent()),
-                }?;
-            },
-        };
-
-        Ok(())
-    }
-
-    fn convert_code_block<W: Write>(
-        &self,
-        info: &InfoString,
-        lit: &Literal,
-        writer: &mut W
-    ) -> io::Result<()> {
-        match info.is_empty() {
-            true => {
-                write!(writer, "{}<pre><code>", self.make_indent())?;
-                self.write_literal(lit, writer)?;
-                write!(writer, "</code></pre>\n")?;
-            },
-
-            false => {
-                write!(
-                    writer,
-                    "{}<pre><code class=\"language-{}\">",
-                    self.make_indent(),
-                    info.strip()
-                )?;
-                self.write_literal(lit, writer)?;
-                write!(writer, "</code></pre>\n")?;
-            },
+impl<'a> SimpleData<'a> {
+    pub fn new(source: &'a Path) -> Self {
+        Self {
+            source: source,
         }
-
-        Ok(())
-    }
-
-    fn convert_html_block<W: Write>(
-        &self,
-        lit: &Literal,
-        writer: &mut W
-    ) -> io::Result<()> {
-        write!(writer, "{}", self.make_indent())?;
-        self.write_literal(lit, writer)
-    }
-
-    fn convert_paragraph<W: Write>(
-        &mut self,
-        event: &Event,
-        writer: &mut W
-    ) -> io::Result<()> {
-        match event {
-            &Event::Enter => {
-                write!(writer, "{}<p>", self.make_indent())?;
-                self.indent += 1;
-            },
-
-            &Event::Exit => {
-                self.indent -= 1;
-                write!(writer, "</p>\n")?;
-            },
-        };
-
-        Ok(())
-    }
-
-    fn convert_heading<W: Write>(
-        &mut self,
-        lvl: &HeadingLevel,
-        event: &Event,
-        writer: &mut W
-    ) -> io::Result<()> {
-        match event {
-            &Event::Enter => {
-                match lvl {
-                    &HeadingLevel::One => write!(writer, "{}<h1>", self.make_indent()),
-                    &HeadingLevel::Two => write!(writer, "{}<h2>", self.make_indent()),
-                    &HeadingLevel::Three => write!(writer, "{}<h3>", self.make_indent()),
-                    &HeadingLevel::Four => write!(writer, "{}<h4>", self.make_indent()),
-                    &HeadingLevel::Five => write!(writer, "{}<h5>", self.make_indent()),
-                    &HeadingLevel::Six => write!(writer, "{}<h6>", self.make_indent()),
-                }?;
-
-                self.indent += 1;
-            },
-
-            &Event::Exit => {
-                self.indent -= 1;
-                
-                match lvl {
-                    &HeadingLevel::One => write!(writer, "</h1>\n"),
-                    &HeadingLevel::Two => write!(writer, "</h2>\n"),
-                    &HeadingLevel::Three => write!(writer, "</h3>\n"),
-                    &HeadingLevel::Four => write!(writer, "</h4>\n"),
-                    &HeadingLevel::Five => write!(writer, "</h5>\n"),
-                    &HeadingLevel::Six => write!(writer, "</h6>\n"),
-                }?;
-            },
-        };
-
-        Ok(())
-    }
-
-    fn convert_thematic_break<W: Write>(
-        &self,
-        writer: &mut W
-    ) -> io::Result<()> {
-        write!(writer, "{}<hr />\n", self.make_indent())?;
-        Ok(())
-    }
-
-    fn convert_text<W: Write>(
-        &self,
-        lit: &Literal,
-        writer: &mut W
-    ) -> io::Result<()> {
-        self.write_literal(lit, writer)
-    }
-
-    fn convert_soft_break<W: Write>(
-        &self,
-        writer: &mut W
-    ) -> io::Result<()> {
-        write!(writer, "\n")?;
-        Ok(())
-    }
-
-    fn convert_line_break<W: Write>(
-        &self,
-        writer: &mut W
-    ) -> io::Result<()> {
-        write!(writer, "<br />\n")?;
-        Ok(())
-    }
-
-    fn convert_code<W: Write>(
-        &self,
-        lit: &Literal,
-        writer: &mut W
-    ) -> io::Result<()> {
-        write!(writer, "<code>")?;
-        self.write_literal(lit, writer)?;
-        write!(writer, "</code>")
-    }
-
-    fn convert_html_inline<W: Write>(
-        &self,
-        lit: &Literal,
-        writer: &mut W
-    ) -> io::Result<()> {
-        self.write_literal(lit, writer)
     }
+}
 
-    fn convert_emph<W: Write>(
-        &self,
-        event: &Event,
-        writer: &mut W
-    ) -> io::Result<()> {
-        match event {
-            &Event::Enter => write!(writer, "<em>"),
-            &Event::Exit => write!(writer, "</em>"),
-        }?;
+impl<'a> SimpleConverter<'a> {
 
-        Ok(())
+    /// Switches between the default pretty-printed output and the compliant
+    /// output, which matches cmark's own HTML renderer byte for byte.
+    pub fn set_compliant(&mut self, compliant: bool) {
+        self.html.set_compliant(compliant);
     }
 
-    fn convert_strong<W: Write>(
-        &self,
-        event: &Event,
-        writer: &mut W
-    ) -> io::Result<()> {
-        match event {
-            &Event::Enter => write!(writer, "<strong>"),
-            &Event::Exit => write!(writer, "</strong>"),
-        }?;
-
-        Ok(())
+    /// Replaces the four spaces indenting blocks one level.
+    pub fn set_indent_str(&mut self, indent_str: &str) {
+        self.html.set_indent_str(indent_str);
     }
 
-    fn convert_link<W: Write>(
-        &self,
-        url: &Url,
-        title: &Title,
-        event: &Event,
-        writer: &mut W
-    ) -> io::Result<()> {
-        match event {
-            &Event::Enter => match title.is_empty() {
-                true => write!(writer, "<a href=\"{}\">", url),
-                false => write!(writer, "<a href=\"{}\" title=\"{}\">", url, title),
-            },
-
-            &Event::Exit => write!(writer, "</a>"),
-        }?;
-
-        Ok(())
-    }
-
-    fn convert_image<W: Write>(
-        &self,
-        url: &Url,
-        title: &Title,
-        event: &Event,
-        writer: &mut W
-    ) -> io::Result<()> {
-        match event {
-            &Event::Enter => match title.is_empty() {
-                true => write!(writer, "<img src=\"{}\" alt=\"", url),
-                false => write!(writer, "<img src=\"{}\" title=\"{}\" alt=\"", url, title),
-            },
-
-            &Event::Exit => write!(writer, "\" />"),
-        }?;
-
-        Ok(())
+    /// Replaces the visitor deciding how each node is rendered. Defaults to
+    /// `DefaultVisitor`.
+    #[allow(dead_code)]
+    pub fn set_visitor(&mut self, visitor: Box<dyn Visitor>) {
+        self.visitor = visitor;
     }
 
 }
 
-impl Converter for SimpleConverter {
+impl<'a> Converter for SimpleConverter<'a> {
 
-    type MoreData = (); 
+    type MoreData = SimpleData<'a>;
 
-    fn new() -> Self {
+    fn new(options: Options) -> Self {
         Self {
-            indent: 0,
-            tightness: false,
+            html: HtmlRenderer::new(options),
+            visitor: Box::new(DefaultVisitor),
+            phantom: PhantomData,
         }
     }
 
-    fn convert<R: Read, W: Write>(
+    fn options(&self) -> Options {
+        self.html.options()
+    }
+
+    fn render<W: Write>(
         &mut self,
-        reader: &mut R,
+        document: &Document,
         writer: &mut W,
-        _data: Self::MoreData
-    ) -> io::Result<()> {
-        let mut read_buffer = String::new();
-        reader.read_to_string(&mut read_buffer).unwrap();
-
-        let iter = Iter::from_parser({
-            let mut parser = Parser::new(Options::DEFAULT);
-            parser.feed(read_buffer.as_str(), read_buffer.len())
-                .expect("feeding failed");
-            parser
-        });
-
-        for (node, event) in iter {
-            match node {
-                Node::Block(Block::Document) => Ok(()),
-
-                Node::Block(Block::Blockquote) =>
-                    self.convert_blockquote(&event, writer),
-
-                Node::Block(Block::List(ty, delim, start, tightness)) =>
-                    self.convert_list(
-                        &ty,
-                        &delim,
-                        &start,
-                        &tightness,
-                        &event,
-                        writer
-                    ),
-
-                Node::Block(Block::Item) =>
-                    self.convert_item(&event, writer),
-
-                Node::Block(Block::CodeBlock(info, lit)) =>
-                    self.convert_code_block(&info, &lit, writer),
-
-                Node::Block(Block::HtmlBlock(lit)) =>
-                    self.convert_html_block(&lit, writer),
-
-                Node::Block(Block::CustomBlock) => {
-                    println!("custom blocks not implemented yet");
-                    Ok(())
-                },
-
-                Node::Block(Block::Paragraph) =>
-                    self.convert_paragraph(&event, writer),
-
-                Node::Block(Block::Heading(lvl)) =>
-                    self.convert_heading(&lvl, &event, writer),
-
-                Node::Block(Block::ThematicBreak) =>
-                    self.convert_thematic_break(writer),
-
-                Node::Inline(Inline::Text(lit)) =>
-                    self.convert_text(&lit, writer),
-
-                Node::Inline(Inline::SoftBreak) =>
-                    self.convert_soft_break(writer),
-
-                Node::Inline(Inline::LineBreak) =>
-                    self.convert_line_break(writer),
-
-                Node::Inline(Inline::Code(lit)) =>
-                    self.convert_code(&lit, writer),
-
-                Node::Inline(Inline::HtmlInline(lit)) =>
-                    self.convert_html_inline(&lit, writer),
-
-                Node::Inline(Inline::CustomInline) => {
-                    println!("custom inlines not implemented yet");
-                    Ok(())
-                },
-
-                Node::Inline(Inline::Emph) =>
-                    self.convert_emph(&event, writer),
-
-                Node::Inline(Inline::Strong) =>
-                    self.convert_strong(&event, writer),
-
-                Node::Inline(Inline::Link(url, title)) =>
-                    self.convert_link(&url, &title, &event, writer),
-
-                Node::Inline(Inline::Image(url, title)) =>
-                    self.convert_image(&url, &title, &event, writer),
-            }?;
-        }
-
-        Ok(())
+        data: Self::MoreData
+    ) -> Result<(), Error> {
+        self.html.render(document, &mut *self.visitor, writer, data.source)
     }
 
 }
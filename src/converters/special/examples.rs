@@ -0,0 +1,234 @@
+/*
+ *  Numbered examples
+ *
+ *      ```examples
+ *      (@harmony) Ev-ler-de.
+ *      (@agree) Subject agreement:
+ *      a. (@agree-ok) The cat sleeps.
+ *      b. (@agree-bad) *The cat sleep.
+ *      c. ?The cats sleeps.
+ *      d. #The cat sleeps the dog.
+ *      ```
+ *
+ *  A line starting with a letter and a period or parenthesis is a sub-example
+ *  of the example before it, and any other line starts a new example. Sub-
+ *  examples are lettered in order, whatever letter they are written with. A
+ *  line starting with whitespace continues the one before it. An example with
+ *  sub-examples shows its own text above them, if it has any.
+ *
+ *  Examples and sub-examples may start with a label `(@name)`, and then with
+ *  a judgement made of `*`, `?` and `#`, which is set in a column of its own.
+ *  Anywhere in the post, `(@name)` then stands for the number of the example,
+ *  e.g. (2b).
+ */
+
+use converters::escape::escape_html;
+use converters::special::Lines;
+
+/// Characters a judgement is made of.
+const JUDGEMENTS: &str = "*?#";
+
+/// An example or a sub-example.
+#[derive(Debug, Clone, PartialEq)]
+struct Item {
+    label: Option<String>,
+    judgement: String,
+    text: String,
+}
+
+/// A numbered example, with its sub-examples if it has any.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Example {
+    main: Item,
+    subs: Vec<Item>,
+}
+
+impl Item {
+    fn new(text: &str) -> Self {
+        let (label, rest) = split_label(text.trim());
+        let rest = rest.trim_start();
+        let end = rest.find(|ch| !JUDGEMENTS.contains(ch)).unwrap_or(rest.len());
+
+        Self {
+            label: label,
+            judgement: rest[.. end].to_owned(),
+            text: rest[end ..].trim_start().to_owned(),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.judgement.is_empty() && self.text.is_empty()
+    }
+
+    fn to_html(&self) -> String {
+        format!(
+            "<span class=\"judgement\">{}</span><span class=\"example-text\">{}</span>",
+            escape_html(&self.judgement),
+            escape_html(&self.text)
+        )
+    }
+}
+
+impl Example {
+
+    /// The labels of the example and its sub-examples, along with what they
+    /// stand for when the example is number `number`.
+    pub fn labels(&self, number: usize) -> Vec<(String, String)> {
+        let mut res: Vec<(String, String)> = Vec::new();
+
+        if let Some(ref label) = self.main.label {
+            res.push((label.clone(), number.to_string()));
+        }
+
+        for (idx, sub) in self.subs.iter().enumerate() {
+            if let Some(ref label) = sub.label {
+                res.push((label.clone(), format!("{}{}", number, letter(idx))));
+            }
+        }
+
+        res
+    }
+
+    /// Lays out the example as number `number`, with the judgements in a
+    /// column before the text.
+    pub fn to_lines(&self, number: usize) -> Lines {
+        let mut res = Lines::new();
+
+        res.push(0, format!("<div class=\"example\" id=\"ex-{}\">", number));
+        res.push(1, format!("<span class=\"example-number\">({})</span>", number));
+        res.push(1, "<div class=\"example-body\">");
+
+        match self.subs.is_empty() {
+            true => res.push(2, format!("<p class=\"example-item\">{}</p>", self.main.to_html())),
+
+            false => {
+                if !self.main.is_empty() {
+                    res.push(2, format!("<p class=\"example-lead\">{}</p>", self.main.to_html()));
+                }
+
+                res.push(2, "<ol class=\"example-subs\">");
+                for (idx, sub) in self.subs.iter().enumerate() {
+                    res.push(3, format!(
+                        "<li class=\"example-item\" id=\"ex-{}{}\"><span class=\"example-letter\">{}.</span>{}</li>",
+                        number,
+                        letter(idx),
+                        letter(idx),
+                        sub.to_html()
+                    ));
+                }
+                res.push(2, "</ol>");
+            },
+        };
+
+        res.push(1, "</div>");
+        res.push(0, "</div>");
+
+        res
+    }
+
+}
+
+/// Splits a block into its examples. A block without any is an empty list.
+pub fn parse(text: &str) -> Vec<Example> {
+    //  the lines of each example, its own first
+    let mut examples: Vec<Vec<String>> = Vec::new();
+
+    for line in text.lines().filter(|line| !line.trim().is_empty()) {
+        match (line.starts_with(char::is_whitespace), sub_example(line)) {
+            (true, _) if !examples.is_empty() => {
+                let last = examples.last_mut().and_then(|lines| lines.last_mut()).unwrap();
+                last.push(' ');
+                last.push_str(line.trim());
+            },
+
+            (_, Some(rest)) => {
+                if examples.is_empty() {
+                    examples.push(vec![String::new()]);
+                }
+                examples.last_mut().unwrap().push(rest.to_owned());
+            },
+
+            _ => examples.push(vec![line.to_owned()]),
+        };
+    }
+
+    examples.into_iter()
+        .map(|lines| {
+            let mut items = lines.iter().map(|line| Item::new(line));
+            Example {
+                main: items.next().unwrap(),
+                subs: items.collect(),
+            }
+        })
+        .collect()
+}
+
+/// Takes a label `(@name)` off the start of `text`, returning it along with
+/// the rest of `text`.
+pub fn split_label(text: &str) -> (Option<String>, &str) {
+    let end = match text.starts_with("(@") {
+        true => text.find(')'),
+        false => None,
+    };
+
+    match end {
+        Some(end) if is_label(&text[2 .. end]) => (Some(text[2 .. end].to_owned()), &text[end + 1 ..]),
+        _ => (None, text),
+    }
+}
+
+fn is_label(label: &str) -> bool {
+    !label.is_empty() && label.chars().all(|ch| ch.is_alphanumeric() || "-_:".contains(ch))
+}
+
+/// The rest of a line starting a sub-example, e.g. `b. *The cat sleep.`
+fn sub_example(line: &str) -> Option<&str> {
+    let mut chars = line.chars();
+
+    match (chars.next(), chars.next(), chars.next()) {
+        (Some(letter), Some(delim), Some(space))
+            if letter.is_ascii_lowercase() && (delim == '.' || delim == ')') && space.is_whitespace() =>
+                Some(&line[3 ..]),
+
+        _ => None,
+    }
+}
+
+fn letter(idx: usize) -> char {
+    (b'a' + (idx % 26) as u8) as char
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sub_examples_and_judgements() {
+        let examples = parse("
+(@agree) Subject agreement:
+c. (@ok) The cat
+   sleeps.
+a. *The cat sleep.
+(@other) ?? Other.
+");
+
+        assert_eq!(examples.len(), 2);
+        assert_eq!(examples[0].main.text, "Subject agreement:");
+        assert_eq!(examples[0].subs[0].text, "The cat sleeps.");
+        assert_eq!(examples[0].subs[1].judgement, "*");
+        assert_eq!(examples[1].main.judgement, "??");
+        assert_eq!(examples[1].main.text, "Other.");
+
+        assert_eq!(examples[0].labels(3), vec![
+            ("agree".to_owned(), "3".to_owned()),
+            ("ok".to_owned(), "3a".to_owned()),
+        ]);
+    }
+
+    #[test]
+    fn labels() {
+        assert_eq!(split_label("(@a-1) b"), (Some("a-1".to_owned()), " b"));
+        assert_eq!(split_label("(@a b) c"), (None, "(@a b) c"));
+        assert_eq!(split_label("(see 1)"), (None, "(see 1)"));
+    }
+}
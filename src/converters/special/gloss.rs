@@ -0,0 +1,339 @@
+/*
+ *  Interlinear glossed text
+ *
+ *      ```gloss
+ *      Gila abur-u-n ferma hamišaluǧ güǧüna amuq’-da-č.
+ *      gila abur-u-n ferma hamišaluǧ güǧüna amuq’-da-č
+ *      now they-OBL-GEN farm forever behind stay-FUT-NEG
+ *      'Now their farm will not stay behind forever.'
+ *      ```
+ *
+ *  The last line is the free translation if it starts with a quote. All other
+ *  lines are split into words and lined up word by word, the last of them
+ *  being the gloss. If the first line has a different number of words than
+ *  the next, it is the source line instead, shown as is above the aligned
+ *  ones. Braces keep words together, e.g. `{in front of}`, and `{}` is an
+ *  empty word.
+ *
+ *  A first line holding nothing but a label `(@name)` labels the example, see
+ *  `examples`.
+ *
+ *  In the gloss, abbreviations in capitals are set in small caps, with the
+ *  meaning of those from the Leipzig Glossing Rules as a tooltip.
+ */
+
+use converters::escape::escape_html;
+use converters::special::Lines;
+use converters::special::examples::split_label;
+
+use std::mem;
+
+/// The standard abbreviations of the Leipzig Glossing Rules.
+const ABBREVIATIONS: &[(&str, &str)] = &[
+    ("A", "agent-like argument of canonical transitive verb"),
+    ("ABL", "ablative"),
+    ("ABS", "absolutive"),
+    ("ACC", "accusative"),
+    ("ADJ", "adjective"),
+    ("ADV", "adverb(ial)"),
+    ("AGR", "agreement"),
+    ("ALL", "allative"),
+    ("ANTIP", "antipassive"),
+    ("APPL", "applicative"),
+    ("ART", "article"),
+    ("AUX", "auxiliary"),
+    ("BEN", "benefactive"),
+    ("CAUS", "causative"),
+    ("CLF", "classifier"),
+    ("COM", "comitative"),
+    ("COMP", "complementizer"),
+    ("COMPL", "completive"),
+    ("COND", "conditional"),
+    ("COP", "copula"),
+    ("CVB", "converb"),
+    ("DAT", "dative"),
+    ("DECL", "declarative"),
+    ("DEF", "definite"),
+    ("DEM", "demonstrative"),
+    ("DET", "determiner"),
+    ("DIST", "distal"),
+    ("DISTR", "distributive"),
+    ("DU", "dual"),
+    ("DUR", "durative"),
+    ("ERG", "ergative"),
+    ("EXCL", "exclusive"),
+    ("F", "feminine"),
+    ("FOC", "focus"),
+    ("FUT", "future"),
+    ("GEN", "genitive"),
+    ("IMP", "imperative"),
+    ("INCL", "inclusive"),
+    ("IND", "indicative"),
+    ("INDF", "indefinite"),
+    ("INF", "infinitive"),
+    ("INS", "instrumental"),
+    ("INTR", "intransitive"),
+    ("IPFV", "imperfective"),
+    ("IRR", "irrealis"),
+    ("LOC", "locative"),
+    ("M", "masculine"),
+    ("N", "neuter"),
+    ("NEG", "negation, negative"),
+    ("NMLZ", "nominalizer/nominalization"),
+    ("NOM", "nominative"),
+    ("OBJ", "object"),
+    ("OBL", "oblique"),
+    ("P", "patient-like argument of canonical transitive verb"),
+    ("PASS", "passive"),
+    ("PFV", "perfective"),
+    ("PL", "plural"),
+    ("POSS", "possessive"),
+    ("PRED", "predicative"),
+    ("PRF", "perfect"),
+    ("PRS", "present"),
+    ("PROG", "progressive"),
+    ("PROH", "prohibitive"),
+    ("PROX", "proximal/proximate"),
+    ("PST", "past"),
+    ("PTCP", "participle"),
+    ("PURP", "purposive"),
+    ("Q", "question particle/marker"),
+    ("QUOT", "quotative"),
+    ("RECP", "reciprocal"),
+    ("REFL", "reflexive"),
+    ("REL", "relative"),
+    ("RES", "resultative"),
+    ("S", "single argument of canonical intransitive verb"),
+    ("SBJ", "subject"),
+    ("SBJV", "subjunctive"),
+    ("SG", "singular"),
+    ("TOP", "topic"),
+    ("TR", "transitive"),
+    ("VOC", "vocative"),
+];
+
+/// A glossed example, with its lines split into words.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Gloss {
+    label: Option<String>,
+    source: Option<String>,
+    aligned: Vec<Vec<String>>,
+    translation: Option<String>,
+}
+
+impl Gloss {
+
+    /// Makes out the lines of a gloss. Returns `None` unless there are at
+    /// least two aligned lines, all with the same number of words.
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut lines: Vec<&str> = text.lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .collect();
+
+        let label = match lines.first().map(|line| split_label(line)) {
+            Some((Some(label), ref rest)) if rest.trim().is_empty() => {
+                lines.remove(0);
+                Some(label)
+            },
+            _ => None,
+        };
+
+        let translation = match lines.last() {
+            Some(line) if line.starts_with(|ch| "'\"‘“".contains(ch)) =>
+                lines.pop().map(str::to_owned),
+            _ => None,
+        };
+
+        let mut aligned: Vec<Vec<String>> = lines.iter().map(|line| words(line)).collect();
+
+        let source = match aligned.len() >= 3 && aligned[0].len() != aligned[1].len() {
+            true => {
+                aligned.remove(0);
+                Some(lines[0].to_owned())
+            },
+            false => None,
+        };
+
+        let len = aligned.first()?.len();
+        match aligned.len() >= 2 && aligned.iter().all(|words| words.len() == len) {
+            true => Some(Self {
+                label: label,
+                source: source,
+                aligned: aligned,
+                translation: translation,
+            }),
+            false => None,
+        }
+    }
+
+    pub fn label(&self) -> Option<&str> {
+        self.label.as_ref().map(String::as_str)
+    }
+
+    /// Lays out the gloss as example number `number`, one column per word.
+    pub fn to_lines(&self, number: usize) -> Lines {
+        let mut res = Lines::new();
+
+        res.push(0, format!("<div class=\"example gloss\" id=\"ex-{}\">", number));
+        res.push(1, format!("<span class=\"example-number\">({})</span>", number));
+        res.push(1, "<div class=\"example-body\">");
+
+        if let Some(ref source) = self.source {
+            res.push(2, format!("<p class=\"gloss-source\">{}</p>", escape_html(source)));
+        }
+
+        res.push(2, "<div class=\"gloss-words\">");
+        for idx in 0 .. self.aligned[0].len() {
+            res.push(3, "<div class=\"gloss-word\">");
+
+            let last = self.aligned.len() - 1;
+            for (line, words) in self.aligned.iter().enumerate() {
+                match line == last {
+                    true => res.push(4, format!(
+                        "<span class=\"gloss-line gloss-gloss\">{}</span>",
+                        gloss_html(&words[idx])
+                    )),
+                    false => res.push(4, format!(
+                        "<span class=\"gloss-line\">{}</span>",
+                        escape_html(&words[idx])
+                    )),
+                };
+            }
+
+            res.push(3, "</div>");
+        }
+        res.push(2, "</div>");
+
+        if let Some(ref translation) = self.translation {
+            res.push(2, format!("<p class=\"gloss-translation\">{}</p>", escape_html(translation)));
+        }
+
+        res.push(1, "</div>");
+        res.push(0, "</div>");
+
+        res
+    }
+
+}
+
+/// Splits a line into words at whitespace, except within braces.
+fn words(line: &str) -> Vec<String> {
+    let mut res: Vec<String> = Vec::new();
+    let mut word = String::new();
+    let mut braced = false;
+    let mut depth = 0;
+
+    for ch in line.chars() {
+        match ch {
+            '{' => {
+                depth += 1;
+                braced = true;
+            },
+
+            '}' if depth > 0 => depth -= 1,
+
+            _ if ch.is_whitespace() && depth == 0 => {
+                if !word.is_empty() || braced {
+                    res.push(mem::replace(&mut word, String::new()));
+                }
+                braced = false;
+            },
+
+            _ => word.push(ch),
+        };
+    }
+
+    if !word.is_empty() || braced {
+        res.push(word);
+    }
+
+    res
+}
+
+/// HTML for a word of the gloss line. Between the separators `-`, `=`, `.`
+/// and such, a morpheme in capitals, possibly after a person number as in
+/// `3SG`, is an abbreviation.
+fn gloss_html(word: &str) -> String {
+    let mut res = String::new();
+    let mut rest = word;
+
+    while !rest.is_empty() {
+        let end = rest.find(|ch: char| !ch.is_alphanumeric()).unwrap_or(rest.len());
+        let (morpheme, after) = rest.split_at(end);
+        res.push_str(&morpheme_html(morpheme));
+
+        let sep_len = after.chars().next().map(char::len_utf8).unwrap_or(0);
+        res.push_str(&escape_html(&after[.. sep_len]));
+        rest = &after[sep_len ..];
+    }
+
+    res
+}
+
+fn morpheme_html(morpheme: &str) -> String {
+    let digits = morpheme.find(|ch: char| !ch.is_ascii_digit()).unwrap_or(morpheme.len());
+    let (person, abbr) = morpheme.split_at(digits);
+
+    let meaning = ABBREVIATIONS.iter()
+        .find(|&&(known, _)| known == abbr)
+        .map(|&(_, meaning)| meaning);
+
+    let is_abbr = !abbr.is_empty()
+        && abbr.chars().all(char::is_uppercase)
+        && (abbr.chars().count() >= 2 || meaning.is_some());
+
+    match (is_abbr, meaning) {
+        (true, Some(meaning)) => format!(
+            "{}<abbr class=\"gloss-abbr\" title=\"{}\">{}</abbr>",
+            person,
+            meaning,
+            abbr.to_lowercase()
+        ),
+
+        (true, None) => format!(
+            "{}<abbr class=\"gloss-abbr\">{}</abbr>",
+            person,
+            abbr.to_lowercase()
+        ),
+
+        (false, _) => escape_html(morpheme),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn source_line_and_translation() {
+        let gloss = Gloss::parse("
+            (@farm)
+            Ferma hamišaluǧ amuq’-da-č.
+            ferma {stay behind}-FUT-NEG
+            farm stay-FUT-NEG
+            'The farm will not stay behind.'
+        ").unwrap();
+
+        assert_eq!(gloss.label(), Some("farm"));
+        assert_eq!(gloss.source, Some("Ferma hamišaluǧ amuq’-da-č.".to_owned()));
+        assert_eq!(gloss.aligned[0], vec!["ferma".to_owned(), "stay behind-FUT-NEG".to_owned()]);
+        assert_eq!(gloss.translation, Some("'The farm will not stay behind.'".to_owned()));
+    }
+
+    #[test]
+    fn lines_must_line_up() {
+        assert_eq!(Gloss::parse("a b c\nx y\n"), None);
+        assert_eq!(Gloss::parse("a b\n"), None);
+    }
+
+    #[test]
+    fn abbreviations() {
+        assert_eq!(
+            gloss_html("Mary-3SG.NOM"),
+            "Mary-3<abbr class=\"gloss-abbr\" title=\"singular\">sg</abbr>.\
+<abbr class=\"gloss-abbr\" title=\"nominative\">nom</abbr>"
+        );
+        assert_eq!(gloss_html("I-XYZ"), "I-<abbr class=\"gloss-abbr\">xyz</abbr>");
+    }
+}
@@ -0,0 +1,208 @@
+/*
+ *  X-SAMPA
+ *
+ *  IPA is hard to type, so it can be written in X-SAMPA instead, in a code
+ *  span starting with `ipa:` between single backticks:
+ *
+ *      The plural is `ipa:/ev-lEr/`, not `ipa:/ev-lar/`.
+ *
+ *  Anything that is not X-SAMPA, such as the slashes, is kept as is, and so
+ *  is IPA typed directly. The IPA is put in a span of class `ipa`, for the
+ *  theme to give it a font that has all the symbols.
+ */
+
+use converters::escape::escape_html;
+
+/// Marks a code span as X-SAMPA.
+pub const PREFIX: &str = "ipa:";
+
+/// X-SAMPA and the IPA it stands for. Diacritics become combining characters.
+const X_SAMPA: &[(&str, &str)] = &[
+    ("b_<", "ɓ"),
+    ("d`", "ɖ"),
+    ("d_<", "ɗ"),
+    ("g", "ɡ"),
+    ("g_<", "ɠ"),
+    ("h\\", "ɦ"),
+    ("j\\", "ʝ"),
+    ("l`", "ɭ"),
+    ("l\\", "ɺ"),
+    ("n`", "ɳ"),
+    ("p\\", "ɸ"),
+    ("r`", "ɽ"),
+    ("r\\", "ɹ"),
+    ("r\\`", "ɻ"),
+    ("s`", "ʂ"),
+    ("s\\", "ɕ"),
+    ("t`", "ʈ"),
+    ("v\\", "ʋ"),
+    ("x\\", "ɧ"),
+    ("z`", "ʐ"),
+    ("z\\", "ʑ"),
+
+    ("A", "ɑ"),
+    ("B", "β"),
+    ("B\\", "ʙ"),
+    ("C", "ç"),
+    ("D", "ð"),
+    ("E", "ɛ"),
+    ("F", "ɱ"),
+    ("G", "ɣ"),
+    ("G\\", "ɢ"),
+    ("G\\_<", "ʛ"),
+    ("H", "ɥ"),
+    ("H\\", "ʜ"),
+    ("I", "ɪ"),
+    ("I\\", "ᵻ"),
+    ("J", "ɲ"),
+    ("J\\", "ɟ"),
+    ("J\\_<", "ʄ"),
+    ("K", "ɬ"),
+    ("K\\", "ɮ"),
+    ("L", "ʎ"),
+    ("L\\", "ʟ"),
+    ("M", "ɯ"),
+    ("M\\", "ɰ"),
+    ("N", "ŋ"),
+    ("N\\", "ɴ"),
+    ("O", "ɔ"),
+    ("O\\", "ʘ"),
+    ("P", "ʋ"),
+    ("Q", "ɒ"),
+    ("R", "ʁ"),
+    ("R\\", "ʀ"),
+    ("S", "ʃ"),
+    ("T", "θ"),
+    ("U", "ʊ"),
+    ("U\\", "ᵿ"),
+    ("V", "ʌ"),
+    ("W", "ʍ"),
+    ("X", "χ"),
+    ("X\\", "ħ"),
+    ("Y", "ʏ"),
+    ("Z", "ʒ"),
+
+    ("\"", "ˈ"),
+    ("%", "ˌ"),
+    ("'", "ʲ"),
+    (":", "ː"),
+    (":\\", "ˑ"),
+    ("@", "ə"),
+    ("@\\", "ɘ"),
+    ("@`", "ɚ"),
+    ("{", "æ"),
+    ("}", "ʉ"),
+    ("1", "ɨ"),
+    ("2", "ø"),
+    ("3", "ɜ"),
+    ("3\\", "ɞ"),
+    ("4", "ɾ"),
+    ("5", "ɫ"),
+    ("6", "ɐ"),
+    ("7", "ɤ"),
+    ("8", "ɵ"),
+    ("9", "œ"),
+    ("&", "ɶ"),
+    ("?", "ʔ"),
+    ("?\\", "ʕ"),
+    ("<\\", "ʢ"),
+    (">\\", "ʡ"),
+    ("^", "ꜛ"),
+    ("!", "ꜜ"),
+    ("!\\", "ǃ"),
+    ("|\\", "ǀ"),
+    ("||", "‖"),
+    ("|\\|\\", "ǁ"),
+    ("=\\", "ǂ"),
+    ("-\\", "‿"),
+    ("`", "˞"),
+    ("~", "\u{303}"),
+    ("=", "\u{329}"),
+
+    ("_\"", "\u{308}"),
+    ("_+", "\u{31f}"),
+    ("_-", "\u{320}"),
+    ("_/", "\u{30c}"),
+    ("_0", "\u{325}"),
+    ("_=", "\u{329}"),
+    ("_>", "ʼ"),
+    ("_?\\", "ˤ"),
+    ("_\\", "\u{302}"),
+    ("_^", "\u{32f}"),
+    ("_}", "\u{31a}"),
+    ("_~", "\u{303}"),
+    ("_A", "\u{318}"),
+    ("_a", "\u{33a}"),
+    ("_B", "\u{30f}"),
+    ("_c", "\u{31c}"),
+    ("_d", "\u{32a}"),
+    ("_e", "\u{334}"),
+    ("_F", "\u{302}"),
+    ("_G", "ˠ"),
+    ("_H", "\u{301}"),
+    ("_h", "ʰ"),
+    ("_j", "ʲ"),
+    ("_k", "\u{330}"),
+    ("_L", "\u{300}"),
+    ("_l", "ˡ"),
+    ("_M", "\u{304}"),
+    ("_m", "\u{33b}"),
+    ("_N", "\u{33c}"),
+    ("_n", "ⁿ"),
+    ("_O", "\u{339}"),
+    ("_o", "\u{31e}"),
+    ("_q", "\u{319}"),
+    ("_R", "\u{30c}"),
+    ("_r", "\u{31d}"),
+    ("_T", "\u{30b}"),
+    ("_t", "\u{324}"),
+    ("_v", "\u{32c}"),
+    ("_w", "ʷ"),
+    ("_X", "\u{306}"),
+    ("_x", "\u{33d}"),
+];
+
+/// Converts X-SAMPA to IPA, taking the longest symbol at each point.
+pub fn to_ipa(text: &str) -> String {
+    let mut res = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(ch) = rest.chars().next() {
+        let symbol = X_SAMPA.iter()
+            .filter(|&&(x_sampa, _)| rest.starts_with(x_sampa))
+            .max_by_key(|&&(x_sampa, _)| x_sampa.len());
+
+        rest = match symbol {
+            Some(&(x_sampa, ipa)) => {
+                res.push_str(ipa);
+                &rest[x_sampa.len() ..]
+            },
+
+            None => {
+                res.push(ch);
+                &rest[ch.len_utf8() ..]
+            },
+        };
+    }
+
+    res
+}
+
+/// HTML for X-SAMPA, converted and put in its span.
+pub fn ipa_html(text: &str) -> String {
+    format!("<span class=\"ipa\">{}</span>", escape_html(&to_ipa(text)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn x_sampa() {
+        assert_eq!(to_ipa("/ev-lEr/"), "/ev-lɛr/");
+        assert_eq!(to_ipa("[\"t_hEst]"), "[ˈtʰɛst]");
+        assert_eq!(to_ipa("r\\`i:"), "ɻiː");
+        assert_eq!(to_ipa("n_0 ʃ"), "n\u{325} ʃ");
+        assert_eq!(ipa_html("{&"), "<span class=\"ipa\">æɶ</span>");
+    }
+}
@@ -0,0 +1,783 @@
+/*
+ *  Math
+ *
+ *  TeX between `$` signs is inline math, and between `$$` display math:
+ *
+ *      The roots of $ax^2 + bx + c$ are
+ *
+ *      $$
+ *      x_{1,2} = \frac{-b \pm \sqrt{b^2 - 4ac}}{2a}
+ *      $$
+ *
+ *  As in pandoc, the opening `$` of inline math must be followed by a non-space
+ *  character, and the closing one must follow a non-space character and not
+ *  be followed by a digit, so that prices like $5 and $10 are left alone. Math
+ *  cannot span blank lines, inline math cannot hold backticks, and `\$` is a
+ *  dollar sign.
+ *
+ *  cmark knows nothing of math, and would take backslashes and underscores in
+ *  it for escapes and emphasis. So the math is found before parsing, and ends
+ *  up in code nodes of class `math` or `math math-display`, see `spans`.
+ *
+ *  The TeX is turned into MathML as the post is built. It covers the usual
+ *  symbols, scripts, fractions, roots, accents, delimiters, fonts and matrix
+ *  environments; anything else is shown as the TeX itself, in a span of class
+ *  `math`.
+ */
+
+use converters::escape::escape_html;
+
+use std::fmt;
+
+const GREEK: &[(&str, char)] = &[
+    ("alpha", 'α'), ("beta", 'β'), ("gamma", 'γ'), ("delta", 'δ'),
+    ("epsilon", 'ϵ'), ("varepsilon", 'ε'), ("zeta", 'ζ'), ("eta", 'η'),
+    ("theta", 'θ'), ("vartheta", 'ϑ'), ("iota", 'ι'), ("kappa", 'κ'),
+    ("lambda", 'λ'), ("mu", 'μ'), ("nu", 'ν'), ("xi", 'ξ'), ("omicron", 'ο'),
+    ("pi", 'π'), ("varpi", 'ϖ'), ("rho", 'ρ'), ("varrho", 'ϱ'),
+    ("sigma", 'σ'), ("varsigma", 'ς'), ("tau", 'τ'), ("upsilon", 'υ'),
+    ("phi", 'ϕ'), ("varphi", 'φ'), ("chi", 'χ'), ("psi", 'ψ'), ("omega", 'ω'),
+    ("Gamma", 'Γ'), ("Delta", 'Δ'), ("Theta", 'Θ'), ("Lambda", 'Λ'),
+    ("Xi", 'Ξ'), ("Pi", 'Π'), ("Sigma", 'Σ'), ("Upsilon", 'Υ'), ("Phi", 'Φ'),
+    ("Psi", 'Ψ'), ("Omega", 'Ω'),
+];
+
+/// Commands for symbols that are identifiers rather than operators.
+const IDENTIFIERS: &[(&str, &str)] = &[
+    ("infty", "∞"), ("partial", "∂"), ("nabla", "∇"), ("emptyset", "∅"),
+    ("varnothing", "∅"), ("ell", "ℓ"), ("hbar", "ℏ"), ("aleph", "ℵ"),
+    ("Re", "ℜ"), ("Im", "ℑ"), ("wp", "℘"), ("top", "⊤"), ("bot", "⊥"),
+    ("angle", "∠"), ("triangle", "△"), ("%", "%"), ("$", "$"), ("#", "#"),
+    ("&", "&"), ("_", "_"),
+];
+
+const OPERATORS: &[(&str, &str)] = &[
+    ("pm", "±"), ("mp", "∓"), ("times", "×"), ("div", "÷"), ("cdot", "⋅"),
+    ("ast", "∗"), ("star", "⋆"), ("circ", "∘"), ("bullet", "∙"),
+    ("oplus", "⊕"), ("ominus", "⊖"), ("otimes", "⊗"), ("odot", "⊙"),
+    ("cap", "∩"), ("cup", "∪"), ("setminus", "∖"), ("wedge", "∧"),
+    ("land", "∧"), ("vee", "∨"), ("lor", "∨"), ("neg", "¬"), ("lnot", "¬"),
+    ("leq", "≤"), ("le", "≤"), ("geq", "≥"), ("ge", "≥"), ("neq", "≠"),
+    ("ne", "≠"), ("ll", "≪"), ("gg", "≫"), ("approx", "≈"), ("equiv", "≡"),
+    ("sim", "∼"), ("simeq", "≃"), ("cong", "≅"), ("propto", "∝"),
+    ("prec", "≺"), ("succ", "≻"), ("in", "∈"), ("notin", "∉"), ("ni", "∋"),
+    ("subset", "⊂"), ("supset", "⊃"), ("subseteq", "⊆"), ("supseteq", "⊇"),
+    ("to", "→"), ("rightarrow", "→"), ("leftarrow", "←"), ("gets", "←"),
+    ("leftrightarrow", "↔"), ("Rightarrow", "⇒"), ("Leftarrow", "⇐"),
+    ("Leftrightarrow", "⇔"), ("implies", "⟹"), ("impliedby", "⟸"),
+    ("iff", "⟺"), ("mapsto", "↦"), ("longrightarrow", "⟶"),
+    ("longleftarrow", "⟵"), ("uparrow", "↑"), ("downarrow", "↓"),
+    ("forall", "∀"), ("exists", "∃"), ("nexists", "∄"), ("mid", "∣"),
+    ("parallel", "∥"), ("perp", "⊥"), ("vdash", "⊢"), ("models", "⊨"),
+    ("ldots", "…"), ("dots", "…"), ("cdots", "⋯"), ("vdots", "⋮"),
+    ("ddots", "⋱"), ("colon", ":"), ("prime", "′"),
+    ("langle", "⟨"), ("rangle", "⟩"), ("lfloor", "⌊"), ("rfloor", "⌋"),
+    ("lceil", "⌈"), ("rceil", "⌉"), ("vert", "|"), ("lvert", "|"),
+    ("rvert", "|"), ("Vert", "‖"), ("lVert", "‖"), ("rVert", "‖"),
+    ("|", "‖"), ("{", "{"), ("}", "}"),
+];
+
+/// Big operators, and whether their scripts go above and below them in
+/// display math.
+const BIG_OPERATORS: &[(&str, &str, bool)] = &[
+    ("sum", "∑", true), ("prod", "∏", true), ("coprod", "∐", true),
+    ("bigcup", "⋃", true), ("bigcap", "⋂", true), ("bigoplus", "⨁", true),
+    ("bigotimes", "⨂", true), ("bigvee", "⋁", true), ("bigwedge", "⋀", true),
+    ("int", "∫", false), ("iint", "∬", false), ("iiint", "∭", false),
+    ("oint", "∮", false),
+];
+
+/// Named functions, and whether their scripts go below them in display math.
+const FUNCTIONS: &[(&str, bool)] = &[
+    ("sin", false), ("cos", false), ("tan", false), ("cot", false),
+    ("sec", false), ("csc", false), ("arcsin", false), ("arccos", false),
+    ("arctan", false), ("sinh", false), ("cosh", false), ("tanh", false),
+    ("log", false), ("ln", false), ("lg", false), ("exp", false),
+    ("det", true), ("dim", false), ("ker", false), ("deg", false),
+    ("arg", false), ("hom", false), ("lim", true), ("liminf", true),
+    ("limsup", true), ("max", true), ("min", true), ("sup", true),
+    ("inf", true), ("gcd", true), ("Pr", true),
+];
+
+const SPACES: &[(&str, &str)] = &[
+    (",", "0.1667em"), (":", "0.2222em"), (">", "0.2222em"), (";", "0.2778em"),
+    (" ", "0.25em"), ("enspace", "0.5em"), ("quad", "1em"), ("qquad", "2em"),
+    ("!", "-0.1667em"),
+];
+
+/// Accents, and whether they go below.
+const ACCENTS: &[(&str, &str, bool)] = &[
+    ("hat", "^", false), ("widehat", "^", false), ("check", "ˇ", false),
+    ("tilde", "~", false), ("widetilde", "~", false), ("acute", "´", false),
+    ("grave", "`", false), ("dot", "˙", false), ("ddot", "¨", false),
+    ("breve", "˘", false), ("bar", "¯", false), ("vec", "→", false),
+    ("overline", "‾", false), ("overrightarrow", "→", false),
+    ("underline", "_", true),
+];
+
+/// Sizes of the `\big` commands.
+const BIG_SIZES: &[(&str, &str)] = &[
+    ("big", "1.2em"), ("bigl", "1.2em"), ("bigr", "1.2em"), ("bigm", "1.2em"),
+    ("Big", "1.8em"), ("Bigl", "1.8em"), ("Bigr", "1.8em"), ("Bigm", "1.8em"),
+    ("bigg", "2.4em"), ("biggl", "2.4em"), ("biggr", "2.4em"), ("biggm", "2.4em"),
+    ("Bigg", "3em"), ("Biggl", "3em"), ("Biggr", "3em"), ("Biggm", "3em"),
+];
+
+/// Why some TeX cannot be turned into MathML.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MathErr {
+    UnknownCommand(String),
+    UnknownEnvironment(String),
+
+    /// Braces, `\left` and `\right`, or `\begin` and `\end` do not match.
+    Unbalanced,
+
+    /// A command, `^` or `_` is missing what it applies to.
+    MissingArgument,
+
+    /// Something that is not a delimiter after `\left`, `\right` or `\big`.
+    BadDelimiter,
+
+    /// Two subscripts or two superscripts on the same thing.
+    DoubleScript,
+
+    /// Something out of place, such as `&` outside of a matrix.
+    Unexpected(String),
+}
+
+impl fmt::Display for MathErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &MathErr::UnknownCommand(ref name) => write!(f, "unsupported command \\{}", name),
+            &MathErr::UnknownEnvironment(ref name) => write!(f, "unsupported environment {}", name),
+            &MathErr::Unbalanced => write!(f, "unbalanced braces or delimiters"),
+            &MathErr::MissingArgument => write!(f, "missing argument"),
+            &MathErr::BadDelimiter => write!(f, "not a delimiter"),
+            &MathErr::DoubleScript => write!(f, "double subscript or superscript"),
+            &MathErr::Unexpected(ref what) => write!(f, "unexpected {}", what),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    /// A command without its backslash, e.g. `frac` or `{`.
+    Command(String),
+
+    Open,
+    Close,
+    Sup,
+    Sub,
+    Prime,
+    Align,
+    NewRow,
+    Letter(char),
+    Number(String),
+    Symbol(char),
+    Space,
+}
+
+impl Token {
+    /// The token as text, for `\text` and the like.
+    fn to_text(&self) -> String {
+        match self {
+            &Token::Command(ref name) => match name.chars().all(char::is_alphabetic) {
+                true => format!("\\{}", name),
+                false => name.clone(),
+            },
+            &Token::Open => "{".to_owned(),
+            &Token::Close => "}".to_owned(),
+            &Token::Sup => "^".to_owned(),
+            &Token::Sub => "_".to_owned(),
+            &Token::Prime => "'".to_owned(),
+            &Token::Align => "&".to_owned(),
+            &Token::NewRow => "\\\\".to_owned(),
+            &Token::Letter(ch) | &Token::Symbol(ch) => ch.to_string(),
+            &Token::Number(ref number) => number.clone(),
+            &Token::Space => " ".to_owned(),
+        }
+    }
+}
+
+fn tokens(tex: &str) -> Vec<Token> {
+    let mut res: Vec<Token> = Vec::new();
+    let mut chars = tex.chars().peekable();
+
+    while let Some(ch) = chars.next() {
+        res.push(match ch {
+            '\\' => match chars.next() {
+                Some('\\') => Token::NewRow,
+                Some(ch) if ch.is_ascii_alphabetic() => {
+                    let mut name = ch.to_string();
+                    while let Some(&ch) = chars.peek() {
+                        match ch.is_ascii_alphabetic() {
+                            true => name.push(ch),
+                            false => break,
+                        };
+                        chars.next();
+                    }
+                    Token::Command(name)
+                },
+                Some(ch) if ch.is_whitespace() => Token::Command(" ".to_owned()),
+                Some(ch) => Token::Command(ch.to_string()),
+                None => Token::Symbol('\\'),
+            },
+
+            '{' => Token::Open,
+            '}' => Token::Close,
+            '^' => Token::Sup,
+            '_' => Token::Sub,
+            '\'' => Token::Prime,
+            '&' => Token::Align,
+
+            _ if ch.is_ascii_digit() => {
+                let mut number = ch.to_string();
+                while let Some(&ch) = chars.peek() {
+                    match ch.is_ascii_digit() || (ch == '.' && !number.contains('.')) {
+                        true => number.push(ch),
+                        false => break,
+                    };
+                    chars.next();
+                }
+                Token::Number(number)
+            },
+
+            _ if ch.is_whitespace() => Token::Space,
+            _ if ch.is_alphabetic() => Token::Letter(ch),
+            _ => Token::Symbol(ch),
+        });
+    }
+
+    res
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    display: bool,
+}
+
+impl Parser {
+    fn new(tokens: Vec<Token>, display: bool) -> Self {
+        Self {
+            tokens: tokens,
+            pos: 0,
+            display: display,
+        }
+    }
+
+    /// The next token that is not a space.
+    fn peek(&mut self) -> Option<Token> {
+        while self.tokens.get(self.pos) == Some(&Token::Space) {
+            self.pos += 1;
+        }
+
+        self.tokens.get(self.pos).cloned()
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let res = self.peek();
+        self.pos += 1;
+        res
+    }
+
+    /// Parses everything up to the end of the group, the matrix cell or the
+    /// `\left`, leaving what ends it to the caller.
+    fn row(&mut self) -> Result<String, MathErr> {
+        let mut items: Vec<String> = Vec::new();
+
+        loop {
+            match self.peek() {
+                None | Some(Token::Close) | Some(Token::Align) | Some(Token::NewRow) => break,
+                Some(Token::Command(ref name)) if name == "right" || name == "end" => break,
+                _ => items.push(self.item()?),
+            };
+        }
+
+        items.retain(|item| !item.is_empty());
+        match items.len() {
+            1 => Ok(items.remove(0)),
+            _ => Ok(format!("<mrow>{}</mrow>", items.concat())),
+        }
+    }
+
+    /// Parses one thing along with its subscript and superscript.
+    fn item(&mut self) -> Result<String, MathErr> {
+        let (base, limits) = match self.peek() {
+            Some(Token::Sub) | Some(Token::Sup) => ("<mrow></mrow>".to_owned(), false),
+            _ => self.atom()?,
+        };
+
+        let mut sub: Option<String> = None;
+        let mut sup: Option<String> = None;
+        let mut primes = String::new();
+
+        loop {
+            let script = match self.peek() {
+                Some(Token::Sub) => &mut sub,
+                Some(Token::Sup) => &mut sup,
+                Some(Token::Prime) => {
+                    self.pos += 1;
+                    primes.push('′');
+                    continue;
+                },
+                _ => break,
+            };
+
+            self.pos += 1;
+            match script.is_some() {
+                true => return Err(MathErr::DoubleScript),
+                false => *script = Some(self.argument()?),
+            };
+        }
+
+        let sup = match (primes.is_empty(), sup) {
+            (true, sup) => sup,
+            (false, None) => Some(format!("<mo>{}</mo>", primes)),
+            (false, Some(sup)) => Some(format!("<mrow><mo>{}</mo>{}</mrow>", primes, sup)),
+        };
+
+        let (under, over, both) = match limits && self.display {
+            true => ("munder", "mover", "munderover"),
+            false => ("msub", "msup", "msubsup"),
+        };
+
+        Ok(match (sub, sup) {
+            (None, None) => base,
+            (Some(sub), None) => format!("<{0}>{1}{2}</{0}>", under, base, sub),
+            (None, Some(sup)) => format!("<{0}>{1}{2}</{0}>", over, base, sup),
+            (Some(sub), Some(sup)) => format!("<{0}>{1}{2}{3}</{0}>", both, base, sub, sup),
+        })
+    }
+
+    /// Parses a required argument: a group, or else a single token. Of a
+    /// number, only the first digit is taken, as in `\frac12`.
+    fn argument(&mut self) -> Result<String, MathErr> {
+        match self.peek() {
+            None | Some(Token::Close) | Some(Token::Align) | Some(Token::NewRow) |
+            Some(Token::Sub) | Some(Token::Sup) => Err(MathErr::MissingArgument),
+
+            Some(Token::Number(ref number)) if number.len() > 1 => {
+                self.tokens[self.pos] = Token::Number(number[1 ..].to_owned());
+                Ok(format!("<mn>{}</mn>", &number[.. 1]))
+            },
+
+            _ => self.atom().map(|(res, _)| res),
+        }
+    }
+
+    /// Parses a single thing, returning its MathML and whether its scripts
+    /// go above and below it in display math.
+    fn atom(&mut self) -> Result<(String, bool), MathErr> {
+        let res = match self.next() {
+            Some(Token::Letter(ch)) => format!("<mi>{}</mi>", ch),
+            Some(Token::Number(number)) => format!("<mn>{}</mn>", number),
+            Some(Token::Prime) => "<mo>′</mo>".to_owned(),
+            Some(Token::Symbol(ch)) => symbol(ch)?,
+            Some(Token::Command(name)) => return self.command(&name),
+
+            Some(Token::Open) => {
+                let res = self.row()?;
+                match self.next() {
+                    Some(Token::Close) => res,
+                    _ => return Err(MathErr::Unbalanced),
+                }
+            },
+
+            Some(Token::Close) => return Err(MathErr::Unbalanced),
+            Some(token) => return Err(MathErr::Unexpected(token.to_text())),
+            None => return Err(MathErr::MissingArgument),
+        };
+
+        Ok((res, false))
+    }
+
+    fn command(&mut self, name: &str) -> Result<(String, bool), MathErr> {
+        if let Some(&(_, ch)) = GREEK.iter().find(|&&(greek, _)| greek == name) {
+            return Ok((match ch.is_uppercase() {
+                true => format!("<mi mathvariant=\"normal\">{}</mi>", ch),
+                false => format!("<mi>{}</mi>", ch),
+            }, false));
+        }
+
+        if let Some(&(_, text)) = IDENTIFIERS.iter().find(|&&(identifier, _)| identifier == name) {
+            return Ok((format!("<mi>{}</mi>", escape_html(text)), false));
+        }
+
+        if let Some(&(_, text)) = OPERATORS.iter().find(|&&(operator, _)| operator == name) {
+            return Ok((mo(text), false));
+        }
+
+        if let Some(&(_, text, limits)) = BIG_OPERATORS.iter().find(|&&(operator, _, _)| operator == name) {
+            return Ok((mo(text), limits));
+        }
+
+        if let Some(&(_, limits)) = FUNCTIONS.iter().find(|&&(function, _)| function == name) {
+            return Ok((format!("<mi>{}</mi>", name), limits));
+        }
+
+        if let Some(&(_, width)) = SPACES.iter().find(|&&(space, _)| space == name) {
+            return Ok((format!("<mspace width=\"{}\"/>", width), false));
+        }
+
+        if let Some(&(_, accent, below)) = ACCENTS.iter().find(|&&(command, _, _)| command == name) {
+            let arg = self.argument()?;
+            return Ok((match below {
+                true => format!("<munder accentunder=\"true\">{}{}</munder>", arg, mo(accent)),
+                false => format!("<mover accent=\"true\">{}{}</mover>", arg, mo(accent)),
+            }, false));
+        }
+
+        if let Some(&(_, size)) = BIG_SIZES.iter().find(|&&(big, _)| big == name) {
+            let delimiter = self.delimiter()?;
+            return Ok((format!(
+                "<mo minsize=\"{0}\" maxsize=\"{0}\">{1}</mo>",
+                size,
+                escape_html(delimiter)
+            ), false));
+        }
+
+        let res = match name {
+            "frac" | "dfrac" | "tfrac" | "cfrac" => {
+                let num = self.argument()?;
+                let den = self.argument()?;
+                format!("<mfrac>{}{}</mfrac>", num, den)
+            },
+
+            "binom" => {
+                let n = self.argument()?;
+                let k = self.argument()?;
+                format!("<mrow><mo>(</mo><mfrac linethickness=\"0\">{}{}</mfrac><mo>)</mo></mrow>", n, k)
+            },
+
+            "sqrt" => match self.peek() {
+                Some(Token::Symbol('[')) => {
+                    self.pos += 1;
+                    let index = self.optional()?;
+                    let arg = self.argument()?;
+                    format!("<mroot>{}{}</mroot>", arg, index)
+                },
+                _ => format!("<msqrt>{}</msqrt>", self.argument()?),
+            },
+
+            "text" | "textrm" | "textnormal" | "mbox" =>
+                format!("<mtext>{}</mtext>", escape_html(&self.raw_group()?)),
+
+            "operatorname" => format!("<mi>{}</mi>", escape_html(&self.raw_group()?)),
+
+            "mathrm" | "mathbf" | "mathit" | "mathbb" | "mathcal" | "mathscr" |
+            "mathfrak" | "mathsf" | "mathtt" => {
+                let text = self.raw_group()?;
+                match styled(name, &text) {
+                    Some(ref res) if name == "mathrm" && res.chars().count() == 1 =>
+                        format!("<mi mathvariant=\"normal\">{}</mi>", res),
+                    Some(res) => format!("<mi>{}</mi>", res),
+                    None => return Err(MathErr::UnknownCommand(name.to_owned())),
+                }
+            },
+
+            "left" => {
+                let open = self.delimiter()?;
+                let body = self.row()?;
+                match self.next() {
+                    Some(Token::Command(ref right)) if right == "right" => (),
+                    _ => return Err(MathErr::Unbalanced),
+                };
+                let close = self.delimiter()?;
+                format!("<mrow>{}{}{}</mrow>", fence(open), body, fence(close))
+            },
+
+            "overbrace" | "underbrace" => {
+                let arg = self.argument()?;
+                return Ok((match name {
+                    "overbrace" => format!("<mover>{}<mo>⏞</mo></mover>", arg),
+                    _ => format!("<munder>{}<mo>⏟</mo></munder>", arg),
+                }, true));
+            },
+
+            "begin" => self.environment()?,
+
+            "displaystyle" | "textstyle" | "limits" | "nolimits" => String::new(),
+
+            "right" | "end" => return Err(MathErr::Unbalanced),
+            _ => return Err(MathErr::UnknownCommand(name.to_owned())),
+        };
+
+        Ok((res, false))
+    }
+
+    /// Parses the optional argument of `\sqrt`, whose `[` is already taken.
+    fn optional(&mut self) -> Result<String, MathErr> {
+        let start = self.pos;
+        let mut depth = 0;
+
+        loop {
+            match self.tokens.get(self.pos) {
+                Some(&Token::Symbol(']')) if depth == 0 => break,
+                Some(&Token::Open) => depth += 1,
+                Some(&Token::Close) => depth -= 1,
+                Some(_) => (),
+                None => return Err(MathErr::Unbalanced),
+            };
+            self.pos += 1;
+        }
+
+        let mut inner = Parser::new(self.tokens[start .. self.pos].to_vec(), self.display);
+        self.pos += 1;
+
+        let res = inner.row()?;
+        match inner.peek() {
+            None => Ok(res),
+            Some(_) => Err(MathErr::Unbalanced),
+        }
+    }
+
+    /// Takes the text of a group as it is.
+    fn raw_group(&mut self) -> Result<String, MathErr> {
+        match self.next() {
+            Some(Token::Open) => (),
+            _ => return Err(MathErr::MissingArgument),
+        };
+
+        let mut res = String::new();
+        let mut depth = 0;
+
+        loop {
+            let token = match self.tokens.get(self.pos) {
+                Some(token) => token.clone(),
+                None => return Err(MathErr::Unbalanced),
+            };
+            self.pos += 1;
+
+            match token {
+                Token::Close if depth == 0 => return Ok(res),
+                Token::Close => depth -= 1,
+                Token::Open => depth += 1,
+                _ => (),
+            };
+            res.push_str(&token.to_text());
+        }
+    }
+
+    /// Parses a delimiter. An empty string stands for `.`, no delimiter.
+    fn delimiter(&mut self) -> Result<&'static str, MathErr> {
+        match self.next() {
+            Some(Token::Symbol('.')) => Ok(""),
+            Some(Token::Symbol('(')) => Ok("("),
+            Some(Token::Symbol(')')) => Ok(")"),
+            Some(Token::Symbol('[')) => Ok("["),
+            Some(Token::Symbol(']')) => Ok("]"),
+            Some(Token::Symbol('|')) => Ok("|"),
+            Some(Token::Symbol('/')) => Ok("/"),
+
+            Some(Token::Command(name)) => OPERATORS.iter()
+                .find(|&&(operator, _)| operator == name)
+                .map(|&(_, text)| text)
+                .ok_or(MathErr::BadDelimiter),
+
+            _ => Err(MathErr::BadDelimiter),
+        }
+    }
+
+    /// Parses an environment, whose `\begin` is already taken.
+    fn environment(&mut self) -> Result<String, MathErr> {
+        let name = self.raw_group()?;
+
+        let (open, close, align) = match name.as_str() {
+            "matrix" => ("", "", None),
+            "pmatrix" => ("(", ")", None),
+            "bmatrix" => ("[", "]", None),
+            "Bmatrix" => ("{", "}", None),
+            "vmatrix" => ("|", "|", None),
+            "Vmatrix" => ("‖", "‖", None),
+            "cases" => ("{", "", Some("left")),
+            "aligned" | "align" | "align*" => ("", "", Some("right left")),
+            "gathered" => ("", "", None),
+            _ => return Err(MathErr::UnknownEnvironment(name)),
+        };
+
+        let mut rows: Vec<Vec<String>> = vec![Vec::new()];
+        loop {
+            let cell = self.row()?;
+            rows.last_mut().unwrap().push(cell);
+
+            match self.peek() {
+                Some(Token::Align) => self.pos += 1,
+                Some(Token::NewRow) => {
+                    self.pos += 1;
+                    rows.push(Vec::new());
+                },
+                _ => break,
+            };
+        }
+
+        match self.next() {
+            Some(Token::Command(ref end)) if end == "end" => (),
+            _ => return Err(MathErr::Unbalanced),
+        };
+        if self.raw_group()? != name {
+            return Err(MathErr::Unbalanced);
+        }
+
+        //  a `\\` after the last row leaves an empty one
+        if rows.len() > 1 && rows.last() == Some(&vec![String::new()]) {
+            rows.pop();
+        }
+
+        let rows: Vec<String> = rows.into_iter()
+            .map(|cells| format!(
+                "<mtr>{}</mtr>",
+                cells.iter().map(|cell| format!("<mtd>{}</mtd>", cell)).collect::<String>()
+            ))
+            .collect();
+
+        let table = match align {
+            Some(align) => format!("<mtable columnalign=\"{}\">{}</mtable>", align, rows.concat()),
+            None => format!("<mtable>{}</mtable>", rows.concat()),
+        };
+
+        Ok(format!("<mrow>{}{}{}</mrow>", fence(open), table, fence(close)))
+    }
+}
+
+/// Turns TeX into a MathML `math` element.
+pub fn to_mathml(tex: &str, display: bool) -> Result<String, MathErr> {
+    let mut parser = Parser::new(tokens(tex), display);
+    let res = parser.row()?;
+
+    match parser.peek() {
+        None => (),
+        Some(Token::Close) | Some(Token::Command(_)) => return Err(MathErr::Unbalanced),
+        Some(token) => return Err(MathErr::Unexpected(token.to_text())),
+    };
+
+    Ok(match display {
+        true => format!("<math display=\"block\">{}</math>", res),
+        false => format!("<math>{}</math>", res),
+    })
+}
+
+/// HTML for TeX that cannot be turned into MathML: the TeX itself, in a span
+/// of class `math`.
+pub fn tex_html(tex: &str, display: bool) -> String {
+    match display {
+        true => format!("<span class=\"math math-display\">{}</span>", escape_html(tex)),
+        false => format!("<span class=\"math\">{}</span>", escape_html(tex)),
+    }
+}
+
+fn mo(text: &str) -> String {
+    format!("<mo>{}</mo>", escape_html(text))
+}
+
+fn fence(delimiter: &str) -> String {
+    match delimiter.is_empty() {
+        true => String::new(),
+        false => format!("<mo fence=\"true\" stretchy=\"true\">{}</mo>", escape_html(delimiter)),
+    }
+}
+
+fn symbol(ch: char) -> Result<String, MathErr> {
+    match ch {
+        '-' => Ok(mo("−")),
+        '*' => Ok(mo("∗")),
+        '~' => Ok("<mspace width=\"0.25em\"/>".to_owned()),
+        '#' | '%' | '$' | '\\' => Err(MathErr::Unexpected(ch.to_string())),
+        _ => Ok(mo(&ch.to_string())),
+    }
+}
+
+/// Sets letters and digits in one of the math alphabets of Unicode. Spaces
+/// are dropped. Returns `None` if anything else is in the way.
+fn styled(command: &str, text: &str) -> Option<String> {
+    text.chars()
+        .filter(|ch| !ch.is_whitespace())
+        .map(|ch| match command {
+            "mathrm" if ch.is_alphanumeric() => Some(ch),
+            _ => math_char(command, ch),
+        })
+        .collect()
+}
+
+fn math_char(command: &str, ch: char) -> Option<char> {
+    //  letters missing from the blocks, since they were in Unicode already
+    let exception = match (command, ch) {
+        ("mathit", 'h') => Some('ℎ'),
+        ("mathcal", 'B') | ("mathscr", 'B') => Some('ℬ'),
+        ("mathcal", 'E') | ("mathscr", 'E') => Some('ℰ'),
+        ("mathcal", 'F') | ("mathscr", 'F') => Some('ℱ'),
+        ("mathcal", 'H') | ("mathscr", 'H') => Some('ℋ'),
+        ("mathcal", 'I') | ("mathscr", 'I') => Some('ℐ'),
+        ("mathcal", 'L') | ("mathscr", 'L') => Some('ℒ'),
+        ("mathcal", 'M') | ("mathscr", 'M') => Some('ℳ'),
+        ("mathcal", 'R') | ("mathscr", 'R') => Some('ℛ'),
+        ("mathcal", 'e') | ("mathscr", 'e') => Some('ℯ'),
+        ("mathcal", 'g') | ("mathscr", 'g') => Some('ℊ'),
+        ("mathcal", 'o') | ("mathscr", 'o') => Some('ℴ'),
+        ("mathfrak", 'C') => Some('ℭ'),
+        ("mathfrak", 'H') => Some('ℌ'),
+        ("mathfrak", 'I') => Some('ℑ'),
+        ("mathfrak", 'R') => Some('ℜ'),
+        ("mathfrak", 'Z') => Some('ℨ'),
+        ("mathbb", 'C') => Some('ℂ'),
+        ("mathbb", 'H') => Some('ℍ'),
+        ("mathbb", 'N') => Some('ℕ'),
+        ("mathbb", 'P') => Some('ℙ'),
+        ("mathbb", 'Q') => Some('ℚ'),
+        ("mathbb", 'R') => Some('ℝ'),
+        ("mathbb", 'Z') => Some('ℤ'),
+        _ => None,
+    };
+    if exception.is_some() {
+        return exception;
+    }
+
+    //  where the capitals, small letters and digits start
+    let (upper, lower, digits) = match command {
+        "mathbf" => (0x1D400, 0x1D41A, Some(0x1D7CE)),
+        "mathit" => (0x1D434, 0x1D44E, None),
+        "mathcal" | "mathscr" => (0x1D49C, 0x1D4B6, None),
+        "mathfrak" => (0x1D504, 0x1D51E, None),
+        "mathbb" => (0x1D538, 0x1D552, Some(0x1D7D8)),
+        "mathsf" => (0x1D5A0, 0x1D5BA, Some(0x1D7E2)),
+        "mathtt" => (0x1D670, 0x1D68A, Some(0x1D7F6)),
+        _ => return None,
+    };
+
+    let code = match ch {
+        'A' ..= 'Z' => upper + (ch as u32 - 'A' as u32),
+        'a' ..= 'z' => lower + (ch as u32 - 'a' as u32),
+        '0' ..= '9' => digits? + (ch as u32 - '0' as u32),
+        _ => return None,
+    };
+
+    ::std::char::from_u32(code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mathml() {
+        assert_eq!(
+            to_mathml("x_1^2 + \\frac{a}{b}", false).unwrap(),
+            "<math><mrow><msubsup><mi>x</mi><mn>1</mn><mn>2</mn></msubsup><mo>+</mo>\
+<mfrac><mi>a</mi><mi>b</mi></mfrac></mrow></math>"
+        );
+        assert_eq!(
+            to_mathml("\\sum_{i=0}^n i", true).unwrap(),
+            "<math display=\"block\"><mrow><munderover><mo>∑</mo><mrow><mi>i</mi><mo>=</mo>\
+<mn>0</mn></mrow><mi>n</mi></munderover><mi>i</mi></mrow></math>"
+        );
+        assert_eq!(to_mathml("\\mathbb{R}", false).unwrap(), "<math><mi>ℝ</mi></math>");
+    }
+
+    #[test]
+    fn unsupported() {
+        assert_eq!(to_mathml("\\foo", false), Err(MathErr::UnknownCommand("foo".to_owned())));
+        assert_eq!(to_mathml("{a", false), Err(MathErr::Unbalanced));
+        assert_eq!(to_mathml("a^b^c", false), Err(MathErr::DoubleScript));
+        assert_eq!(tex_html("a < \\foo", false), "<span class=\"math\">a &lt; \\foo</span>");
+    }
+}
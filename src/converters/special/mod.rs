@@ -0,0 +1,324 @@
+/*
+ *  Special syntax
+ *
+ *  Fenced code blocks whose info string names one of the syntaxes below are
+ *  typeset by BasicConverter instead of being shown as code:
+ *
+ *      ```gloss        interlinear glossed text, see `gloss`
+ *      ```examples     numbered examples, see `examples`
+ *      ```tree         syntax trees in bracket notation, see `tree`
+ *      ```rules        phonological rules and feature matrices, see `rules`
+ *
+ *  Any other code block is rendered as usual. A block that does not make
+ *  sense as its syntax is rendered as usual too, with a warning.
+ *
+ *  Glosses and examples are numbered together through the post, and their
+ *  labels can be referred to from any text in it, before or after them.
+ *
+ *  Code spans starting with `ipa:` hold X-SAMPA, see `ipa`, and TeX between
+ *  dollar signs is math, see `math`. Both are picked out before parsing, see
+ *  `spans`.
+ */
+
+pub mod examples;
+pub mod gloss;
+pub mod ipa;
+pub mod math;
+pub mod rules;
+pub mod spans;
+pub mod tree;
+
+use converters::escape::escape_html;
+use converters::html::{ HtmlRenderer, Output };
+use converters::visitor::Visitor;
+
+use self::examples::{ Example, split_label };
+use self::gloss::Gloss;
+use self::rules::Rules;
+use self::tree::SyntaxTree;
+
+use cmark::*;
+
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::io;
+use std::io::Write;
+use std::path::{ Path, PathBuf };
+
+/// A block of HTML as lines, each with its indentation level within the
+/// block.
+pub struct Lines {
+    lines: Vec<(usize, String)>,
+}
+
+impl Lines {
+    pub fn new() -> Self {
+        Self {
+            lines: Vec::new(),
+        }
+    }
+
+    pub fn push<S: Into<String>>(&mut self, level: usize, line: S) {
+        self.lines.push((level, line.into()));
+    }
+
+    pub fn append(&mut self, mut other: Lines) {
+        self.lines.append(&mut other.lines);
+    }
+
+    /// Writes out the lines, indented to sit at the current position of
+    /// `html`.
+    pub fn write(&self, html: &HtmlRenderer, writer: &mut Output) -> io::Result<()> {
+        for &(level, ref line) in &self.lines {
+            write!(writer, "{}{}\n", html.indent(level), line)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A code block in one of the special syntaxes.
+enum Special {
+    Gloss(Gloss),
+    Examples(Vec<Example>),
+    Tree(SyntaxTree),
+    Rules(Rules),
+}
+
+impl Special {
+
+    /// Parses a code block with the info string `info`. Returns `Ok(None)`
+    /// for an ordinary code block, and what is wrong with it for a block that
+    /// does not make sense as its syntax.
+    fn parse(info: &str, text: &str) -> Result<Option<Self>, &'static str> {
+        match info {
+            "gloss" => match Gloss::parse(text) {
+                Some(gloss) => Ok(Some(Special::Gloss(gloss))),
+                None => Err("The lines of this gloss do not line up"),
+            },
+
+            "examples" => match examples::parse(text) {
+                ref examples if examples.is_empty() => Err("There are no examples in this block"),
+                examples => Ok(Some(Special::Examples(examples))),
+            },
+
+            "tree" => SyntaxTree::parse(text).map(|tree| Some(Special::Tree(tree))),
+            "rules" => Rules::parse(text).map(|rules| Some(Special::Rules(rules))),
+
+            _ => Ok(None),
+        }
+    }
+
+    /// How many numbers the block takes up.
+    fn count(&self) -> usize {
+        match self {
+            &Special::Gloss(_) => 1,
+            &Special::Examples(ref examples) => examples.len(),
+            &Special::Tree(_) | &Special::Rules(_) => 0,
+        }
+    }
+
+    /// The labels in the block, along with what they stand for when its first
+    /// example is number `first`.
+    fn labels(&self, first: usize) -> Vec<(String, String)> {
+        match self {
+            &Special::Gloss(ref gloss) => gloss.label()
+                .map(|label| (label.to_owned(), first.to_string()))
+                .into_iter()
+                .collect(),
+
+            &Special::Examples(ref examples) => examples.iter()
+                .enumerate()
+                .flat_map(|(idx, example)| example.labels(first + idx))
+                .collect(),
+
+            &Special::Tree(_) | &Special::Rules(_) => Vec::new(),
+        }
+    }
+
+    fn to_lines(&self, first: usize) -> Lines {
+        match self {
+            &Special::Gloss(ref gloss) => gloss.to_lines(first),
+
+            &Special::Examples(ref examples) => {
+                let mut res = Lines::new();
+                for (idx, example) in examples.iter().enumerate() {
+                    res.append(example.to_lines(first + idx));
+                }
+                res
+            },
+
+            &Special::Tree(ref tree) => tree.to_lines(),
+            &Special::Rules(ref rules) => rules.to_lines(),
+        }
+    }
+
+}
+
+/// Typesets the special syntax, and renders everything else like
+/// `DefaultVisitor`. Meant for a single post, since it numbers the examples
+/// in it.
+pub struct SpecialVisitor {
+    /// Number of the last example.
+    examples: usize,
+
+    /// What each label stands for, e.g. `2b`.
+    labels: HashMap<String, String>,
+
+    /// The post, for log messages.
+    source: PathBuf,
+}
+
+impl SpecialVisitor {
+    pub fn new() -> Self {
+        Self {
+            examples: 0,
+            labels: HashMap::new(),
+            source: PathBuf::new(),
+        }
+    }
+
+    /// Writes out a text with every `(@label)` in it turned into a link to
+    /// the example. `span` is where the text is, for warnings.
+    fn write_refs(&self, text: &str, span: &SourceSpan, writer: &mut Output) -> io::Result<()> {
+        let mut rest = text;
+
+        while let Some(start) = rest.find("(@") {
+            write!(writer, "{}", escape_html(&rest[.. start]))?;
+
+            rest = match split_label(&rest[start ..]) {
+                (Some(label), after) => {
+                    match self.labels.get(&label) {
+                        Some(number) => write!(
+                            writer,
+                            "(<a class=\"example-ref\" href=\"#ex-{}\">{}</a>)",
+                            number,
+                            number
+                        )?,
+
+                        None => {
+                            warn!(
+                                "{}:{}: There is no example labelled {}",
+                                self.source.display(), span, label
+                            );
+                            write!(writer, "(@{})", escape_html(&label))?;
+                        },
+                    };
+                    after
+                },
+
+                (None, _) => {
+                    write!(writer, "(@")?;
+                    &rest[start + 2 ..]
+                },
+            };
+        }
+
+        write!(writer, "{}", escape_html(rest))
+    }
+
+    /// Writes out the MathML for some TeX, or else the TeX itself.
+    fn write_math(
+        &self,
+        tex: &str,
+        display: bool,
+        span: &SourceSpan,
+        writer: &mut Output
+    ) -> io::Result<()> {
+        match math::to_mathml(tex, display) {
+            Ok(res) => write!(writer, "{}", res),
+            Err(e) => {
+                warn!(
+                    "{}:{}: Cannot turn ${}$ into MathML, showing the TeX: {}",
+                    self.source.display(), span, tex, e
+                );
+                write!(writer, "{}", math::tex_html(tex, display))
+            },
+        }
+    }
+}
+
+impl Visitor for SpecialVisitor {
+
+    /// Numbers the examples ahead of time, so that references can come
+    /// before the examples they refer to.
+    fn begin(&mut self, document: &Document, source: &Path) {
+        self.examples = 0;
+        self.labels.clear();
+        self.source = source.to_path_buf();
+
+        let mut number = 0;
+        for tree in document.root().descendants() {
+            let special = match tree.node {
+                Node::Block(Block::CodeBlock(ref info, ref lit)) => Special::parse(&info.strip(), lit),
+                _ => continue,
+            };
+
+            if let Ok(Some(special)) = special {
+                for (label, target) in special.labels(number + 1) {
+                    match self.labels.entry(label) {
+                        Entry::Occupied(entry) => warn!(
+                            "{}:{}: There is an example labelled {} already",
+                            source.display(), tree.span, entry.key()
+                        ),
+                        Entry::Vacant(entry) => {
+                            entry.insert(target);
+                        },
+                    };
+                }
+
+                number += special.count();
+            }
+        }
+    }
+
+    fn visit_code_block(
+        &mut self,
+        html: &mut HtmlRenderer,
+        info: &InfoString,
+        lit: &Literal,
+        span: &SourceSpan,
+        writer: &mut Output
+    ) -> io::Result<()> {
+        match Special::parse(&info.strip(), lit) {
+            Ok(Some(special)) => {
+                let lines = special.to_lines(self.examples + 1);
+                self.examples += special.count();
+                lines.write(html, writer)
+            },
+
+            Ok(None) => html.convert_code_block(info, lit, span, writer),
+
+            Err(e) => {
+                warn!("{}:{}: {}, showing it as code", self.source.display(), span, e);
+                html.convert_code_block(info, lit, span, writer)
+            },
+        }
+    }
+
+    fn visit_text(
+        &mut self,
+        _html: &mut HtmlRenderer,
+        lit: &Literal,
+        span: &SourceSpan,
+        writer: &mut Output
+    ) -> io::Result<()> {
+        self.write_refs(lit, span, writer)
+    }
+
+    fn visit_code(
+        &mut self,
+        html: &mut HtmlRenderer,
+        lit: &Literal,
+        span: &SourceSpan,
+        writer: &mut Output
+    ) -> io::Result<()> {
+        match html.attribute("class") {
+            Some(spans::INLINE_CLASS) => self.write_math(lit, false, span, writer),
+            Some(spans::DISPLAY_CLASS) => self.write_math(lit, true, span, writer),
+            Some(spans::IPA_CLASS) => write!(writer, "<span class=\"ipa\">{}</span>", escape_html(lit)),
+            _ => html.convert_code(lit, writer),
+        }
+    }
+
+}
@@ -0,0 +1,261 @@
+/*
+ *  Phonological rules
+ *
+ *      ```rules
+ *      [+syll] -> [-back] / [-back] C0 _
+ *      t -> 4 / V _ V
+ *      k -> 0 / _ #
+ *      ```
+ *
+ *  Each line with an arrow `->` is a rule `A -> B / C _ D`, laid out in
+ *  columns so that the arrows, slashes and focus bars of all the rules line
+ *  up. The arrow, the slash and the focus bar must be set apart by
+ *  whitespace. The environment may be left out, and so may either side of
+ *  the focus bar. Any other line, e.g. a feature matrix on its own, spans all
+ *  the columns.
+ *
+ *  Within the lines:
+ *
+ *  -   `[+syll, -back]` is a feature matrix. The features are separated by
+ *      commas, or by whitespace if there are no commas. `-` becomes a minus
+ *      sign and `+-` a plus-minus sign, while Greek letters for variables are
+ *      typed as they are.
+ *  -   `0` is the null segment. `C` and `V` stand for any consonant and vowel
+ *      rather than for X-SAMPA, and `C0` or `V1` have the number as a
+ *      subscript.
+ *  -   `#`, `+` and `$` are boundaries.
+ *  -   Anything else is a segment in X-SAMPA, see `ipa`.
+ */
+
+use converters::escape::escape_html;
+use converters::special::Lines;
+use converters::special::ipa::ipa_html;
+
+/// A line of a block of rules, split into its parts.
+#[derive(Debug, Clone, PartialEq)]
+enum Line {
+    Rule {
+        target: Vec<String>,
+        change: Vec<String>,
+
+        /// Everything after the slash, if there is one.
+        env: Option<Vec<String>>,
+    },
+
+    Other(Vec<String>),
+}
+
+/// A block of phonological rules.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rules {
+    lines: Vec<Line>,
+}
+
+impl Rules {
+
+    /// Parses a block of rules, or says what is wrong with it.
+    pub fn parse(text: &str) -> Result<Self, &'static str> {
+        let mut lines: Vec<Line> = Vec::new();
+
+        for line in text.lines().filter(|line| !line.trim().is_empty()) {
+            let mut tokens = match tokens(line) {
+                Some(tokens) => tokens,
+                None => return Err("The brackets of this block do not match"),
+            };
+
+            let arrow = tokens.iter().position(|token| token == "->" || token == "→");
+            lines.push(match arrow {
+                Some(arrow) => {
+                    let mut change = tokens.split_off(arrow + 1);
+                    tokens.pop();
+
+                    let env = change.iter()
+                        .position(|token| token == "/")
+                        .map(|slash| change.split_off(slash).split_off(1));
+
+                    Line::Rule {
+                        target: tokens,
+                        change: change,
+                        env: env,
+                    }
+                },
+
+                None => Line::Other(tokens),
+            });
+        }
+
+        match lines.is_empty() {
+            true => Err("There are no rules in this block"),
+            false => Ok(Self {
+                lines: lines,
+            }),
+        }
+    }
+
+    /// Lays out the rules as a table, one column for each of their parts.
+    pub fn to_lines(&self) -> Lines {
+        let mut res = Lines::new();
+
+        res.push(0, "<table class=\"rules\">");
+        for line in &self.lines {
+            res.push(1, "<tr>");
+
+            match line {
+                &Line::Rule { ref target, ref change, ref env } => {
+                    res.push(2, format!("<td class=\"rule-target\">{}</td>", cell(target)));
+                    res.push(2, "<td class=\"rule-arrow\">→</td>");
+                    res.push(2, format!("<td class=\"rule-change\">{}</td>", cell(change)));
+
+                    let (slash, left, focus, right) = match env {
+                        &Some(ref env) => match env.iter().position(|token| token == "_") {
+                            Some(focus) => ("/", cell(&env[.. focus]), "__", cell(&env[focus + 1 ..])),
+                            None => ("/", cell(env), "", String::new()),
+                        },
+                        &None => ("", String::new(), "", String::new()),
+                    };
+
+                    res.push(2, format!("<td class=\"rule-slash\">{}</td>", slash));
+                    res.push(2, format!("<td class=\"rule-left\">{}</td>", left));
+                    res.push(2, format!("<td class=\"rule-focus\">{}</td>", focus));
+                    res.push(2, format!("<td class=\"rule-right\">{}</td>", right));
+                },
+
+                &Line::Other(ref tokens) =>
+                    res.push(2, format!("<td class=\"rule-other\" colspan=\"7\">{}</td>", cell(tokens))),
+            };
+
+            res.push(1, "</tr>");
+        }
+        res.push(0, "</table>");
+
+        res
+    }
+
+}
+
+/// Splits a line at whitespace, except within brackets. Returns `None` if the
+/// brackets do not match.
+fn tokens(line: &str) -> Option<Vec<String>> {
+    let mut res: Vec<String> = Vec::new();
+    let mut token = String::new();
+    let mut depth = 0;
+
+    for ch in line.chars() {
+        match ch {
+            '[' => depth += 1,
+            ']' if depth == 0 => return None,
+            ']' => depth -= 1,
+            _ => (),
+        };
+
+        match ch.is_whitespace() && depth == 0 {
+            true => if !token.is_empty() {
+                res.push(token.clone());
+                token.clear();
+            },
+            false => token.push(ch),
+        };
+    }
+
+    if !token.is_empty() {
+        res.push(token);
+    }
+
+    match depth {
+        0 => Some(res),
+        _ => None,
+    }
+}
+
+fn cell(tokens: &[String]) -> String {
+    tokens.iter().map(|token| token_html(token)).collect::<Vec<_>>().join(" ")
+}
+
+fn token_html(token: &str) -> String {
+    let mut chars = token.chars();
+    let first = chars.next().unwrap_or(' ');
+    let rest = chars.as_str();
+
+    match token {
+        "0" | "∅" => "∅".to_owned(),
+        "#" | "##" | "+" | "$" | "_" => escape_html(token),
+
+        _ if token.starts_with('[') && token.ends_with(']') =>
+            matrix_html(&token[1 .. token.len() - 1]),
+
+        "C" | "V" => token.to_owned(),
+
+        _ if (first == 'C' || first == 'V') && rest.chars().all(|ch| ch.is_ascii_digit()) =>
+            format!("{}<sub>{}</sub>", first, rest),
+
+        _ => ipa_html(token),
+    }
+}
+
+fn matrix_html(features: &str) -> String {
+    let features: Vec<&str> = match features.contains(',') {
+        true => features.split(',').map(str::trim).filter(|feature| !feature.is_empty()).collect(),
+        false => features.split_whitespace().collect(),
+    };
+
+    let features: Vec<String> = features.into_iter().map(feature_html).collect();
+    format!("<span class=\"matrix\">{}</span>", features.join(""))
+}
+
+/// HTML for a feature such as `+voice` or `αback`, with its value apart.
+fn feature_html(feature: &str) -> String {
+    let end = feature.find(|ch| !is_value(ch)).unwrap_or(feature.len());
+    let (value, name) = feature.split_at(end);
+
+    let value = match value {
+        "+-" | "-+" => "±".to_owned(),
+        _ => value.replace('-', "−"),
+    };
+
+    format!(
+        "<span class=\"feature\"><span class=\"feature-value\">{}</span>{}</span>",
+        escape_html(&value),
+        escape_html(name)
+    )
+}
+
+fn is_value(ch: char) -> bool {
+    "+-±−".contains(ch) || ('α' ..= 'ω').contains(&ch)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rule_parts() {
+        let rules = Rules::parse("[+syll, -back] -> [+round] / C0 _ #\nk -> 0\n[αback]").unwrap();
+
+        assert_eq!(rules.lines[0], Line::Rule {
+            target: vec!["[+syll, -back]".to_owned()],
+            change: vec!["[+round]".to_owned()],
+            env: Some(vec!["C0".to_owned(), "_".to_owned(), "#".to_owned()]),
+        });
+        assert_eq!(rules.lines[1], Line::Rule {
+            target: vec!["k".to_owned()],
+            change: vec!["0".to_owned()],
+            env: None,
+        });
+        assert_eq!(rules.lines[2], Line::Other(vec!["[αback]".to_owned()]));
+
+        assert!(Rules::parse("[+syll -> a").is_err());
+    }
+
+    #[test]
+    fn features() {
+        assert_eq!(
+            matrix_html("+-voice αback"),
+            "<span class=\"matrix\">\
+<span class=\"feature\"><span class=\"feature-value\">±</span>voice</span>\
+<span class=\"feature\"><span class=\"feature-value\">α</span>back</span>\
+</span>"
+        );
+        assert_eq!(token_html("C0"), "C<sub>0</sub>");
+        assert_eq!(token_html("V"), "V");
+    }
+}
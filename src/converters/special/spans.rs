@@ -0,0 +1,1280 @@
+/*
+ *  Math and IPA spans
+ *
+ *  Math and IPA sit in the running text, see `math` and `ipa`. cmark knows
+ *  nothing of them, so before parsing, `Protect` turns them into code spans
+ *  starting with a mark, a Unicode noncharacter, which cannot come from the
+ *  post itself, since any in it are replaced with U+FFFD:
+ *
+ *      $x_1$               `U+FDD0 x_1`
+ *      $$ ... $$           `U+FDD1 ... `
+ *      `ipa:["t_hEst]`     `U+FDD2 ["t_hEst]`
+ *
+ *  `Protect` follows block quotes and list items the way cmark does, so that
+ *  code blocks, HTML blocks, other code spans, raw HTML, autolinks and link
+ *  destinations are left alone, and so are line numbers. A marked code span
+ *  right next to another one gets U+FDD3 in between, or cmark would read both
+ *  runs of backticks as one. After parsing, `SpecialSpans` turns the marked code spans into code nodes
+ *  holding just the TeX or the IPA, with a class in `Tree::attributes` telling
+ *  them apart from ordinary code.
+ *
+ *  Only a code span written with a single backtick is IPA, so that one with
+ *  more, e.g. ``ipa:x``, stays code.
+ */
+
+use converters::special::ipa;
+
+use transforms::Transform;
+
+use cmark::*;
+
+use std::io;
+use std::io::{ BufRead, Read };
+use std::str;
+
+const INLINE_MARK: char = '\u{FDD0}';
+const DISPLAY_MARK: char = '\u{FDD1}';
+const IPA_MARK: char = '\u{FDD2}';
+
+/// Keeps a marked code span apart from a code span right next to it.
+const SEPARATOR: char = '\u{FDD3}';
+
+/// Class of the code nodes holding inline math.
+pub const INLINE_CLASS: &str = "math";
+
+/// Class of the code nodes holding display math.
+pub const DISPLAY_CLASS: &str = "math math-display";
+
+/// Class of the code nodes holding IPA.
+pub const IPA_CLASS: &str = "ipa";
+
+/// Reads a markdown with its math and IPA turned into marked code spans. Reads
+/// ahead a paragraph at a time, since math can span lines.
+///
+/// Only paragraphs and headings are marked, so `Protect` follows as much of
+/// the block structure as it takes to find them: block quotes and list items,
+/// whose prefixes it keeps out of the paragraph, code blocks and HTML blocks.
+pub struct Protect<R> {
+    reader: R,
+
+    /// Protected markdown not read yet, from `pos` on.
+    out: Vec<u8>,
+    pos: usize,
+
+    /// Lines of the paragraph so far, split into the prefixes of their
+    /// containers and their content.
+    paragraph: Vec<(Vec<u8>, Vec<u8>)>,
+
+    /// Block quotes and list items the last line was in, outermost first.
+    containers: Vec<Container>,
+
+    /// The leaf block the last line was in.
+    leaf: Leaf,
+
+    done: bool,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Container {
+    Quote,
+
+    /// A list item, with the width of its marker and the spaces around it,
+    /// which its other lines are indented by.
+    Item(usize),
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Leaf {
+    None,
+    Paragraph,
+
+    /// A fenced code block, with the character and the length of its fence.
+    Fence(u8, usize),
+
+    IndentedCode,
+
+    /// An HTML block, with the text ending it, or `None` if a blank line
+    /// does.
+    Html(Option<&'static str>),
+}
+
+impl<R: BufRead> Protect<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader: reader,
+            out: Vec::new(),
+            pos: 0,
+            paragraph: Vec::new(),
+            containers: Vec::new(),
+            leaf: Leaf::None,
+            done: false,
+        }
+    }
+
+    /// Reads lines until some protected markdown is ready, or there are no
+    /// more lines.
+    fn fill(&mut self) -> io::Result<()> {
+        let mut line: Vec<u8> = Vec::new();
+
+        while self.out.is_empty() && !self.done {
+            line.clear();
+            match self.reader.read_until(b'\n', &mut line)? {
+                0 => {
+                    self.flush();
+                    self.done = true;
+                },
+                _ => self.push_line(&line),
+            };
+        }
+
+        Ok(())
+    }
+
+    /// Follows a line through the block structure, along the lines of the
+    /// CommonMark spec's own parsing strategy: the line continues the open
+    /// containers it has the prefixes of, may open new ones, and the rest
+    /// goes to a leaf block.
+    fn push_line(&mut self, line: &[u8]) {
+        let mut cursor = Cursor::new(line);
+        let matched = self.containers.iter()
+            .take_while(|&&container| cursor.continues(container))
+            .count();
+        let all_matched = matched == self.containers.len();
+
+        //  code and HTML blocks take their lines as they are
+        if all_matched {
+            let blank = cursor.is_blank();
+            match self.leaf {
+                Leaf::Fence(fence, len) => {
+                    if closes_fence(&cursor, fence, len) {
+                        self.leaf = Leaf::None;
+                    }
+                    return self.push_verbatim(line);
+                },
+
+                Leaf::Html(Some(end)) => {
+                    if contains_ignore_case(cursor.rest(), end) {
+                        self.leaf = Leaf::None;
+                    }
+                    return self.push_verbatim(line);
+                },
+
+                Leaf::Html(None) if !blank => return self.push_verbatim(line),
+
+                Leaf::IndentedCode if blank || cursor.indent() >= 4 => return self.push_verbatim(line),
+
+                _ => (),
+            };
+        }
+
+        //  display math goes on until it is closed, or a blank line. The
+        //  line gets the prefixes of all the containers, so that once it is
+        //  indented as part of the math, it cannot start a block in any.
+        if self.leaf == Leaf::Paragraph && !cursor.is_blank() && self.in_display_math() {
+            let prefix = self.continuation_prefix();
+            return self.paragraph.push((prefix, cursor.rest().to_vec()));
+        }
+
+        //  new block quotes and list items
+        let interrupts = all_matched && self.leaf == Leaf::Paragraph;
+        let mut opened = false;
+
+        while cursor.indent() < 4 {
+            let text = trim_start(cursor.rest());
+
+            if text.first() == Some(&b'>') {
+                if !opened {
+                    self.close(matched);
+                    opened = true;
+                }
+
+                cursor.skip_indent();
+                cursor.bump(1);
+                cursor.skip_space();
+                self.containers.push(Container::Quote);
+                continue;
+            }
+
+            let marker = match is_thematic_break(text) {
+                true => None,
+                false => list_marker(text),
+            };
+
+            if let Some((len, can_interrupt)) = marker {
+                let empty = text[len ..].iter().all(u8::is_ascii_whitespace);
+                if interrupts && !opened && (empty || !can_interrupt) {
+                    break;
+                }
+
+                if !opened {
+                    self.close(matched);
+                    opened = true;
+                }
+
+                let start = cursor.col;
+                cursor.skip_indent();
+                cursor.bump(len);
+
+                //  the content starts after one to four spaces, or one if
+                //  there are more, since the rest is indented code
+                let spaces = cursor.indent();
+                let content = match empty || spaces > 4 {
+                    true => cursor.col + 1,
+                    false => cursor.col + spaces,
+                };
+                if !empty {
+                    cursor.advance_to(content);
+                }
+
+                self.containers.push(Container::Item(content - start));
+                continue;
+            }
+
+            break;
+        }
+
+        let blank = cursor.is_blank();
+        let indent = cursor.indent();
+        let text = trim_start(cursor.rest());
+
+        //  a line not starting a block of its own may continue a paragraph
+        //  without the prefixes of its containers
+        if !opened && !all_matched {
+            let lazy = self.leaf == Leaf::Paragraph
+                && !blank
+                && (indent >= 4 || !starts_leaf(text));
+
+            match lazy {
+                true => return self.push_paragraph(&cursor),
+                false => self.close(matched),
+            };
+        }
+
+        if blank {
+            self.leaf = Leaf::None;
+            return self.push_verbatim(line);
+        }
+
+        if indent >= 4 {
+            return match self.leaf {
+                Leaf::Paragraph => self.push_paragraph(&cursor),
+                _ => {
+                    self.leaf = Leaf::IndentedCode;
+                    self.push_verbatim(line)
+                },
+            };
+        }
+
+        if let Some((fence, len)) = opens_fence(text) {
+            self.leaf = Leaf::Fence(fence, len);
+            return self.push_verbatim(line);
+        }
+
+        if let Some(end) = html_block(text, self.leaf == Leaf::Paragraph) {
+            self.leaf = match end {
+                Some(end) if contains_ignore_case(&text[2 ..], end) => Leaf::None,
+                _ => Leaf::Html(end),
+            };
+            return self.push_verbatim(line);
+        }
+
+        if is_thematic_break(text) {
+            self.leaf = Leaf::None;
+            return self.push_verbatim(line);
+        }
+
+        //  a heading is a paragraph of a line, as far as spans go
+        if is_heading(text) {
+            self.flush();
+            self.push_paragraph(&cursor);
+            self.flush();
+            self.leaf = Leaf::None;
+            return;
+        }
+
+        self.push_paragraph(&cursor);
+        self.leaf = Leaf::Paragraph;
+    }
+
+    fn push_paragraph(&mut self, cursor: &Cursor) {
+        let prefix = cursor.line[.. cursor.pos].to_vec();
+        self.paragraph.push((prefix, cursor.rest().to_vec()));
+    }
+
+    /// The prefix continuing all the open containers.
+    fn continuation_prefix(&self) -> Vec<u8> {
+        let mut res: Vec<u8> = Vec::new();
+
+        for container in &self.containers {
+            match container {
+                &Container::Quote => res.extend_from_slice(b"> "),
+                &Container::Item(width) => for _ in 0 .. width {
+                    res.push(b' ');
+                },
+            };
+        }
+
+        res
+    }
+
+    /// The paragraph so far, without the prefixes of its lines.
+    fn content(&self) -> Vec<u8> {
+        let mut res: Vec<u8> = Vec::new();
+        for &(_, ref content) in &self.paragraph {
+            res.extend_from_slice(content);
+        }
+        res
+    }
+
+    fn in_display_math(&self) -> bool {
+        str::from_utf8(&self.content()).map(in_display_math).unwrap_or(false)
+    }
+
+    /// Closes the containers from the `matched`th on, with the leaf block.
+    fn close(&mut self, matched: usize) {
+        self.flush();
+        self.containers.truncate(matched);
+        self.leaf = Leaf::None;
+    }
+
+    fn push_verbatim(&mut self, line: &[u8]) {
+        self.flush();
+        self.out.extend_from_slice(line);
+    }
+
+    /// Moves the paragraph over to the output, with its math and IPA marked
+    /// and the prefixes of its lines put back. A paragraph that is not UTF-8
+    /// is left for cmark to deal with.
+    fn flush(&mut self) {
+        let content = unmark(&self.content());
+
+        match str::from_utf8(&content) {
+            Ok(text) => {
+                //  marking keeps the line breaks, so the lines still match up
+                for (idx, text) in mark_paragraph(text).split('\n').enumerate() {
+                    if idx > 0 {
+                        self.out.push(b'\n');
+                    }
+                    if let Some(&(ref prefix, _)) = self.paragraph.get(idx) {
+                        self.out.extend_from_slice(prefix);
+                    }
+                    self.out.extend_from_slice(text.as_bytes());
+                }
+            },
+
+            Err(_) => for &(ref prefix, ref content) in &self.paragraph {
+                self.out.extend_from_slice(prefix);
+                self.out.extend_from_slice(content);
+            },
+        };
+
+        self.paragraph.clear();
+    }
+}
+
+impl<R: BufRead> Read for Protect<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.out.len() {
+            self.out.clear();
+            self.pos = 0;
+            self.fill()?;
+        }
+
+        let len = buf.len().min(self.out.len() - self.pos);
+        buf[.. len].copy_from_slice(&self.out[self.pos .. self.pos + len]);
+        self.pos += len;
+
+        Ok(len)
+    }
+}
+
+/// A position in a line, in bytes and in columns, with tabs stopping every
+/// four columns. A tab can be consumed in part, so `col` can be ahead of
+/// `pos_col`, the column at `pos`.
+struct Cursor<'a> {
+    line: &'a [u8],
+    pos: usize,
+    pos_col: usize,
+    col: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(line: &'a [u8]) -> Self {
+        Cursor {
+            line: line,
+            pos: 0,
+            pos_col: 0,
+            col: 0,
+        }
+    }
+
+    fn rest(&self) -> &'a [u8] {
+        &self.line[self.pos ..]
+    }
+
+    fn is_blank(&self) -> bool {
+        self.rest().iter().all(u8::is_ascii_whitespace)
+    }
+
+    /// Columns of whitespace from the current column on.
+    fn indent(&self) -> usize {
+        let mut col = self.pos_col;
+        for &ch in self.rest() {
+            match ch {
+                b' ' => col += 1,
+                b'\t' => col += 4 - col % 4,
+                _ => break,
+            };
+        }
+        col - self.col
+    }
+
+    /// Consumes whitespace up to `target`, which must be within the
+    /// indentation.
+    fn advance_to(&mut self, target: usize) {
+        while self.col < target {
+            let next = match self.line[self.pos] {
+                b'\t' => self.pos_col + 4 - self.pos_col % 4,
+                _ => self.pos_col + 1,
+            };
+
+            match next <= target {
+                true => {
+                    self.pos += 1;
+                    self.pos_col = next;
+                    self.col = next;
+                },
+                false => self.col = target,
+            };
+        }
+    }
+
+    fn skip_indent(&mut self) {
+        let target = self.col + self.indent();
+        self.advance_to(target);
+    }
+
+    /// Consumes one space, or a column of a tab, if there is one.
+    fn skip_space(&mut self) {
+        if self.indent() > 0 {
+            let target = self.col + 1;
+            self.advance_to(target);
+        }
+    }
+
+    /// Consumes `len` bytes that are not whitespace.
+    fn bump(&mut self, len: usize) {
+        self.pos += len;
+        self.pos_col += len;
+        self.col = self.pos_col;
+    }
+
+    /// Consumes the prefix of `container`, if the line has it.
+    fn continues(&mut self, container: Container) -> bool {
+        match container {
+            Container::Quote => {
+                let quoted = self.indent() < 4 && trim_start(self.rest()).first() == Some(&b'>');
+                if quoted {
+                    self.skip_indent();
+                    self.bump(1);
+                    self.skip_space();
+                }
+                quoted
+            },
+
+            Container::Item(width) => {
+                let blank = self.is_blank();
+                let inside = !blank && self.indent() >= width;
+                if inside {
+                    let target = self.col + width;
+                    self.advance_to(target);
+                }
+                blank || inside
+            },
+        }
+    }
+}
+
+/// The length of a list marker at the start of a line, and whether it can
+/// interrupt a paragraph, which only bullets and the number 1 can.
+fn list_marker(line: &[u8]) -> Option<(usize, bool)> {
+    let digits = line.iter().take_while(|ch| ch.is_ascii_digit()).count();
+    let rest = &line[digits ..];
+
+    let interrupts = match (digits, rest.first()) {
+        (0, Some(&b'-')) | (0, Some(&b'*')) | (0, Some(&b'+')) => true,
+        (1 ..= 9, Some(&b'.')) | (1 ..= 9, Some(&b')')) => line[.. digits].iter().rev().skip(1).all(|&ch| ch == b'0')
+            && line[digits - 1] == b'1',
+        _ => return None,
+    };
+
+    match rest.get(1).map_or(true, u8::is_ascii_whitespace) {
+        true => Some((digits + 1, interrupts)),
+        false => None,
+    }
+}
+
+fn is_thematic_break(line: &[u8]) -> bool {
+    let ch = match line.first() {
+        Some(&ch) if ch == b'*' || ch == b'-' || ch == b'_' => ch,
+        _ => return false,
+    };
+
+    line.iter().all(|&c| c == ch || c.is_ascii_whitespace())
+        && line.iter().filter(|&&c| c == ch).count() >= 3
+}
+
+fn is_heading(line: &[u8]) -> bool {
+    let hashes = line.iter().take_while(|&&ch| ch == b'#').count();
+    (1 ..= 6).contains(&hashes)
+        && line.get(hashes).map_or(true, u8::is_ascii_whitespace)
+}
+
+/// Whether a line starts a block that ends a paragraph, rather than going on
+/// with it. The line has its indentation trimmed, and containers are taken
+/// care of already.
+fn starts_leaf(line: &[u8]) -> bool {
+    is_thematic_break(line)
+        || is_heading(line)
+        || opens_fence(line).is_some()
+        || html_block(line, true).is_some()
+}
+
+/// The character and the length of the fence opening a code block.
+fn opens_fence(line: &[u8]) -> Option<(u8, usize)> {
+    let fence = match line.first() {
+        Some(&ch) if ch == b'`' || ch == b'~' => ch,
+        _ => return None,
+    };
+
+    let len = line.iter().take_while(|&&ch| ch == fence).count();
+    let info_ok = fence == b'~' || !line[len ..].contains(&b'`');
+
+    match len >= 3 && info_ok {
+        true => Some((fence, len)),
+        false => None,
+    }
+}
+
+fn closes_fence(cursor: &Cursor, fence: u8, len: usize) -> bool {
+    let text = trim_start(cursor.rest());
+    let run = text.iter().take_while(|&&ch| ch == fence).count();
+
+    cursor.indent() < 4 && run >= len && text[run ..].iter().all(u8::is_ascii_whitespace)
+}
+
+/// Tags starting an HTML block that a blank line ends.
+const HTML_BLOCK_TAGS: &[&str] = &[
+    "address", "article", "aside", "base", "basefont", "blockquote", "body",
+    "caption", "center", "col", "colgroup", "dd", "details", "dialog", "dir",
+    "div", "dl", "dt", "fieldset", "figcaption", "figure", "footer", "form",
+    "frame", "frameset", "h1", "h2", "h3", "h4", "h5", "h6", "head", "header",
+    "hr", "html", "iframe", "legend", "li", "link", "main", "menu", "menuitem",
+    "meta", "nav", "noframes", "ol", "optgroup", "option", "p", "param",
+    "section", "source", "summary", "table", "tbody", "td", "tfoot", "th",
+    "thead", "title", "tr", "track", "ul",
+];
+
+/// Whether a line starts an HTML block, and if so, the text ending it, or
+/// `None` if a blank line does. An HTML block starting with just any tag
+/// cannot interrupt a paragraph.
+fn html_block(line: &[u8], in_paragraph: bool) -> Option<Option<&'static str>> {
+    if line.first() != Some(&b'<') {
+        return None;
+    }
+
+    let lower = line.to_ascii_lowercase();
+    let ends_name = |len: usize| lower.get(len).map_or(true, |&ch| ch == b'>' || ch.is_ascii_whitespace());
+
+    for &(start, end) in &[("<script", "</script>"), ("<pre", "</pre>"), ("<style", "</style>")] {
+        if lower.starts_with(start.as_bytes()) && ends_name(start.len()) {
+            return Some(Some(end));
+        }
+    }
+
+    let special = [("<!--", "-->"), ("<?", "?>"), ("<![CDATA[", "]]>")];
+    for &(start, end) in &special {
+        if line.starts_with(start.as_bytes()) {
+            return Some(Some(end));
+        }
+    }
+
+    if line.starts_with(b"<!") && line.get(2).map_or(false, u8::is_ascii_uppercase) {
+        return Some(Some(">"));
+    }
+
+    let name_start = match lower.get(1) {
+        Some(&b'/') => 2,
+        _ => 1,
+    };
+    let name_len = lower[name_start ..].iter().take_while(|ch| ch.is_ascii_alphanumeric()).count();
+    let name = &lower[name_start .. name_start + name_len];
+    let after = &lower[name_start + name_len ..];
+
+    let block_tag = HTML_BLOCK_TAGS.iter().any(|tag| tag.as_bytes() == name)
+        && (ends_name(name_start + name_len) || after.starts_with(b"/>"));
+    if block_tag {
+        return Some(None);
+    }
+
+    let tag_len = match name_start {
+        2 => closing_tag_len(line),
+        _ => open_tag_len(line),
+    };
+    match tag_len {
+        Some(len) if !in_paragraph && line[len ..].iter().all(u8::is_ascii_whitespace) => Some(None),
+        _ => None,
+    }
+}
+
+fn contains_ignore_case(text: &[u8], needle: &str) -> bool {
+    text.to_ascii_lowercase().windows(needle.len()).any(|window| window == needle.as_bytes())
+}
+
+/// Turns the code spans marked by `Protect` into code nodes holding just the
+/// TeX or the IPA, of class `INLINE_CLASS`, `DISPLAY_CLASS` or `IPA_CLASS`.
+pub struct SpecialSpans;
+
+impl SpecialSpans {
+    pub fn new() -> Self {
+        SpecialSpans
+    }
+}
+
+impl Transform for SpecialSpans {
+    fn name(&self) -> &str {
+        "special-spans"
+    }
+
+    fn transform(&mut self, document: &mut Document) -> Result<(), Error> {
+        document.root_mut().for_each_mut(|tree| {
+            let (class, content) = match tree.node {
+                Node::Inline(Inline::Text(ref lit)) if lit.contains(SEPARATOR) => {
+                    let text = lit.replace(SEPARATOR, "");
+                    tree.node.set_literal(&text);
+                    return;
+                },
+
+                Node::Inline(Inline::Code(ref lit)) => match lit.chars().next() {
+                    Some(INLINE_MARK) => (INLINE_CLASS, lit[INLINE_MARK.len_utf8() ..].trim().to_owned()),
+                    Some(DISPLAY_MARK) => (DISPLAY_CLASS, lit[DISPLAY_MARK.len_utf8() ..].trim().to_owned()),
+                    Some(IPA_MARK) => (IPA_CLASS, ipa::to_ipa(&lit[IPA_MARK.len_utf8() ..])),
+                    _ => return,
+                },
+                _ => return,
+            };
+
+            tree.node.set_literal(&content);
+            tree.attributes.push(("class".to_owned(), class.to_owned()));
+        });
+
+        Ok(())
+    }
+}
+
+/// Replaces the marks in a text with U+FFFD, so that only `Protect` makes
+/// them. Works on bytes, since the text need not be UTF-8.
+fn unmark(text: &[u8]) -> Vec<u8> {
+    let mut res = Vec::with_capacity(text.len());
+    let mut idx = 0;
+
+    while idx < text.len() {
+        //  U+FDD0 to U+FDD3 in UTF-8, and U+FFFD, of the same length
+        match &text[idx ..] {
+            &[0xEF, 0xB7, 0x90 ..= 0x93, ..] => {
+                res.extend_from_slice(&[0xEF, 0xBF, 0xBD]);
+                idx += 3;
+            },
+            _ => {
+                res.push(text[idx]);
+                idx += 1;
+            },
+        };
+    }
+
+    res
+}
+
+fn trim_start(line: &[u8]) -> &[u8] {
+    let start = line.iter().take_while(|&&ch| ch == b' ' || ch == b'\t').count();
+    &line[start ..]
+}
+
+/// Marks the math and IPA in a paragraph. Of the link reference definitions
+/// it may start with, only the labels are marked, so that they still match
+/// the links using them.
+fn mark_paragraph(text: &str) -> String {
+    let mut res = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some((start, end, len)) = link_definition(rest.as_bytes()) {
+        res.push_str(&rest[.. start]);
+        res.push_str(&mark_spans(&rest[start .. end]));
+        res.push_str(&rest[end .. len]);
+        rest = &rest[len ..];
+    }
+
+    res.push_str(&mark_spans(rest));
+    res
+}
+
+/// Marks the math and IPA in the running text of a paragraph. Code spans,
+/// raw HTML, autolinks and link destinations are left alone.
+fn mark_spans(text: &str) -> String {
+    let mut res = String::with_capacity(text.len());
+    let mut rest = text;
+
+    //  a run of backticks closing no code span would close a marked one of
+    //  the same length. cmark 0.28 then also misses the second code span of
+    //  any length, so from there on, each marked one gets a length no other
+    //  run in the paragraph has.
+    let mut unclosed = false;
+    let mut avoid: Vec<usize> = Vec::new();
+
+    while let Some(start) = rest.find(SPECIAL) {
+        res.push_str(&rest[.. start]);
+        let after = &rest[start ..];
+
+        let len = match verbatim_len(after) {
+            Some(len) if len > 1 && after.starts_with('`') && !after.starts_with("``") && after[1 ..].starts_with(ipa::PREFIX) => {
+                push_apart(&mut res, &format!("`{}{}", IPA_MARK, &after[1 + ipa::PREFIX.len() .. len]));
+                rest = &after[len ..];
+                continue;
+            },
+
+            Some(len) => {
+                if !unclosed && after[.. len].bytes().all(|ch| ch == b'`') {
+                    unclosed = true;
+                    avoid = run_lengths(text);
+                }
+                len
+            },
+
+            None => match find_math(after) {
+                Some((len, tex, display)) => {
+                    let span = code_span(tex, display, &avoid);
+                    if unclosed {
+                        avoid.push(span.chars().take_while(|&ch| ch == '`').count());
+                    }
+                    push_apart(&mut res, &span);
+                    rest = &after[len ..];
+                    continue;
+                },
+                None if after.starts_with("$$") => 2,
+                None => 1,
+            },
+        };
+
+        push_apart(&mut res, &after[.. len]);
+        rest = &after[len ..];
+    }
+
+    res.push_str(rest);
+    res
+}
+
+/// Appends `text`, with `SEPARATOR` in between if both sides have backticks
+/// there, which cmark would take for one run.
+fn push_apart(res: &mut String, text: &str) {
+    if res.ends_with('`') && text.starts_with('`') {
+        res.push(SEPARATOR);
+    }
+    res.push_str(text);
+}
+
+/// Whether the running text of a paragraph leaves display math open, so
+/// that the next line goes on with it, whatever block it looks like.
+fn in_display_math(text: &str) -> bool {
+    let mut rest = text;
+
+    while let Some(start) = rest.find(SPECIAL) {
+        let after = &rest[start ..];
+
+        let len = match verbatim_len(after) {
+            Some(len) => len,
+            None => match find_math(after) {
+                Some((len, _, _)) => len,
+                None if after.starts_with("$$") => return true,
+                None => 1,
+            },
+        };
+
+        rest = &after[len ..];
+    }
+
+    false
+}
+
+/// Characters that may start math, IPA, or something to leave alone.
+const SPECIAL: &[char] = &['\\', '`', '$', '<', ']'];
+
+/// Length of what `text` starts with if it is to be left alone: an escaped
+/// character, a code span, raw HTML, an autolink, a link destination, or just
+/// the character itself. `None` for a dollar sign.
+fn verbatim_len(text: &str) -> Option<usize> {
+    match text.as_bytes()[0] {
+        b'\\' => Some(1 + text[1 ..].chars().next().map(char::len_utf8).unwrap_or(0)),
+        b'`' => Some(code_span_len(text)),
+        b'<' => Some(autolink_len(text.as_bytes()).or_else(|| html_tag_len(text.as_bytes())).unwrap_or(1)),
+        b']' => Some(inline_link_len(text.as_bytes()).unwrap_or(1)),
+        _ => None,
+    }
+}
+
+/// The link reference definition at the start of `text`: where its label
+/// starts and ends, and its length up to the end of its last line.
+fn link_definition(text: &[u8]) -> Option<(usize, usize, usize)> {
+    let start = 1 + text.iter().take(3).take_while(|&&ch| ch == b' ').count();
+    if text.get(start - 1) != Some(&b'[') {
+        return None;
+    }
+
+    let end = start + escaped_len(&text[start ..], |ch| ch == b']' || ch == b'[');
+    if !text[end ..].starts_with(b"]:") {
+        return None;
+    }
+
+    let mut pos = end + 2;
+    pos += whitespace_len(&text[pos ..]);
+    pos += match link_destination_len(&text[pos ..]) {
+        0 => return None,
+        len => len,
+    };
+
+    //  the title is optional, and may be on the next line
+    let spaces = whitespace_len(&text[pos ..]);
+    if spaces > 0 {
+        if let Some(title) = link_title_len(&text[pos + spaces ..]) {
+            let title_end = pos + spaces + title;
+            if let Some(len) = blank_rest_len(&text[title_end ..]) {
+                return Some((start, end, title_end + len));
+            }
+        }
+    }
+
+    blank_rest_len(&text[pos ..]).map(|len| (start, end, pos + len))
+}
+
+/// Length of the rest of a line if it is blank, with the line break.
+fn blank_rest_len(text: &[u8]) -> Option<usize> {
+    let len = text.iter().take_while(|&&ch| ch == b' ' || ch == b'\t' || ch == b'\r').count();
+    match text.get(len) {
+        Some(&b'\n') => Some(len + 1),
+        Some(_) => None,
+        None => Some(len),
+    }
+}
+
+/// Length of the destination and title of an inline link, starting with the
+/// `](` right after the link text.
+fn inline_link_len(text: &[u8]) -> Option<usize> {
+    if !text.starts_with(b"](") {
+        return None;
+    }
+
+    let mut pos = 2 + whitespace_len(&text[2 ..]);
+    pos += link_destination_len(&text[pos ..]);
+
+    let spaces = whitespace_len(&text[pos ..]);
+    if spaces > 0 {
+        pos += spaces;
+        if let Some(len) = link_title_len(&text[pos ..]) {
+            pos += len;
+            pos += whitespace_len(&text[pos ..]);
+        }
+    }
+
+    match text.get(pos) {
+        Some(&b')') => Some(pos + 1),
+        _ => None,
+    }
+}
+
+/// Length of a link destination, either in angle brackets or a run of
+/// characters other than spaces, with balanced parentheses.
+fn link_destination_len(text: &[u8]) -> usize {
+    if text.first() == Some(&b'<') {
+        let len = 1 + escaped_len(&text[1 ..], |ch| ch == b'>' || ch == b'<' || ch == b'\n');
+        return match text.get(len) {
+            Some(&b'>') => len + 1,
+            _ => 0,
+        };
+    }
+
+    let mut depth = 0;
+    let mut pos = 0;
+    while pos < text.len() {
+        match text[pos] {
+            b'\\' if text.get(pos + 1).map_or(false, u8::is_ascii_punctuation) => pos += 1,
+            b'(' => depth += 1,
+            b')' if depth == 0 => break,
+            b')' => depth -= 1,
+            ch if ch <= b' ' => break,
+            _ => (),
+        };
+        pos += 1;
+    }
+
+    pos
+}
+
+fn link_title_len(text: &[u8]) -> Option<usize> {
+    let close = match text.first() {
+        Some(&b'"') => b'"',
+        Some(&b'\'') => b'\'',
+        Some(&b'(') => b')',
+        _ => return None,
+    };
+
+    let len = 1 + escaped_len(&text[1 ..], |ch| ch == close);
+    match text.get(len) {
+        Some(_) => Some(len + 1),
+        None => None,
+    }
+}
+
+/// Length of an autolink, e.g. `<https://example.com>` or
+/// `<someone@example.com>`.
+fn autolink_len(text: &[u8]) -> Option<usize> {
+    let end = text.iter().position(|&ch| ch == b'>')?;
+    let body = &text[1 .. end];
+
+    if body.iter().any(|&ch| ch <= b' ' || ch == b'<') {
+        return None;
+    }
+
+    let scheme = body.iter().take_while(|&&ch| ch.is_ascii_alphanumeric() || ch == b'+' || ch == b'.' || ch == b'-').count();
+    let uri = (2 ..= 32).contains(&scheme)
+        && body[0].is_ascii_alphabetic()
+        && body.get(scheme) == Some(&b':');
+    let email = body.contains(&b'@');
+
+    match uri || email {
+        true => Some(end + 1),
+        false => None,
+    }
+}
+
+/// Length of the raw HTML at the start of `text`: a tag, a comment, a
+/// processing instruction, a declaration or a CDATA section.
+fn html_tag_len(text: &[u8]) -> Option<usize> {
+    let special = [("<!--", "-->"), ("<?", "?>"), ("<![CDATA[", "]]>")];
+    for &(start, end) in &special {
+        if text.starts_with(start.as_bytes()) {
+            let body = &text[start.len() ..];
+            let len = body.windows(end.len()).position(|window| window == end.as_bytes())?;
+            return Some(start.len() + len + end.len());
+        }
+    }
+
+    if text.starts_with(b"<!") && text.get(2).map_or(false, u8::is_ascii_uppercase) {
+        return text.iter().position(|&ch| ch == b'>').map(|end| end + 1);
+    }
+
+    closing_tag_len(text).or_else(|| open_tag_len(text))
+}
+
+fn closing_tag_len(text: &[u8]) -> Option<usize> {
+    if !text.starts_with(b"</") {
+        return None;
+    }
+
+    let mut pos = 2 + tag_name_len(&text[2 ..])?;
+    pos += whitespace_len(&text[pos ..]);
+
+    match text.get(pos) {
+        Some(&b'>') => Some(pos + 1),
+        _ => None,
+    }
+}
+
+fn open_tag_len(text: &[u8]) -> Option<usize> {
+    if text.first() != Some(&b'<') {
+        return None;
+    }
+
+    let mut pos = 1 + tag_name_len(&text[1 ..])?;
+    loop {
+        let spaces = whitespace_len(&text[pos ..]);
+        let name = attribute_name_len(&text[pos + spaces ..]);
+        if spaces == 0 || name == 0 {
+            pos += spaces;
+            break;
+        }
+        pos += spaces + name;
+
+        let before_eq = whitespace_len(&text[pos ..]);
+        if text.get(pos + before_eq) == Some(&b'=') {
+            let start = pos + before_eq + 1;
+            let value = start + whitespace_len(&text[start ..]);
+            pos = value + attribute_value_len(&text[value ..])?;
+        }
+    }
+
+    match &text[pos ..] {
+        &[b'>', ..] => Some(pos + 1),
+        &[b'/', b'>', ..] => Some(pos + 2),
+        _ => None,
+    }
+}
+
+fn tag_name_len(text: &[u8]) -> Option<usize> {
+    match text.first().map_or(false, u8::is_ascii_alphabetic) {
+        true => Some(text.iter().take_while(|&&ch| ch.is_ascii_alphanumeric() || ch == b'-').count()),
+        false => None,
+    }
+}
+
+fn attribute_name_len(text: &[u8]) -> usize {
+    match text.first() {
+        Some(&ch) if ch.is_ascii_alphabetic() || ch == b'_' || ch == b':' => text.iter()
+            .take_while(|&&ch| ch.is_ascii_alphanumeric() || ch == b'_' || ch == b'.' || ch == b':' || ch == b'-')
+            .count(),
+        _ => 0,
+    }
+}
+
+fn attribute_value_len(text: &[u8]) -> Option<usize> {
+    match text.first() {
+        Some(&quote) if quote == b'"' || quote == b'\'' => text[1 ..].iter()
+            .position(|&ch| ch == quote)
+            .map(|end| end + 2),
+        _ => match text.iter().take_while(|&&ch| !b" \t\r\n\"'=<>`".contains(&ch)).count() {
+            0 => None,
+            len => Some(len),
+        },
+    }
+}
+
+/// Length of the whitespace at the start of `text`, with at most one line
+/// break.
+fn whitespace_len(text: &[u8]) -> usize {
+    let mut breaks = 0;
+    text.iter()
+        .take_while(|&&ch| match ch {
+            b'\n' => {
+                breaks += 1;
+                breaks == 1
+            },
+            _ => ch == b' ' || ch == b'\t' || ch == b'\r',
+        })
+        .count()
+}
+
+/// Length of the text before the first character `stop` takes, skipping the
+/// characters escaped with a backslash.
+fn escaped_len<F: Fn(u8) -> bool>(text: &[u8], stop: F) -> usize {
+    let mut pos = 0;
+    while pos < text.len() && !stop(text[pos]) {
+        pos += match text[pos] {
+            b'\\' if text.get(pos + 1).map_or(false, u8::is_ascii_punctuation) => 2,
+            _ => 1,
+        };
+    }
+    pos.min(text.len())
+}
+
+/// Lengths of the runs of backticks in a text.
+fn run_lengths(text: &str) -> Vec<usize> {
+    text.split(|ch| ch != '`')
+        .filter(|run| !run.is_empty())
+        .map(str::len)
+        .collect()
+}
+
+/// Length of the code span at the start of `text`, or of its opening
+/// backticks if it is not closed.
+fn code_span_len(text: &str) -> usize {
+    let ticks = text.chars().take_while(|&ch| ch == '`').count();
+    let mut pos = ticks;
+
+    while let Some(start) = text[pos ..].find('`') {
+        let start = pos + start;
+        let len = text[start ..].chars().take_while(|&ch| ch == '`').count();
+        if len == ticks {
+            return start + len;
+        }
+        pos = start + len;
+    }
+
+    ticks
+}
+
+/// Finds the math at the start of `text`, returning its length with the
+/// dollar signs, the TeX, and whether it is display math.
+fn find_math(text: &str) -> Option<(usize, &str, bool)> {
+    if text.starts_with("$$") {
+        let end = text[2 ..].find("$$")? + 2;
+        let tex = &text[2 .. end];
+        return match tex.trim().is_empty() {
+            true => None,
+            false => Some((end + 2, tex, true)),
+        };
+    }
+
+    let body = &text[1 ..];
+    if body.is_empty() || body.starts_with(char::is_whitespace) {
+        return None;
+    }
+
+    //  a backtick would start a code span, which goes first
+    let mut prev = ' ';
+    for (idx, ch) in body.char_indices() {
+        if ch == '`' {
+            return None;
+        }
+
+        let closes = ch == '$'
+            && !prev.is_whitespace()
+            && prev != '\\'
+            && !body[idx + 1 ..].starts_with(|ch: char| ch.is_ascii_digit());
+
+        if closes {
+            return Some((idx + 2, &body[.. idx], false));
+        }
+        prev = ch;
+    }
+
+    None
+}
+
+/// A marked code span holding the TeX, with a fence longer than any run of
+/// backticks in it, and of none of the lengths in `avoid`. Line breaks are
+/// kept, so that later lines keep their numbers, and the lines after them are
+/// indented, so that nothing in them starts a new block.
+fn code_span(tex: &str, display: bool, avoid: &[usize]) -> String {
+    let mark = match display {
+        true => DISPLAY_MARK,
+        false => INLINE_MARK,
+    };
+    let content = format!("{}{}", mark, tex.replace('\n', "\n    "));
+
+    let mut longest = 0;
+    let mut run = 0;
+    for ch in content.chars() {
+        run = match ch {
+            '`' => run + 1,
+            _ => 0,
+        };
+        longest = longest.max(run);
+    }
+
+    let mut len = longest + 1;
+    while avoid.contains(&len) {
+        len += 1;
+    }
+
+    let ticks = "`".repeat(len);
+    match content.ends_with('`') {
+        true => format!("{}{} {}", ticks, content, ticks),
+        false => format!("{}{}{}", ticks, content, ticks),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn protect(markdown: &[u8]) -> String {
+        let mut res = Vec::new();
+        Protect::new(markdown).read_to_end(&mut res).unwrap();
+        String::from_utf8_lossy(&res).into_owned()
+    }
+
+    #[test]
+    fn protect_math() {
+        assert_eq!(
+            protect(b"Let $x_1 = \\{a\\}$ cost $5 and `$y$`.\n"),
+            "Let `\u{FDD0}x_1 = \\{a\\}` cost $5 and `$y$`.\n"
+        );
+
+        let markdown = "$$\na_1\n- b\n$$\n\n```\n$z$\n```\nc\n";
+        let protected = protect(markdown.as_bytes());
+        assert_eq!(protected, "`\u{FDD1}\n    a_1\n    - b\n    `\n\n```\n$z$\n```\nc\n");
+        assert_eq!(protected.lines().count(), markdown.lines().count());
+
+        assert_eq!(protect(b"    $a$\n"), "    $a$\n");
+    }
+
+    #[test]
+    fn protect_containers() {
+        //  code in block quotes and list items stays code
+        for markdown in &["> ~~~\n> $y$\n> ~~~\n", "- item\n\n      $z$ code\n", "> a\n>\n>     $w$ code\n"] {
+            assert_eq!(protect(markdown.as_bytes()), *markdown);
+        }
+
+        //  display math goes on with the prefixes of the containers
+        assert_eq!(
+            protect(b"> a $x$\n> $$\n> - b\n$$\n"),
+            "> a `\u{FDD0}x`\n> `\u{FDD1}\n>     - b\n>     `\n"
+        );
+        assert_eq!(
+            protect(b">>- $$\n- b\n$$\n"),
+            ">>- `\u{FDD1}\n> >       - b\n> >       `\n"
+        );
+    }
+
+    #[test]
+    fn protect_html_and_links() {
+        let markdown = "<div>\nCosts $a$ here\n</div>\n\n[link](/a$b$c) <http://d/$e$> <span title=\"$f$\">\n";
+        assert_eq!(protect(markdown.as_bytes()), markdown);
+
+        //  labels of link reference definitions are marked like the links
+        assert_eq!(
+            protect(b"[$x$]: /a$b$\n  \"$c$\"\n[$x$](/d \"$e$\")\n"),
+            "[`\u{FDD0}x`]: /a$b$\n  \"$c$\"\n[`\u{FDD0}x`](/d \"$e$\")\n"
+        );
+    }
+
+    #[test]
+    fn protect_backtick_runs() {
+        //  next to other code spans, and apart from an unclosed run
+        assert_eq!(
+            protect(b"$a$$b$ `c`$d$\n"),
+            "`\u{FDD0}a`\u{FDD3}`\u{FDD0}b` `c`\u{FDD3}`\u{FDD0}d`\n"
+        );
+        assert_eq!(
+            protect(b"`a $b$ $c$ ``d``\n"),
+            "`a ```\u{FDD0}b``` ````\u{FDD0}c```` ``d``\n"
+        );
+    }
+
+    #[test]
+    fn protect_ipa_and_marks() {
+        assert_eq!(
+            protect("`ipa:a` ``ipa:b`` `\u{FDD0}c`\n".as_bytes()),
+            "`\u{FDD2}a` ``ipa:b`` `\u{FFFD}c`\n"
+        );
+
+        //  paragraphs that are not UTF-8 go through as they are
+        let mut res = Vec::new();
+        Protect::new(&b"a \xff $x$\n\nb $y$\n"[..]).read_to_end(&mut res).unwrap();
+        let mut expected = b"a \xff $x$\n\nb `".to_vec();
+        expected.extend_from_slice("\u{FDD0}y`\n".as_bytes());
+        assert_eq!(res, expected);
+    }
+
+    #[test]
+    fn special_spans() {
+        let mut parser = Parser::new(Options::DEFAULT);
+        parser.feed_reader(&mut Protect::new(&b"# On $x_1$\n\n`math:y` and `ipa:\"a` $$\nz\n$$\n"[..])).unwrap();
+        let mut document = Document::from_parser(parser).unwrap();
+        SpecialSpans::new().transform(&mut document).unwrap();
+
+        let heading = &document.root().children[0];
+        assert_eq!(heading.text(), "On x_1");
+        assert_eq!(heading.children[1].attributes, vec![("class".to_owned(), INLINE_CLASS.to_owned())]);
+
+        let paragraph = &document.root().children[1];
+        assert_eq!(paragraph.text(), "math:y and ˈa z");
+        assert!(paragraph.children[0].attributes.is_empty());
+        assert_eq!(paragraph.children[2].attributes, vec![("class".to_owned(), IPA_CLASS.to_owned())]);
+        assert_eq!(paragraph.children[4].attributes, vec![("class".to_owned(), DISPLAY_CLASS.to_owned())]);
+
+        let mut parser = Parser::new(Options::DEFAULT);
+        parser.feed_reader(&mut Protect::new(&b"$a$$b$\n"[..])).unwrap();
+        let mut document = Document::from_parser(parser).unwrap();
+        SpecialSpans::new().transform(&mut document).unwrap();
+        assert_eq!(document.root().text(), "ab");
+    }
+}
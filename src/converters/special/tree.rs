@@ -0,0 +1,402 @@
+/*
+ *  Syntax trees
+ *
+ *      ```tree
+ *      [CP [DP@wh what] [C' did [TP you [VP [V see] [DP t->wh]]]]]
+ *      ```
+ *
+ *  A node is written `[label children...]`, each child being either another
+ *  node or words. Words next to each other make up a single leaf. A leaf
+ *  starting with `^`, as in `[NP ^the old man]`, hangs under a triangle, for
+ *  a constituent whose structure is left out.
+ *
+ *  A label or leaf ending in `@name` names its node, and one ending in
+ *  `->name` draws a movement arrow from its node to the named one, under the
+ *  tree. The names themselves are not shown.
+ *
+ *  The tree is laid out into inline SVG as the post is built. We have no font
+ *  metrics, so the width of the text is guessed from its number of
+ *  characters.
+ */
+
+use converters::escape::escape_html;
+use converters::special::Lines;
+
+use std::cmp;
+use std::collections::HashMap;
+use std::iter::Peekable;
+
+/// Size of the text, in pixels.
+const FONT_SIZE: i32 = 16;
+
+/// Average width of a character, in pixels.
+const CHAR_WIDTH: i32 = 9;
+
+/// Distance from the baseline of the text to its top, and to its bottom.
+const ASCENT: i32 = 12;
+const DESCENT: i32 = 5;
+
+/// Distance between the baselines of a node and its children.
+const LEVEL_HEIGHT: i32 = 48;
+
+/// Room between siblings, and around the tree.
+const GAP: i32 = 16;
+const MARGIN: i32 = 8;
+
+/// How far below the lowest of its ends an arrow bends.
+const ARROW_DEPTH: i32 = 32;
+
+/// A node, or a leaf if it has no children.
+#[derive(Debug, Clone, PartialEq)]
+struct Node {
+    text: String,
+
+    /// Whether the node is a leaf hanging under a triangle.
+    triangle: bool,
+
+    name: Option<String>,
+
+    /// Name of the node a movement arrow goes to.
+    arrow: Option<String>,
+
+    children: Vec<Node>,
+}
+
+/// A syntax tree, parsed from bracket notation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxTree {
+    root: Node,
+
+    /// The bracket notation, as the text alternative of the drawing.
+    source: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Token<'a> {
+    Open,
+    Close,
+    Word(&'a str),
+}
+
+/// The elements of the drawing as they are laid out.
+struct Drawing {
+    branches: Vec<String>,
+    texts: Vec<String>,
+
+    /// Where arrows may end, by name: the middle of the node and the bottom
+    /// of its subtree.
+    names: HashMap<String, (i32, i32)>,
+
+    /// Where arrows start, and the names they go to.
+    arrows: Vec<((i32, i32), String)>,
+}
+
+impl Node {
+    fn new(text: &str) -> Self {
+        let (text, arrow) = split_suffix(text, "->");
+        let (text, name) = split_suffix(text, "@");
+
+        Self {
+            text: text.to_owned(),
+            triangle: false,
+            name: name,
+            arrow: arrow,
+            children: Vec::new(),
+        }
+    }
+
+    fn leaf(words: &[&str]) -> Self {
+        let mut res = Self::new(&words.join(" "));
+
+        if res.text.starts_with('^') {
+            res.text = res.text[1 ..].trim_start().to_owned();
+            res.triangle = true;
+        }
+
+        res
+    }
+
+    /// Parses a node whose `[` is already taken.
+    fn parse<'a, I>(tokens: &mut Peekable<I>) -> Option<Self>
+        where I: Iterator<Item = Token<'a>>
+    {
+        let mut res = match tokens.peek() {
+            Some(&Token::Word(label)) => {
+                tokens.next();
+                Self::new(label)
+            },
+            _ => Self::new(""),
+        };
+
+        let mut words: Vec<&str> = Vec::new();
+        loop {
+            let token = match tokens.next()? {
+                Token::Word(word) => {
+                    words.push(word);
+                    continue;
+                },
+                token => token,
+            };
+
+            if !words.is_empty() {
+                res.children.push(Self::leaf(&words));
+                words.clear();
+            }
+
+            match token {
+                Token::Open => res.children.push(Self::parse(tokens)?),
+                _ => return Some(res),
+            };
+        }
+    }
+
+    fn width(&self) -> i32 {
+        let children = self.children.iter().map(Node::width).sum::<i32>()
+            + GAP * cmp::max(self.children.len() as i32 - 1, 0);
+
+        cmp::max(text_width(&self.text), children)
+    }
+
+    fn descendants(&self) -> Vec<&Node> {
+        let mut res = vec![self];
+        for child in &self.children {
+            res.extend(child.descendants());
+        }
+        res
+    }
+
+    /// Lays out the subtree with its left edge at `left`, and returns the
+    /// middle of the node and the bottom of the subtree.
+    fn layout(&self, left: i32, depth: i32, drawing: &mut Drawing) -> (i32, i32) {
+        let width = self.width();
+        let y = MARGIN + ASCENT + depth * LEVEL_HEIGHT;
+
+        let (x, bottom) = match self.children.is_empty() {
+            true => (left + width / 2, y),
+
+            false => {
+                let children = self.children.iter().map(Node::width).sum::<i32>()
+                    + GAP * (self.children.len() as i32 - 1);
+
+                let mut child_left = left + (width - children) / 2;
+                let mut middles: Vec<i32> = Vec::new();
+                let mut bottom = y;
+
+                for child in &self.children {
+                    let (middle, child_bottom) = child.layout(child_left, depth + 1, drawing);
+                    middles.push(middle);
+                    bottom = cmp::max(bottom, child_bottom);
+                    child_left += child.width() + GAP;
+                }
+
+                let x = (middles[0] + middles[middles.len() - 1]) / 2;
+                let top = y + LEVEL_HEIGHT - ASCENT;
+
+                for (child, middle) in self.children.iter().zip(middles) {
+                    match child.triangle {
+                        true => {
+                            let half = text_width(&child.text) / 2;
+                            drawing.branches.push(format!(
+                                "<path d=\"M{} {} L{} {} L{} {} Z\"/>",
+                                x, y + DESCENT, middle - half, top, middle + half, top
+                            ));
+                        },
+
+                        false => drawing.branches.push(format!(
+                            "<line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\"/>",
+                            x, y + DESCENT, middle, top
+                        )),
+                    };
+                }
+
+                (x, bottom)
+            },
+        };
+
+        let class = match self.children.is_empty() {
+            true => "tree-leaf",
+            false => "tree-label",
+        };
+        drawing.texts.push(format!(
+            "<text class=\"{}\" x=\"{}\" y=\"{}\">{}</text>",
+            class, x, y, escape_html(&self.text)
+        ));
+
+        if let Some(ref name) = self.name {
+            drawing.names.insert(name.clone(), (x, bottom));
+        }
+        if let Some(ref arrow) = self.arrow {
+            drawing.arrows.push(((x, bottom), arrow.clone()));
+        }
+
+        (x, bottom)
+    }
+}
+
+impl SyntaxTree {
+
+    /// Parses a tree from bracket notation, or says what is wrong with it.
+    pub fn parse(text: &str) -> Result<Self, &'static str> {
+        let mut tokens = tokens(text).into_iter().peekable();
+
+        let root = match tokens.next() {
+            Some(Token::Open) => Node::parse(&mut tokens),
+            _ => None,
+        };
+
+        let root = match (root, tokens.next()) {
+            (Some(root), None) => root,
+            _ => return Err("The brackets of this tree do not match"),
+        };
+
+        let nodes = root.descendants();
+        let known = |name: &String| nodes.iter().any(|node| node.name.as_ref() == Some(name));
+        if nodes.iter().filter_map(|node| node.arrow.as_ref()).any(|name| !known(name)) {
+            return Err("This tree has an arrow to a name it does not have");
+        }
+
+        Ok(Self {
+            root: root,
+            source: text.split_whitespace().collect::<Vec<_>>().join(" "),
+        })
+    }
+
+    /// Draws the tree as an SVG image.
+    pub fn to_lines(&self) -> Lines {
+        let mut drawing = Drawing {
+            branches: Vec::new(),
+            texts: Vec::new(),
+            names: HashMap::new(),
+            arrows: Vec::new(),
+        };
+
+        let (_, bottom) = self.root.layout(MARGIN, 0, &mut drawing);
+        let width = self.root.width() + 2 * MARGIN;
+        let mut height = bottom + DESCENT + MARGIN;
+
+        let mut arrows: Vec<String> = Vec::new();
+        for &((x1, y1), ref name) in &drawing.arrows {
+            let (x2, y2) = drawing.names[name];
+            let (y1, y2) = (y1 + DESCENT + 2, y2 + DESCENT + 2);
+            let low = cmp::max(y1, y2) + ARROW_DEPTH;
+
+            arrows.push(format!(
+                "<path d=\"M{} {} C{} {} {} {} {} {}\"/>",
+                x1, y1, x1, low, x2, low, x2, y2 + 6
+            ));
+            arrows.push(format!(
+                "<path class=\"tree-arrowhead\" d=\"M{} {} L{} {} L{} {} Z\" fill=\"currentColor\"/>",
+                x2, y2, x2 - 4, y2 + 8, x2 + 4, y2 + 8
+            ));
+
+            height = cmp::max(height, low + MARGIN);
+        }
+
+        let mut res = Lines::new();
+
+        res.push(0, "<div class=\"syntax-tree\">");
+        res.push(1, format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" viewBox=\"0 0 {} {}\" \
+role=\"img\" aria-label=\"{}\" font-size=\"{}\" text-anchor=\"middle\" fill=\"currentColor\">",
+            width, height, width, height, escape_html(&self.source), FONT_SIZE
+        ));
+
+        res.push(2, "<g class=\"tree-branches\" stroke=\"currentColor\" fill=\"none\">");
+        for branch in drawing.branches {
+            res.push(3, branch);
+        }
+        res.push(2, "</g>");
+
+        if !arrows.is_empty() {
+            res.push(2, "<g class=\"tree-arrows\" stroke=\"currentColor\" fill=\"none\">");
+            for arrow in arrows {
+                res.push(3, arrow);
+            }
+            res.push(2, "</g>");
+        }
+
+        for text in drawing.texts {
+            res.push(2, text);
+        }
+
+        res.push(1, "</svg>");
+        res.push(0, "</div>");
+
+        res
+    }
+
+}
+
+fn tokens(text: &str) -> Vec<Token<'_>> {
+    let mut res: Vec<Token> = Vec::new();
+    let mut start: Option<usize> = None;
+
+    for (idx, ch) in text.char_indices() {
+        let token = match ch {
+            '[' => Some(Token::Open),
+            ']' => Some(Token::Close),
+            _ if ch.is_whitespace() => None,
+            _ => {
+                start = start.or(Some(idx));
+                continue;
+            },
+        };
+
+        if let Some(start) = start.take() {
+            res.push(Token::Word(&text[start .. idx]));
+        }
+        res.extend(token);
+    }
+
+    if let Some(start) = start {
+        res.push(Token::Word(&text[start ..]));
+    }
+
+    res
+}
+
+/// Takes a name after `mark` off the end of `text`.
+fn split_suffix<'a>(text: &'a str, mark: &str) -> (&'a str, Option<String>) {
+    match text.rfind(mark) {
+        Some(idx) if is_name(&text[idx + mark.len() ..]) =>
+            (&text[.. idx], Some(text[idx + mark.len() ..].to_owned())),
+        _ => (text, None),
+    }
+}
+
+fn is_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|ch| ch.is_alphanumeric() || ch == '-' || ch == '_')
+}
+
+fn text_width(text: &str) -> i32 {
+    text.chars().count() as i32 * CHAR_WIDTH
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bracket_notation() {
+        let tree = SyntaxTree::parse("[S [NP@x ^the old cat] [VP sat [PP on it->x]]]").unwrap();
+        let root = &tree.root;
+
+        assert_eq!(root.text, "S");
+        assert_eq!(root.children[0].name, Some("x".to_owned()));
+        assert_eq!(root.children[0].children[0].text, "the old cat");
+        assert!(root.children[0].children[0].triangle);
+
+        let vp = &root.children[1];
+        assert_eq!(vp.children.len(), 2);
+        assert_eq!(vp.children[0].text, "sat");
+        assert_eq!(vp.children[1].children[0].text, "on it");
+        assert_eq!(vp.children[1].children[0].arrow, Some("x".to_owned()));
+    }
+
+    #[test]
+    fn malformed_trees() {
+        assert!(SyntaxTree::parse("[S [NP the cat]").is_err());
+        assert!(SyntaxTree::parse("[S [NP the cat]]]").is_err());
+        assert!(SyntaxTree::parse("[S [NP the cat->y]]").is_err());
+    }
+}
@@ -0,0 +1,577 @@
+/*
+ *  Page templates
+ *
+ *  A small mustache-like language for the page around a converted post. The
+ *  built-in templates are those of the default theme.
+ *
+ *      {{ name }}                          the variable, HTML-escaped
+ *      {{{ name }}}                        the variable, as is
+ *      {{> name }}                         the partial partials/name.html
+ *      {{#if name}} .. {{else}} .. {{/if}} whether the variable is set and
+ *                                          not empty, false or zero
+ *      {{#each name}} .. {{/each}}         once per item of a list, which is
+ *                                          then called `this`
+ *      {{! comment }}
+ *
+ *  Names can be dotted to look into maps, e.g. `{{ series.part }}`. Missing
+ *  variables are empty. Any tag other than `{{ name }}` alone on its line
+ *  takes the whole line with it, and a partial or a value alone on its line
+ *  is indented like its tag.
+ *
+ *  `{{{ content }}}` is the converted post. It is rendered only when the
+ *  template gets to it, so that the renderer itself indents it to the column
+ *  of the tag while leaving `<pre>` blocks alone. That only works in the
+ *  layout itself; a partial with the content in it would indent everything.
+ */
+
+use converters::escape::escape_html;
+
+use front_matter::Value;
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{ Path, PathBuf };
+use std::vec;
+
+/// Variables available to a template, by name.
+pub type Context = BTreeMap<String, Value>;
+
+/// The layouts and partials of the default theme, used whenever a theme leaves
+/// some of them out.
+const BUILTIN_LAYOUTS: &[(&str, &str)] = &[
+    ("default", include_str!("../../themes/default/templates/default.html")),
+];
+
+const BUILTIN_PARTIALS: &[(&str, &str)] = &[
+    ("head", include_str!("../../themes/default/templates/partials/head.html")),
+];
+
+/// Partials nested deeper than this are assumed to include themselves.
+const MAX_DEPTH: usize = 16;
+
+/// Errors while loading templates. Each comes with the name of the template.
+#[derive(Debug)]
+pub enum TemplateErr {
+    /// A template cannot be read.
+    Io(PathBuf, io::Error),
+
+    /// A `{{` without its `}}`.
+    Unterminated(String),
+
+    /// Something between `{{` and `}}` that is not a tag.
+    BadTag(String, String),
+
+    /// A block tag that is never closed.
+    Unclosed(String, String),
+
+    /// A closing tag, or `{{else}}`, that does not belong to an open block.
+    Unexpected(String, String),
+
+    /// A template includes a partial that does not exist.
+    UnknownPartial(String, String),
+}
+
+impl fmt::Display for TemplateErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &TemplateErr::Io(ref path, ref e) =>
+                write!(f, "cannot read {}: {}", path.display(), e),
+            &TemplateErr::Unterminated(ref name) =>
+                write!(f, "{}: {{{{ without }}}}", name),
+            &TemplateErr::BadTag(ref name, ref tag) =>
+                write!(f, "{}: bad tag {{{{{}}}}}", name, tag),
+            &TemplateErr::Unclosed(ref name, ref tag) =>
+                write!(f, "{}: {{{{{}}}}} is never closed", name, tag),
+            &TemplateErr::Unexpected(ref name, ref tag) =>
+                write!(f, "{}: unexpected {{{{{}}}}}", name, tag),
+            &TemplateErr::UnknownPartial(ref name, ref partial) =>
+                write!(f, "{}: there is no partial named {}", name, partial),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Tag {
+    Var(String),
+    Raw(String),
+    Partial(String),
+    If(String),
+    Else,
+    EndIf,
+    Each(String),
+    EndEach,
+    Comment,
+}
+
+/// A tag comes with the indentation of its line if it is alone on it.
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Text(String),
+    Tag(Tag, Option<String>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Text(String),
+    Var(String),
+    Raw(String, Option<String>),
+    Partial(String, Option<String>),
+    If(String, Vec<Node>, Vec<Node>),
+    Each(String, Vec<Node>),
+}
+
+/// A set of named layouts and the partials they include. There is always a
+/// layout named `default`.
+pub struct Templates {
+    layouts: BTreeMap<String, Vec<Node>>,
+    partials: BTreeMap<String, Vec<Node>>,
+}
+
+impl Templates {
+
+    /// The templates built into the executable.
+    pub fn builtin() -> Self {
+        let parse_all = |templates: &[(&str, &str)]| templates.iter()
+            .map(|&(name, text)| match parse(name, text) {
+                Ok(nodes) => (name.to_owned(), nodes),
+                Err(e) => panic!("Built-in template is broken: {}", e),
+            })
+            .collect();
+
+        Self {
+            layouts: parse_all(BUILTIN_LAYOUTS),
+            partials: parse_all(BUILTIN_PARTIALS),
+        }
+    }
+
+    /// Loads the layouts in `dir/*.html` and the partials in
+    /// `dir/partials/*.html` of each directory in turn, on top of the built-in
+    /// ones, so that later directories override single templates of earlier
+    /// ones. Fails on the first template that does not parse, or that
+    /// includes a missing partial.
+    pub fn load(dirs: &[PathBuf]) -> Result<Self, TemplateErr> {
+        let mut res = Self::builtin();
+
+        for dir in dirs {
+            Self::load_dir(dir, &mut res.layouts)?;
+
+            let partials_dir = dir.join("partials");
+            if partials_dir.is_dir() {
+                Self::load_dir(&partials_dir, &mut res.partials)?;
+            }
+        }
+
+        res.check()?;
+
+        Ok(res)
+    }
+
+    fn load_dir(
+        dir: &Path,
+        templates: &mut BTreeMap<String, Vec<Node>>
+    ) -> Result<(), TemplateErr> {
+        let iter = fs::read_dir(dir)
+            .map_err(|e| TemplateErr::Io(dir.to_path_buf(), e))?;
+
+        for entry in iter {
+            let path = entry.map_err(|e| TemplateErr::Io(dir.to_path_buf(), e))?.path();
+
+            let name = match (path.extension(), path.file_stem()) {
+                (Some(ext), Some(stem)) if ext == "html" => stem.to_string_lossy().into_owned(),
+                _ => continue,
+            };
+
+            let text = fs::read_to_string(&path)
+                .map_err(|e| TemplateErr::Io(path.clone(), e))?;
+
+            info!("Loading template {}...", path.display());
+            templates.insert(name.clone(), parse(&name, &text)?);
+        }
+
+        Ok(())
+    }
+
+    /// Checks that every partial that is included exists.
+    fn check(&self) -> Result<(), TemplateErr> {
+        fn check_nodes(
+            templates: &Templates,
+            name: &str,
+            nodes: &[Node]
+        ) -> Result<(), TemplateErr> {
+            for node in nodes {
+                match node {
+                    &Node::Partial(ref partial, _) if !templates.partials.contains_key(partial) =>
+                        return Err(TemplateErr::UnknownPartial(name.to_owned(), partial.clone())),
+
+                    &Node::If(_, ref then, ref otherwise) => {
+                        check_nodes(templates, name, then)?;
+                        check_nodes(templates, name, otherwise)?;
+                    },
+
+                    &Node::Each(_, ref body) => check_nodes(templates, name, body)?,
+
+                    _ => (),
+                };
+            }
+
+            Ok(())
+        }
+
+        for (name, nodes) in self.layouts.iter().chain(self.partials.iter()) {
+            check_nodes(self, name, nodes)?;
+        }
+
+        Ok(())
+    }
+
+    /// Renders the layout called `layout`, or the default one if there is no
+    /// such layout. `content` is called with the indentation of the
+    /// `{{{ content }}}` tag, empty if the tag is not alone on its line, and
+    /// returns the text to put in its place as is.
+    pub fn render<F, E>(
+        &self,
+        layout: &str,
+        context: &Context,
+        mut content: F
+    ) -> Result<String, E>
+        where F: FnMut(&str) -> Result<String, E>
+    {
+        let nodes = match self.layouts.get(layout) {
+            Some(res) => res,
+            None => {
+                warn!("There is no layout named {}, using the default one", layout);
+                &self.layouts["default"]
+            },
+        };
+
+        let mut res = String::new();
+        self.render_nodes(nodes, context, None, &mut content, &mut res, 0)?;
+
+        Ok(res)
+    }
+
+    fn render_nodes<F, E>(
+        &self,
+        nodes: &[Node],
+        context: &Context,
+        this: Option<&Value>,
+        content: &mut F,
+        out: &mut String,
+        depth: usize
+    ) -> Result<(), E>
+        where F: FnMut(&str) -> Result<String, E>
+    {
+        for node in nodes {
+            match node {
+                &Node::Text(ref text) => out.push_str(text),
+
+                &Node::Var(ref name) => if let Some(value) = lookup(context, this, name) {
+                    out.push_str(&escape_html(&value.to_string()));
+                },
+
+                &Node::Raw(ref name, ref indent) if name == "content" => {
+                    let indent = indent.as_ref().map(String::as_str).unwrap_or("");
+                    out.push_str(&content(indent)?);
+                },
+
+                &Node::Raw(ref name, ref indent) => if let Some(value) = lookup(context, this, name) {
+                    push_indented(out, &value.to_string(), indent);
+                },
+
+                &Node::Partial(_, _) if depth >= MAX_DEPTH => {
+                    warn!("Partials are nested too deep, skipping the rest");
+                },
+
+                &Node::Partial(ref name, ref indent) => {
+                    let mut partial = String::new();
+                    self.render_nodes(&self.partials[name], context, this, content, &mut partial, depth + 1)?;
+                    push_indented(out, &partial, indent);
+                },
+
+                &Node::If(ref name, ref then, ref otherwise) => {
+                    let branch = match lookup(context, this, name).map(is_truthy) {
+                        Some(true) => then,
+                        _ => otherwise,
+                    };
+                    self.render_nodes(branch, context, this, content, out, depth)?;
+                },
+
+                &Node::Each(ref name, ref body) => if let Some(&Value::List(ref items)) = lookup(context, this, name) {
+                    for item in items {
+                        self.render_nodes(body, context, Some(item), content, out, depth)?;
+                    }
+                },
+            };
+        }
+
+        Ok(())
+    }
+
+}
+
+/// Looks up a dotted name, first in the current item of an `{{#each}}` and
+/// then in the context.
+fn lookup<'a>(context: &'a Context, this: Option<&'a Value>, name: &str) -> Option<&'a Value> {
+    let mut parts = name.split('.');
+    let first = parts.next()?;
+
+    let mut value = match (first, this) {
+        ("this", Some(value)) => value,
+        (_, Some(&Value::Map(ref map))) if map.contains_key(first) => &map[first],
+        _ => context.get(first)?,
+    };
+
+    for part in parts {
+        value = match value {
+            &Value::Map(ref map) => map.get(part)?,
+            _ => return None,
+        };
+    }
+
+    Some(value)
+}
+
+fn is_truthy(value: &Value) -> bool {
+    match value {
+        &Value::String(ref s) => !s.is_empty(),
+        &Value::Bool(b) => b,
+        &Value::Integer(i) => i != 0,
+        &Value::Float(x) => x != 0.0,
+        &Value::List(ref values) => !values.is_empty(),
+        &Value::Map(ref map) => !map.is_empty(),
+    }
+}
+
+/// Appends `text`, with each non-empty line indented by `indent` if the tag
+/// was alone on its line.
+fn push_indented(out: &mut String, text: &str, indent: &Option<String>) {
+    match indent {
+        &Some(ref indent) => for line in text.lines() {
+            if !line.trim().is_empty() {
+                out.push_str(indent);
+            }
+            out.push_str(line);
+            out.push('\n');
+        },
+
+        &None => out.push_str(text),
+    }
+}
+
+/*
+ *  Parsing
+ */
+
+fn parse(name: &str, text: &str) -> Result<Vec<Node>, TemplateErr> {
+    let mut tokens = tokenize(name, text)?.into_iter();
+
+    match build(name, &mut tokens)? {
+        (nodes, None) => Ok(nodes),
+        (_, Some(tag)) => Err(TemplateErr::Unexpected(name.to_owned(), tag_text(&tag))),
+    }
+}
+
+fn is_name(name: &str) -> bool {
+    !name.is_empty() && name.chars()
+        .all(|ch| ch.is_ascii_alphanumeric() || ch == '-' || ch == '_' || ch == '.')
+}
+
+fn is_blank(text: &str) -> bool {
+    text.chars().all(char::is_whitespace)
+}
+
+fn parse_tag(name: &str, inner: &str) -> Result<Tag, TemplateErr> {
+    let bad = || TemplateErr::BadTag(name.to_owned(), inner.to_owned());
+
+    let (tag, arg) = match inner.find(char::is_whitespace) {
+        Some(idx) => (&inner[.. idx], inner[idx ..].trim()),
+        None => (inner, ""),
+    };
+
+    match (tag, arg) {
+        _ if inner.starts_with('!') => Ok(Tag::Comment),
+        _ if inner.starts_with('>') && is_name(inner[1 ..].trim()) =>
+            Ok(Tag::Partial(inner[1 ..].trim().to_owned())),
+
+        ("#if", arg) if is_name(arg) => Ok(Tag::If(arg.to_owned())),
+        ("#each", arg) if is_name(arg) => Ok(Tag::Each(arg.to_owned())),
+        ("else", "") => Ok(Tag::Else),
+        ("/if", "") => Ok(Tag::EndIf),
+        ("/each", "") => Ok(Tag::EndEach),
+        (tag, "") if is_name(tag) => Ok(Tag::Var(tag.to_owned())),
+
+        _ => Err(bad()),
+    }
+}
+
+fn tag_text(tag: &Tag) -> String {
+    match tag {
+        &Tag::Var(ref name) | &Tag::Raw(ref name) => name.clone(),
+        &Tag::Partial(ref name) => format!("> {}", name),
+        &Tag::If(ref name) => format!("#if {}", name),
+        &Tag::Else => "else".to_owned(),
+        &Tag::EndIf => "/if".to_owned(),
+        &Tag::Each(ref name) => format!("#each {}", name),
+        &Tag::EndEach => "/each".to_owned(),
+        &Tag::Comment => "!".to_owned(),
+    }
+}
+
+/// Splits a template into text and tags, and takes the lines of standalone
+/// tags out of the text.
+fn tokenize(name: &str, text: &str) -> Result<Vec<Token>, TemplateErr> {
+    let mut res: Vec<Token> = Vec::new();
+    let mut pos = 0;
+
+    while let Some(offset) = text[pos ..].find("{{") {
+        let start = pos + offset;
+        let close = match text[start ..].starts_with("{{{") {
+            true => "}}}",
+            false => "}}",
+        };
+
+        let end = match text[start + close.len() ..].find(close) {
+            Some(idx) => start + close.len() + idx + close.len(),
+            None => return Err(TemplateErr::Unterminated(name.to_owned())),
+        };
+
+        let inner = text[start + close.len() .. end - close.len()].trim();
+        let tag = match close {
+            "}}}" if is_name(inner) => Tag::Raw(inner.to_owned()),
+            "}}}" => return Err(TemplateErr::BadTag(name.to_owned(), inner.to_owned())),
+            _ => parse_tag(name, inner)?,
+        };
+
+        //  a tag is standalone if nothing else, not even another tag, is on
+        //  its line
+        let line_start = text[.. start].rfind('\n').map(|idx| idx + 1).unwrap_or(0);
+        let line_end = text[end ..].find('\n').map(|idx| end + idx + 1).unwrap_or(text.len());
+        let standalone = match tag {
+            Tag::Var(_) => false,
+            _ => line_start >= pos
+                && is_blank(&text[line_start .. start])
+                && is_blank(&text[end .. line_end]),
+        };
+
+        let (text_end, indent, next) = match standalone {
+            true => (line_start, Some(text[line_start .. start].to_owned()), line_end),
+            false => (start, None, end),
+        };
+
+        if text_end > pos {
+            res.push(Token::Text(text[pos .. text_end].to_owned()));
+        }
+        res.push(Token::Tag(tag, indent));
+        pos = next;
+    }
+
+    if pos < text.len() {
+        res.push(Token::Text(text[pos ..].to_owned()));
+    }
+
+    Ok(res)
+}
+
+/// Builds nodes out of tokens up to the end, or up to a closing tag or
+/// `{{else}}`, which is handed back for the caller to check.
+fn build(
+    name: &str,
+    tokens: &mut vec::IntoIter<Token>
+) -> Result<(Vec<Node>, Option<Tag>), TemplateErr> {
+    let mut res: Vec<Node> = Vec::new();
+
+    while let Some(token) = tokens.next() {
+        let (tag, indent) = match token {
+            Token::Text(text) => {
+                res.push(Node::Text(text));
+                continue;
+            },
+            Token::Tag(tag, indent) => (tag, indent),
+        };
+
+        match tag {
+            Tag::Var(var) => res.push(Node::Var(var)),
+            Tag::Raw(var) => res.push(Node::Raw(var, indent)),
+            Tag::Partial(partial) => res.push(Node::Partial(partial, indent)),
+            Tag::Comment => (),
+
+            Tag::If(var) => {
+                let unclosed = || TemplateErr::Unclosed(name.to_owned(), format!("#if {}", var));
+
+                let (then, otherwise) = match build(name, tokens)? {
+                    (then, Some(Tag::EndIf)) => (then, Vec::new()),
+                    (then, Some(Tag::Else)) => match build(name, tokens)? {
+                        (otherwise, Some(Tag::EndIf)) => (then, otherwise),
+                        _ => return Err(unclosed()),
+                    },
+                    _ => return Err(unclosed()),
+                };
+
+                res.push(Node::If(var, then, otherwise));
+            },
+
+            Tag::Each(var) => match build(name, tokens)? {
+                (body, Some(Tag::EndEach)) => res.push(Node::Each(var, body)),
+                _ => return Err(TemplateErr::Unclosed(name.to_owned(), format!("#each {}", var))),
+            },
+
+            tag @ Tag::Else | tag @ Tag::EndIf | tag @ Tag::EndEach =>
+                return Ok((res, Some(tag))),
+        };
+    }
+
+    Ok((res, None))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(text: &str, context: &Context) -> String {
+        let templates = Templates {
+            layouts: vec![("default".to_owned(), parse("test", text).unwrap())].into_iter().collect(),
+            partials: vec![("item".to_owned(), parse("item", "<li>{{ this }}</li>\n").unwrap())].into_iter().collect(),
+        };
+
+        templates.render("default", context, |indent| -> Result<String, ()> {
+            Ok(format!("{}<p>content</p>\n", indent))
+        }).unwrap()
+    }
+
+    fn context() -> Context {
+        let mut res = Context::new();
+        res.insert("title".to_owned(), Value::String("A & B".to_owned()));
+        res.insert("tags".to_owned(), Value::List(vec![
+            Value::String("x".to_owned()),
+            Value::String("y".to_owned()),
+        ]));
+        res
+    }
+
+    #[test]
+    fn variables() {
+        assert_eq!(render("<h1>{{ title }}</h1>{{{title}}}{{ missing }}", &context()), "<h1>A &amp; B</h1>A & B");
+    }
+
+    #[test]
+    fn standalone_tags_take_their_lines() {
+        let text = "<ul>\n    {{#each tags}}\n    {{> item }}\n    {{/each}}\n</ul>\n{{#if draft}}\nDraft\n{{else}}\n  {{{ content }}}\n{{/if}}\n";
+
+        assert_eq!(render(text, &context()), "<ul>\n    <li>x</li>\n    <li>y</li>\n</ul>\n  <p>content</p>\n");
+    }
+
+    #[test]
+    fn unclosed_blocks() {
+        match parse("test", "{{#if title}}{{#each tags}}{{/if}}") {
+            Err(TemplateErr::Unclosed(_, ref tag)) => assert_eq!(tag, "#each tags"),
+            res => panic!("{:?}", res.err()),
+        }
+
+        match parse("test", "{{/each}}") {
+            Err(TemplateErr::Unexpected(_, ref tag)) => assert_eq!(tag, "/each"),
+            res => panic!("{:?}", res.err()),
+        }
+    }
+}
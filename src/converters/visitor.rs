@@ -0,0 +1,231 @@
+/*
+ *  Node visitors for the HTML converters
+ *
+ *  The HTML converters walk the document and pass each node to a Visitor,
+ *  along with the HtmlRenderer that holds the rendering state. To render one
+ *  kind of node differently, implement Visitor, override the matching
+ *  visit_* method, and hand the visitor to the converter with set_visitor:
+ *
+ *      struct PlainCode;
+ *
+ *      impl Visitor for PlainCode {
+ *          fn visit_code_block(
+ *              &mut self,
+ *              html: &mut HtmlRenderer,
+ *              info: &InfoString,
+ *              lit: &Literal,
+ *              span: &SourceSpan,
+ *              writer: &mut Output
+ *          ) -> io::Result<()> {
+ *              match info.strip().as_str() {
+ *                  "plain" => write!(writer, "<div class=\"plain\">{}</div>\n", lit),
+ *                  _ => html.convert_code_block(info, lit, span, writer),
+ *              }
+ *          }
+ *      }
+ */
+
+use converters::html::{ HtmlRenderer, Output };
+
+use cmark::*;
+
+use std::io;
+use std::path::Path;
+
+/// Decides how each node is rendered to HTML. Every method defaults to the
+/// matching `HtmlRenderer::convert_*`, so an implementation only overrides
+/// the nodes it wants to render differently, and can still fall back to
+/// `html` for the cases it does not care about.
+///
+/// Every hook is given the source span of its node, for log messages. cmark
+/// does not track the position of every inline, so some spans are unknown.
+pub trait Visitor {
+
+    /// Called with the whole document before any of its nodes, for visitors
+    /// that need to look ahead. `source` is where the document comes from,
+    /// for log messages.
+    fn begin(&mut self, _document: &Document, _source: &Path) {}
+
+    fn visit_blockquote(
+        &mut self,
+        html: &mut HtmlRenderer,
+        span: &SourceSpan,
+        event: &Event,
+        writer: &mut Output
+    ) -> io::Result<()> {
+        html.convert_blockquote(span, event, writer)
+    }
+
+    fn visit_list(
+        &mut self,
+        html: &mut HtmlRenderer,
+        ty: &ListType,
+        delim: &DelimType,
+        start: &StartingNumber,
+        tightness: &Tightness,
+        span: &SourceSpan,
+        event: &Event,
+        writer: &mut Output
+    ) -> io::Result<()> {
+        html.convert_list(ty, delim, start, tightness, span, event, writer)
+    }
+
+    fn visit_item(
+        &mut self,
+        html: &mut HtmlRenderer,
+        span: &SourceSpan,
+        event: &Event,
+        writer: &mut Output
+    ) -> io::Result<()> {
+        html.convert_item(span, event, writer)
+    }
+
+    fn visit_code_block(
+        &mut self,
+        html: &mut HtmlRenderer,
+        info: &InfoString,
+        lit: &Literal,
+        span: &SourceSpan,
+        writer: &mut Output
+    ) -> io::Result<()> {
+        html.convert_code_block(info, lit, span, writer)
+    }
+
+    fn visit_html_block(
+        &mut self,
+        html: &mut HtmlRenderer,
+        lit: &Literal,
+        _span: &SourceSpan,
+        writer: &mut Output
+    ) -> io::Result<()> {
+        html.convert_html_block(lit, writer)
+    }
+
+    fn visit_paragraph(
+        &mut self,
+        html: &mut HtmlRenderer,
+        span: &SourceSpan,
+        event: &Event,
+        writer: &mut Output
+    ) -> io::Result<()> {
+        html.convert_paragraph(span, event, writer)
+    }
+
+    fn visit_heading(
+        &mut self,
+        html: &mut HtmlRenderer,
+        lvl: &HeadingLevel,
+        span: &SourceSpan,
+        event: &Event,
+        writer: &mut Output
+    ) -> io::Result<()> {
+        html.convert_heading(lvl, span, event, writer)
+    }
+
+    fn visit_thematic_break(
+        &mut self,
+        html: &mut HtmlRenderer,
+        span: &SourceSpan,
+        writer: &mut Output
+    ) -> io::Result<()> {
+        html.convert_thematic_break(span, writer)
+    }
+
+    fn visit_text(
+        &mut self,
+        html: &mut HtmlRenderer,
+        lit: &Literal,
+        _span: &SourceSpan,
+        writer: &mut Output
+    ) -> io::Result<()> {
+        html.convert_text(lit, writer)
+    }
+
+    fn visit_soft_break(
+        &mut self,
+        html: &mut HtmlRenderer,
+        _span: &SourceSpan,
+        writer: &mut Output
+    ) -> io::Result<()> {
+        html.convert_soft_break(writer)
+    }
+
+    fn visit_line_break(
+        &mut self,
+        html: &mut HtmlRenderer,
+        _span: &SourceSpan,
+        writer: &mut Output
+    ) -> io::Result<()> {
+        html.convert_line_break(writer)
+    }
+
+    fn visit_code(
+        &mut self,
+        html: &mut HtmlRenderer,
+        lit: &Literal,
+        _span: &SourceSpan,
+        writer: &mut Output
+    ) -> io::Result<()> {
+        html.convert_code(lit, writer)
+    }
+
+    fn visit_html_inline(
+        &mut self,
+        html: &mut HtmlRenderer,
+        lit: &Literal,
+        _span: &SourceSpan,
+        writer: &mut Output
+    ) -> io::Result<()> {
+        html.convert_html_inline(lit, writer)
+    }
+
+    fn visit_emph(
+        &mut self,
+        html: &mut HtmlRenderer,
+        _span: &SourceSpan,
+        event: &Event,
+        writer: &mut Output
+    ) -> io::Result<()> {
+        html.convert_emph(event, writer)
+    }
+
+    fn visit_strong(
+        &mut self,
+        html: &mut HtmlRenderer,
+        _span: &SourceSpan,
+        event: &Event,
+        writer: &mut Output
+    ) -> io::Result<()> {
+        html.convert_strong(event, writer)
+    }
+
+    fn visit_link(
+        &mut self,
+        html: &mut HtmlRenderer,
+        url: &Url,
+        title: &Title,
+        _span: &SourceSpan,
+        event: &Event,
+        writer: &mut Output
+    ) -> io::Result<()> {
+        html.convert_link(url, title, event, writer)
+    }
+
+    fn visit_image(
+        &mut self,
+        html: &mut HtmlRenderer,
+        url: &Url,
+        title: &Title,
+        _span: &SourceSpan,
+        event: &Event,
+        writer: &mut Output
+    ) -> io::Result<()> {
+        html.convert_image(url, title, event, writer)
+    }
+
+}
+
+/// Renders every node the default way.
+pub struct DefaultVisitor;
+
+impl Visitor for DefaultVisitor {}
@@ -0,0 +1,464 @@
+/*
+ *  Front matter
+ *
+ *  A post may start with a block of metadata, either YAML between two `---`
+ *  lines or TOML between two `+++` lines:
+ *
+ *      ---
+ *      title: On Vowel Harmony
+ *      date: 2018-10-02
+ *      tags: [phonology, turkish]
+ *      markdown: [smart]
+ *      ---
+ *
+ *  The fields we know about go into `FrontMatter`'s own fields, and anything
+ *  else is kept in `FrontMatter::custom` for the templates to use.
+ */
+
+use cmark::Options;
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use std::io::{ BufRead, Read };
+use std::str;
+
+/// Most bytes read ahead looking for the end of the front matter.
+const HEADER_LIMIT: u64 = 64 * 1024;
+
+/// A value of a front matter field, whichever format it was written in.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    String(String),
+    Bool(bool),
+    Integer(i64),
+    Float(f64),
+    List(Vec<Value>),
+    Map(BTreeMap<String, Value>),
+}
+
+/// Metadata of one post.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrontMatter {
+    pub title: Option<String>,
+
+    /// Kept as written; TOML dates are turned back into their text.
+    pub date: Option<String>,
+
+    pub author: Option<String>,
+
+    /// Either a list, or a single string of comma-separated tags.
+    pub tags: Vec<String>,
+
+    /// Drafts are skipped unless `--drafts` is given. Defaults to `false`.
+    pub draft: bool,
+
+    /// Name of the page layout to use instead of the default one.
+    pub layout: Option<String>,
+
+    pub description: Option<String>,
+
+    /// cmark options turned on or off for this post only, from the
+    /// `markdown` field. They win over the options given on the command line
+    /// and in the site configuration.
+    pub options: OptionSwitches,
+
+    /// Every other field, by name.
+    pub custom: BTreeMap<String, Value>,
+}
+
+/// cmark options turned on and off by name, as in `[smart, safe=false]`:
+/// each name is one in `Options::NAMES`, optionally followed by `=true` or
+/// `=false`, like the command line flags.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OptionSwitches {
+    pub on: Options,
+    pub off: Options,
+}
+
+impl OptionSwitches {
+    /// Switches nothing on or off.
+    pub fn none() -> Self {
+        Self {
+            on: Options::DEFAULT,
+            off: Options::DEFAULT,
+        }
+    }
+
+    /// `options` with these switched on and off.
+    pub fn apply(&self, options: Options) -> Options {
+        (options | self.on) & !self.off
+    }
+}
+
+/// Errors in the front matter of a post.
+#[derive(Debug)]
+pub enum FrontMatterErr {
+    /// The YAML does not parse.
+    Yaml(String),
+
+    /// The TOML does not parse.
+    Toml(String),
+
+    /// The front matter is not valid UTF-8.
+    NotUtf8,
+
+    /// The front matter is something other than a set of fields.
+    NotAMap,
+
+    /// A known field has a value of the wrong kind, e.g. a list for `title`.
+    BadField(&'static str),
+
+    /// The `markdown` field names an option not in `Options::NAMES`.
+    UnknownOption(String),
+
+    /// Reading the post failed.
+    Io(io::Error),
+}
+
+impl fmt::Display for Value {
+    /// Lists are written comma-separated, which is what a template wants
+    /// most of the time.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &Value::String(ref s) => write!(f, "{}", s),
+            &Value::Bool(b) => write!(f, "{}", b),
+            &Value::Integer(i) => write!(f, "{}", i),
+            &Value::Float(x) => write!(f, "{}", x),
+            &Value::List(ref values) => write!(
+                f,
+                "{}",
+                values.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(", ")
+            ),
+            &Value::Map(_) => write!(f, "[map]"),
+        }
+    }
+}
+
+impl fmt::Display for FrontMatterErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &FrontMatterErr::Yaml(ref e) => write!(f, "invalid YAML: {}", e),
+            &FrontMatterErr::Toml(ref e) => write!(f, "invalid TOML: {}", e),
+            &FrontMatterErr::NotUtf8 => write!(f, "front matter is not valid UTF-8"),
+            &FrontMatterErr::NotAMap => write!(f, "front matter is not a set of fields"),
+            &FrontMatterErr::BadField(field) => write!(f, "field {} has the wrong type", field),
+            &FrontMatterErr::UnknownOption(ref name) => write!(f, "unknown markdown option {}", name),
+            &FrontMatterErr::Io(ref e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Default for FrontMatter {
+    fn default() -> Self {
+        Self {
+            title: None,
+            date: None,
+            author: None,
+            tags: Vec::new(),
+            draft: false,
+            layout: None,
+            description: None,
+            options: OptionSwitches::none(),
+            custom: BTreeMap::new(),
+        }
+    }
+}
+
+impl FrontMatter {
+
+    /// Reads the front matter off the start of `reader`, if there is any,
+    /// and leaves the rest of the markdown unread. Along with the front
+    /// matter comes what the parser should be fed before the rest: an empty
+    /// line for each line of front matter, so that line numbers still match
+    /// the file, or else everything read so far, as it is.
+    ///
+    /// A block without a closing delimiter within `HEADER_LIMIT` bytes is not
+    /// front matter, since `---` on its own is also a thematic break.
+    ///
+    /// Fields missing from the front matter are taken from `defaults`.
+    pub fn read<R: BufRead>(
+        reader: &mut R,
+        defaults: &BTreeMap<String, Value>
+    ) -> Result<(Self, Vec<u8>), FrontMatterErr> {
+        let mut limited = reader.take(HEADER_LIMIT);
+        let mut header: Vec<u8> = Vec::new();
+
+        limited.read_until(b'\n', &mut header).map_err(FrontMatterErr::Io)?;
+        let delim = match trim_line(&header) {
+            b"---" => "---",
+            b"+++" => "+++",
+            _ => return Ok((Self::from_map(defaults.clone())?, header)),
+        };
+
+        let meta_start = header.len();
+        let mut lines = 1;
+
+        //  YAML may also end its document with "..."
+        let meta_end = loop {
+            let start = header.len();
+            let len = limited.read_until(b'\n', &mut header).map_err(FrontMatterErr::Io)?;
+
+            //  a line cut short by the limit is not a delimiter
+            let cut = limited.limit() == 0 && !header.ends_with(b"\n");
+            if len == 0 || cut {
+                return Ok((Self::from_map(defaults.clone())?, header));
+            }
+
+            lines += 1;
+            let line = trim_line(&header[start ..]);
+            if line == delim.as_bytes() || (delim == "---" && line == b"...") {
+                break start;
+            }
+        };
+
+        let meta = match str::from_utf8(&header[meta_start .. meta_end]) {
+            Ok(res) => res,
+            Err(_) => return Err(FrontMatterErr::NotUtf8),
+        };
+
+        let mut map = defaults.clone();
+        map.extend(match delim {
+            "---" => match Self::parse_yaml(meta) {
+                //  a thematic break, some text and another thematic break
+                Err(FrontMatterErr::NotAMap) =>
+                    return Ok((Self::from_map(defaults.clone())?, header)),
+                res => res?,
+            },
+            _ => Self::parse_toml(meta)?,
+        });
+
+        Ok((Self::from_map(map)?, vec![b'\n'; lines]))
+    }
+
+    fn parse_yaml(meta: &str) -> Result<BTreeMap<String, Value>, FrontMatterErr> {
+        match serde_yaml::from_str::<serde_yaml::Value>(meta) {
+            Ok(serde_yaml::Value::Null) => Ok(BTreeMap::new()),
+
+            Ok(value) => match from_yaml(value) {
+                Value::Map(map) => Ok(map),
+                _ => Err(FrontMatterErr::NotAMap),
+            },
+
+            Err(e) => Err(FrontMatterErr::Yaml(e.to_string())),
+        }
+    }
+
+    /// Parses TOML into its fields. Also used for the site configuration.
+    pub fn parse_toml(meta: &str) -> Result<BTreeMap<String, Value>, FrontMatterErr> {
+        match meta.parse::<toml::Value>() {
+            Ok(value) => match from_toml(value) {
+                Value::Map(map) => Ok(map),
+                _ => Err(FrontMatterErr::NotAMap),
+            },
+
+            Err(e) => Err(FrontMatterErr::Toml(e.to_string())),
+        }
+    }
+
+    fn from_map(mut map: BTreeMap<String, Value>) -> Result<Self, FrontMatterErr> {
+        let draft = match map.remove("draft") {
+            Some(Value::Bool(b)) => b,
+            Some(_) => return Err(FrontMatterErr::BadField("draft")),
+            None => false,
+        };
+
+        let options = take_switches(&mut map, "markdown")?;
+
+        Ok(Self {
+            title: take_string(&mut map, "title")?,
+            date: take_string(&mut map, "date")?,
+            author: take_string(&mut map, "author")?,
+            tags: take_list(&mut map, "tags")?,
+            draft: draft,
+            layout: take_string(&mut map, "layout")?,
+            description: take_string(&mut map, "description")?,
+            options: options,
+            custom: map,
+        })
+    }
+
+}
+
+/// A line without its line ending and trailing whitespace.
+fn trim_line(line: &[u8]) -> &[u8] {
+    let end = line.iter().rposition(|&b| !b" \t\r\n".contains(&b)).map_or(0, |idx| idx + 1);
+    &line[.. end]
+}
+
+/// Removes a field that should be a single value, and returns its text.
+pub fn take_string(
+    map: &mut BTreeMap<String, Value>,
+    field: &'static str
+) -> Result<Option<String>, FrontMatterErr> {
+    match map.remove(field) {
+        Some(Value::List(_)) | Some(Value::Map(_)) => Err(FrontMatterErr::BadField(field)),
+        Some(value) => Ok(Some(value.to_string())),
+        None => Ok(None),
+    }
+}
+
+/// Removes a field that should be a list of strings. A single string is split
+/// at commas.
+pub fn take_list(
+    map: &mut BTreeMap<String, Value>,
+    field: &'static str
+) -> Result<Vec<String>, FrontMatterErr> {
+    match map.remove(field) {
+        Some(Value::List(values)) => values.into_iter()
+            .map(|value| match value {
+                Value::List(_) | Value::Map(_) => Err(FrontMatterErr::BadField(field)),
+                _ => Ok(value.to_string()),
+            })
+            .collect(),
+
+        Some(Value::String(s)) => Ok(s.split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(str::to_owned)
+            .collect()),
+
+        Some(_) => Err(FrontMatterErr::BadField(field)),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// Removes a field listing cmark options to turn on and off, see
+/// `OptionSwitches`.
+pub fn take_switches(
+    map: &mut BTreeMap<String, Value>,
+    field: &'static str
+) -> Result<OptionSwitches, FrontMatterErr> {
+    let mut res = OptionSwitches::none();
+
+    for item in take_list(map, field)? {
+        let (name, on) = match item.find('=') {
+            Some(idx) => match &item[idx + 1 ..] {
+                "true" => (&item[.. idx], true),
+                "false" => (&item[.. idx], false),
+                _ => return Err(FrontMatterErr::UnknownOption(item.clone())),
+            },
+            None => (item.as_str(), true),
+        };
+
+        let option = match Options::from_name(name) {
+            Some(option) => option,
+            None => return Err(FrontMatterErr::UnknownOption(item.clone())),
+        };
+
+        match on {
+            true => {
+                res.on |= option;
+                res.off &= !option;
+            },
+            false => {
+                res.off |= option;
+                res.on &= !option;
+            },
+        };
+    }
+
+    Ok(res)
+}
+
+/// Removes a field listing cmark options to turn on, see `OptionSwitches`.
+pub fn take_options(
+    map: &mut BTreeMap<String, Value>,
+    field: &'static str
+) -> Result<Options, FrontMatterErr> {
+    Ok(take_switches(map, field)?.apply(Options::DEFAULT))
+}
+
+fn from_yaml(value: serde_yaml::Value) -> Value {
+    match value {
+        serde_yaml::Value::Null => Value::String(String::new()),
+        serde_yaml::Value::Bool(b) => Value::Bool(b),
+        serde_yaml::Value::Number(n) => match n.as_i64() {
+            Some(i) => Value::Integer(i),
+            None => Value::Float(n.as_f64().unwrap_or(0.0)),
+        },
+        serde_yaml::Value::String(s) => Value::String(s),
+        serde_yaml::Value::Sequence(values) =>
+            Value::List(values.into_iter().map(from_yaml).collect()),
+        serde_yaml::Value::Mapping(mapping) => Value::Map(mapping.into_iter()
+            .map(|(k, v)| (from_yaml(k).to_string(), from_yaml(v)))
+            .collect()),
+    }
+}
+
+fn from_toml(value: toml::Value) -> Value {
+    match value {
+        toml::Value::String(s) => Value::String(s),
+        toml::Value::Integer(i) => Value::Integer(i),
+        toml::Value::Float(x) => Value::Float(x),
+        toml::Value::Boolean(b) => Value::Bool(b),
+        toml::Value::Datetime(d) => Value::String(d.to_string()),
+        toml::Value::Array(values) =>
+            Value::List(values.into_iter().map(from_toml).collect()),
+        toml::Value::Table(table) => Value::Map(table.into_iter()
+            .map(|(k, v)| (k, from_toml(v)))
+            .collect()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reads the front matter off `text`, and returns it along with the body
+    /// the parser would get.
+    fn split(text: &str) -> (FrontMatter, String) {
+        let mut reader = text.as_bytes();
+        let (meta, mut body) = FrontMatter::read(&mut reader, &BTreeMap::new()).unwrap();
+        body.extend_from_slice(reader);
+
+        (meta, String::from_utf8(body).unwrap())
+    }
+
+    #[test]
+    fn yaml() {
+        let text = "---\ntitle: Vowels\ntags: [a, b]\nmarkdown: smart\nseries: 2\n---\n# Hi\n";
+        let (meta, body) = split(text);
+
+        assert_eq!(meta.title, Some("Vowels".to_owned()));
+        assert_eq!(meta.tags, vec!["a".to_owned(), "b".to_owned()]);
+        assert_eq!(meta.options.apply(Options::SAFE), Options::SAFE | Options::SMART);
+        assert_eq!(meta.custom.get("series"), Some(&Value::Integer(2)));
+        assert_eq!(body, "\n\n\n\n\n\n# Hi\n");
+    }
+
+    #[test]
+    fn toml() {
+        let text = "+++\ntitle = \"Vowels\"\ndate = 2018-10-02\ndraft = true\nmarkdown = [\"safe=false\", \"smart\"]\n+++\nText\n";
+        let (meta, body) = split(text);
+
+        assert_eq!(meta.title, Some("Vowels".to_owned()));
+        assert_eq!(meta.date, Some("2018-10-02".to_owned()));
+        assert!(meta.draft);
+        assert_eq!(meta.options.apply(Options::SAFE | Options::NORMALIZE), Options::NORMALIZE | Options::SMART);
+        assert_eq!(body, "\n\n\n\n\n\nText\n");
+    }
+
+    #[test]
+    fn thematic_break_is_not_front_matter() {
+        for text in &["---\n\nSome text\n", "---\nSome *text*\n---\n\nMore text\n", "---\n- a\n- b\n---\n"] {
+            let (meta, body) = split(text);
+
+            assert_eq!(meta, FrontMatter::default());
+            assert_eq!(body, *text);
+        }
+    }
+
+    #[test]
+    fn header_is_read_ahead_only() {
+        let mut text = "+++\ntitle = \"A\"\n+++\n".as_bytes().to_vec();
+        text.extend_from_slice(b"\xff body");
+
+        let mut reader = &text[..];
+        let (meta, header) = FrontMatter::read(&mut reader, &BTreeMap::new()).unwrap();
+
+        assert_eq!(meta.title, Some("A".to_owned()));
+        assert_eq!(header, b"\n\n\n");
+        assert_eq!(reader, b"\xff body");
+    }
+}
@@ -27,8 +27,8 @@ extern crate log;
 extern crate simplelog;
 use simplelog::*;
 
-#[macro_use]
-extern crate lazy_static;
+extern crate serde_yaml;
+extern crate toml;
 
 #[allow(unused)]
 #[allow(non_upper_case_globals)]
@@ -40,23 +40,43 @@ mod bind {
 }
 
 use std::fs;
-use std::io::{ BufReader, BufWriter };
+use std::io;
+use std::io::{ BufRead, BufReader, BufWriter, Read, Write };
 
+//  bindings to all of cmark, not just what this program happens to use
+#[allow(dead_code)]
 mod cmark;
+use cmark::Format;
 
 mod converters;
 use converters::Converter;
 use converters::basic::{ BasicConverter, BasicData };
-use converters::simple::SimpleConverter;
+use converters::native::{ NativeConverter, NativeData };
+use converters::simple::{ SimpleConverter, SimpleData };
+use converters::template::Templates;
+
+mod transforms;
+use transforms::{ Pipeline, PipelineErr };
 
 mod asset;
 use asset::{ Asset, AssetType };
 
+mod front_matter;
+use front_matter::FrontMatter;
+
+mod theme;
+use theme::{ Theme, OVERRIDES_DIR, THEMES_DIR };
+
+mod config;
+use config::CONFIG_FILE;
+
 mod program_options;
 use program_options::{ ProgramOptions, ProgramOptionsErr };
 
-use std::env;
-use std::path::PathBuf;
+#[cfg(test)]
+mod spec_tests;
+
+use std::path::{ Path, PathBuf };
 
 fn copy_assets(
     info: &ProgramOptions,
@@ -168,30 +188,171 @@ fn copy_assets(
     Ok(res)
 }
 
-//  copy assets over to the output directory
+//  write the assets built into the theme, if any, to the output directory
+fn write_builtin_assets(theme: &Theme, dst_dir: &Path) -> Result<Vec<Asset>, ()> {
+    let mut res: Vec<Asset> = Vec::new();
+
+    for &(path, contents) in theme.builtin_assets() {
+        let dst_path = dst_dir.join(path);
+
+        let written = match dst_path.parent() {
+            Some(dir) => fs::create_dir_all(dir).and_then(|_| fs::write(&dst_path, contents)),
+            None => fs::write(&dst_path, contents),
+        };
+
+        match written {
+            Ok(_) => {
+                info!("Writing built-in asset {}", dst_path.display());
+            },
+
+            Err(_) => {
+                error!("Cannot write built-in asset {}.", dst_path.display());
+                return Err(());
+            },
+        };
+
+        let path = PathBuf::from("assets").join(path);
+        let asset_type = AssetType::guess(&path);
+        res.push(Asset::new(path, asset_type));
+    }
+
+    Ok(res)
+}
+
+//  copy the assets of the theme, then the site's overrides of them, over to
+//  the output directory
 fn prepare_assets(
     info: &ProgramOptions,
-    assets_dir: &PathBuf
+    theme: &Theme
 ) -> Result<Vec<Asset>, ()> {
-    let curr_dir = match env::current_dir() {
-        Ok(res) => res,
-        Err(_) => {
-            error!("Cannot obtain current working directory");
-            return Err(());
+    if theme.dir().is_none() {
+        info!("The default theme is not installed, using its built-in assets");
+    }
+
+    let dst_dir = info.output_dir.join("assets");
+    let mut res = write_builtin_assets(theme, &dst_dir)?;
+
+    for layer in theme.layers() {
+        let src_dir = layer.join("assets");
+        if !src_dir.is_dir() {
+            continue;
+        }
+
+        for asset in copy_assets(&info, layer, &src_dir, &dst_dir)? {
+            if !res.contains(&asset) {
+                res.push(asset);
+            }
+        }
+    }
+
+    Ok(res)
+}
+
+//  load the page templates of the theme and the site's overrides of them
+fn load_templates(theme: &Theme) -> Result<Templates, ()> {
+    match Templates::load(&theme.template_dirs()) {
+        Ok(res) => {
+            info!("Templates loaded successfully.");
+            Ok(res)
         },
-    };
 
-    let src_dir = curr_dir.join(assets_dir);
-    let dst_dir = info.output_dir.join(assets_dir);
+        Err(e) => {
+            error!("Failed to load templates: {}. Terminating...", e);
+            Err(())
+        },
+    }
+}
+
+//  a post being converted
+struct Post<'a> {
+    src_path: &'a Path,
+    front_matter: &'a FrontMatter,
+
+    //  the number of levels from the original output directory to the post's
+    dist: usize,
+}
+
+//  run whichever converter the options ask for, logging the outcome
+fn run_converter<R: Read, W: Write>(
+    info: &ProgramOptions,
+    post: &Post,
+    reader: &mut R,
+    writer: &mut W,
+    templates: &Templates,
+    assets: &Vec<Asset>,
+    pipeline: &mut Pipeline,
+) -> Result<(), ()> {
+    let src_path = post.src_path;
+    let options = post.front_matter.options.apply(info.options);
+
+    match match (info.format, info.simple) {
+        (Format::Html, true) => {
+            let mut cvt = SimpleConverter::new(options);
+            cvt.set_compliant(info.compliant);
+            if let Some(ref indent) = info.custom_indent {
+                cvt.set_indent_str(indent);
+            }
+            cvt.convert(reader, writer, pipeline, SimpleData::new(src_path))
+        },
+
+        (Format::Html, false) => {
+            let mut cvt = BasicConverter::new(options);
+            cvt.set_site(info.config.site());
+            if let Some(ref indent) = info.custom_indent {
+                cvt.set_indent_str(indent);
+            }
+            cvt.convert(reader, writer, pipeline, BasicData::new(src_path, post.front_matter, templates, assets, post.dist))
+        },
+
+        (format, _) => {
+            let mut cvt = NativeConverter::new(options);
+            cvt.convert(reader, writer, pipeline, NativeData::new(format, info.width))
+        },
+    } {
+        Ok(_) => {
+            info!("CommonMark conversion successful.");
+            Ok(())
+        },
+
+        Err(e) => {
+            match e.span() {
+                Some(span) => error!("{}:{}: CommonMark conversion failed: {}", src_path.display(), span, e),
+                None => error!("{}: CommonMark conversion failed: {}", src_path.display(), e),
+            };
+
+            Err(())
+        },
+    }
+}
 
-    copy_assets(&info, &curr_dir, &src_dir, &dst_dir)
+//  read the front matter off the start of a markdown, filling in the defaults
+//  the site configuration has for it; the rest is left in the reader, to be
+//  streamed to the parser after the lines returned along with the front matter
+fn read_front_matter<R: BufRead>(
+    info: &ProgramOptions,
+    reader: &mut R,
+    src_path: &Path
+) -> Result<(FrontMatter, Vec<u8>), ()> {
+    let defaults = info.config.defaults_for(
+        src_path.strip_prefix(&info.site_dir).unwrap_or(src_path)
+    );
+
+    match FrontMatter::read(reader, &defaults) {
+        Ok(res) => Ok(res),
+        Err(e) => {
+            error!("{}: Bad front matter: {}. Skipping this file...", src_path.display(), e);
+            Err(())
+        },
+    }
 }
 
 fn convert_file(
     info: &ProgramOptions,
     src_path: &PathBuf,
     dst_path: &PathBuf,
+    templates: &Templates,
     assets: &Vec<Asset>,
+    pipeline: &mut Pipeline,
     dist: usize,
 ) -> Result<(), ()> {
     let extension = match src_path.extension() {
@@ -206,23 +367,34 @@ fn convert_file(
         true => {
             info!("{} is a markdown, converting to post", src_path.display());
 
-            let input = match fs::File::open(&src_path) {
-                Ok(res) => res,
+            let mut input = match fs::File::open(&src_path) {
+                Ok(res) => BufReader::new(res),
                 Err(_) => {
                     error!("Cannot open file {}. Skipping this file...", src_path.display());
                     return Err(());
                 },
             };
-            let mut reader = BufReader::new(input);
 
-            let mut html_path = dst_path.clone();
-            html_path.set_extension("html");
+            let (front_matter, header) = read_front_matter(info, &mut input, src_path)?;
+
+            if front_matter.draft && !info.drafts {
+                info!("{} is a draft, skipping...", src_path.display());
+                return Ok(());
+            }
+
+            let mut out_path = dst_path.clone();
+            out_path.set_extension(info.format.extension());
+
+            if out_path == *src_path {
+                error!("Converting {} would overwrite it. Skipping this file...", src_path.display());
+                return Err(());
+            }
 
             let output = match fs::OpenOptions::new()
                 .write(true)
                 .truncate(true)
                 .create(true)
-                .open(html_path.clone()) {
+                .open(out_path.clone()) {
                     Ok(res) => res,
                     Err(_) => {
                         error!("Cannot open file {} for writing. Skipping this file...", dst_path.display());
@@ -231,29 +403,28 @@ fn convert_file(
                 };
             let mut writer = BufWriter::new(output);
 
-            match match info.simple {
-                true => {
-                    let mut cvt = SimpleConverter::new();
-                    cvt.convert(&mut reader, &mut writer, ())
-                },
-
-                false => {
-                    let mut cvt = BasicConverter::new();
-                    cvt.convert(&mut reader, &mut writer, BasicData::new(assets, dist))
-                },
-            } {
-                Ok(_) => {
-                    info!("CommonMark conversion successful.");
-                },
+            let post = Post {
+                src_path: src_path,
+                front_matter: &front_matter,
+                dist: dist,
+            };
 
+            match run_converter(
+                info,
+                &post,
+                &mut header.as_slice().chain(input),
+                &mut writer,
+                templates,
+                assets,
+                pipeline
+            ) {
+                Ok(_) => (),
                 Err(_) => {
-                    error!("CommonMark conversion failed.");
-
                     if !info.persist {
                         return Err(());
                     }
                 },
-            }
+            };
         },
 
         false => {
@@ -281,7 +452,9 @@ fn convert_dir(
     info: &ProgramOptions,
     src_dir: &PathBuf,
     dst_dir: &PathBuf,
+    templates: &Templates,
     assets: &Vec<Asset>,
+    pipeline: &mut Pipeline,
     dist: usize
 ) -> Result<(), ()> {
     match fs::create_dir_all(&dst_dir) {
@@ -324,11 +497,21 @@ fn convert_dir(
             },
         });
 
+        //  the site's own files stay out of the output
+        let is_site_file = dist == 0 && match path.file_name() {
+            Some(name) => name == OVERRIDES_DIR || name == THEMES_DIR || name == CONFIG_FILE,
+            None => false,
+        };
+
         match path.is_dir() {
+            _ if is_site_file => {
+                info!("{} belongs to the site configuration, skipping...", path.display());
+            },
+
             true => {
                 info!("{} is a directory, going in...", path.display());
 
-                match convert_dir(info, &path, &new_dst_path, assets, dist + 1) {
+                match convert_dir(info, &path, &new_dst_path, templates, assets, pipeline, dist + 1) {
                     Ok(_) => (),
                     Err(_) => {
                         if !info.persist {
@@ -342,7 +525,7 @@ fn convert_dir(
             false => {
                 info!("{} is a file.", path.display());
 
-                match convert_file(info, &path, &new_dst_path, assets, dist) {
+                match convert_file(info, &path, &new_dst_path, templates, assets, pipeline, dist) {
                     Ok(_) => (),
                     Err(_) => {
                         if !info.persist {
@@ -358,12 +541,48 @@ fn convert_dir(
     Ok(())
 }
 
-fn convert(info: &ProgramOptions, assets: &Vec<Asset>) -> Result<(), ()> {
+//  convert markdown piped into standard input, writing to standard output
+fn convert_stdin(
+    info: &ProgramOptions,
+    templates: &Templates,
+    assets: &Vec<Asset>,
+    pipeline: &mut Pipeline
+) -> Result<(), ()> {
+    info!("Converting standard input to standard output");
+
+    let stdin = io::stdin();
+    let mut input = stdin.lock();
+    let src_path = Path::new("<stdin>");
+    let (front_matter, header) = read_front_matter(info, &mut input, src_path)?;
+    let mut reader = header.as_slice().chain(input);
+
+    let stdout = io::stdout();
+    let mut writer = BufWriter::new(stdout.lock());
+
+    let post = Post {
+        src_path: src_path,
+        front_matter: &front_matter,
+        dist: 0,
+    };
+
+    run_converter(info, &post, &mut reader, &mut writer, templates, assets, pipeline)
+}
+
+fn convert(
+    info: &ProgramOptions,
+    templates: &Templates,
+    assets: &Vec<Asset>,
+    pipeline: &mut Pipeline
+) -> Result<(), ()> {
+    if info.stdin {
+        return convert_stdin(&info, templates, assets, pipeline);
+    }
+
     match info.single_file {
         true => match info.input_dir.file_name() {
             Some(filename) => {
                 let dst_dir = info.output_dir.join(filename);
-                convert_file(&info, &info.input_dir, &dst_dir, assets, 0)
+                convert_file(&info, &info.input_dir, &dst_dir, templates, assets, pipeline, 0)
             },
 
             None => {
@@ -372,7 +591,7 @@ fn convert(info: &ProgramOptions, assets: &Vec<Asset>) -> Result<(), ()> {
             },
         },
 
-        false => convert_dir(&info, &info.input_dir, &info.output_dir, assets, 0),
+        false => convert_dir(&info, &info.input_dir, &info.output_dir, templates, assets, pipeline, 0),
     }
 }
 
@@ -395,6 +614,15 @@ fn main() {
 
                 ProgramOptionsErr::BadVerbosity =>
                     error!("Verbosity must be <= 3. Terminating..."),
+
+                ProgramOptionsErr::BadWidth =>
+                    error!("Width must be a non-negative number. Terminating..."),
+
+                ProgramOptionsErr::BadConfig(e) =>
+                    error!("Bad site configuration: {}. Terminating...", e),
+
+                ProgramOptionsErr::CompliantWithoutSimple =>
+                    error!("--compliant only works with simple conversion. Terminating..."),
             };
 
             return;
@@ -417,21 +645,34 @@ fn main() {
         },
     };
 
-    let assets = match info.simple {
-        true => {
+    let theme = match Theme::find(&info.theme, &info.site_dir) {
+        Some(res) => res,
+        None => {
+            error!("There is no theme named {}. Terminating...", info.theme);
+            return;
+        },
+    };
+
+    let assets = match (info.format, info.simple) {
+        _ if info.stdin => {
+            info!("Reading from standard input, skipping assets...");
+            Vec::new()
+        },
+
+        (Format::Html, true) => {
             info!("Simple conversion, skipping assets...");
             Vec::new()
         },
 
-        false => {
-            match prepare_assets(&info, &PathBuf::from("assets")) {
+        (Format::Html, false) => {
+            match prepare_assets(&info, &theme) {
                 Ok(res) => {
                     info!("Assets copied successfully.");
                     res
                 },
 
                 Err(_) => {
-                    error!("Failed to copy assets from input directory to output directory.");
+                    error!("Failed to copy assets from the theme to output directory.");
 
                     if !info.persist {
                         error!("The program is non-persisting. Terminating...");
@@ -442,9 +683,32 @@ fn main() {
                 },
             }
         },
+
+        _ => {
+            info!("Non-HTML conversion, skipping assets...");
+            Vec::new()
+        },
+    };
+
+    let templates = match (info.format, info.simple) {
+        (Format::Html, false) => match load_templates(&theme) {
+            Ok(res) => res,
+            Err(_) => return,
+        },
+
+        _ => Templates::builtin(),
+    };
+
+    let mut pipeline = match Pipeline::from_names(&info.transforms) {
+        Ok(res) => res,
+
+        Err(PipelineErr::UnknownTransform(name)) => {
+            error!("There is no transform named {}. Terminating...", name);
+            return;
+        },
     };
 
-    match convert(&info, &assets) {
+    match convert(&info, &templates, &assets, &mut pipeline) {
         Ok(_) => {
             info!("File(s) in input directory converted successfully.");
         },
@@ -1,4 +1,12 @@
-use clap::{ App, Arg };
+use clap::{ App, Arg, ArgMatches };
+
+use transforms::BUILTIN_NAMES;
+
+use theme::DEFAULT_THEME;
+
+use config::{ Config, ConfigErr };
+
+use cmark::{ Format, Options };
 
 use std::path::{ PathBuf };
 
@@ -9,6 +17,16 @@ pub enum ProgramOptionsErr {
 
     /// The user specified a verbosity > 3.
     BadVerbosity,
+
+    /// The user specified a wrapping width that is not a number.
+    BadWidth,
+
+    /// The site configuration file cannot be read.
+    BadConfig(ConfigErr),
+
+    /// Compliant conversion was asked for without simple conversion, on the
+    /// command line or in the site configuration.
+    CompliantWithoutSimple,
 }
 
 /// Options passed to this program.
@@ -17,6 +35,20 @@ pub struct ProgramOptions {
     /// and copied / parsed.
     pub input_dir: PathBuf,
 
+    /// Standard input: If `true`, the input path was given as `-`, and a single
+    /// markdown is read from standard input and converted to standard output.
+    /// Assets are not copied. Defaults to `false`.
+    pub stdin: bool,
+
+    /// Directory holding the site configuration, the site's themes and its
+    /// theme overrides: the input directory, the directory of the input file
+    /// with `single_file`, or the current directory with `stdin`.
+    pub site_dir: PathBuf,
+
+    /// Settings from the site configuration file. The options below that have
+    /// a setting too take it as their default, and the command line wins.
+    pub config: Config,
+
     /// Path to the output directory. Files will be copied / generated and
     /// written to this directory, following the same directory structure in the
     /// input directory.
@@ -36,13 +68,46 @@ pub struct ProgramOptions {
     /// `false`.
     pub simple: bool,
 
-    /// Customized indent string. Defaults to `None`.
+    /// Compliant conversion: If `true`, simple conversion produces exactly the
+    /// HTML of cmark's reference renderer, instead of indenting it. Has no
+    /// effect without `simple`. Defaults to `false`.
+    pub compliant: bool,
+
+    /// String indenting the blocks of the HTML output one level, instead of
+    /// four spaces. Defaults to `None`.
     pub custom_indent: Option<String>,
 
     /// Single file: If `true`, the input path will be taken to be a path to
     /// one single file, rather than a directory. If `false`, the input path
     /// will be taken to be a directory. Defaults to `false`.
     pub single_file: bool,
+
+    /// Theme, by name or by path to its directory. Defaults to
+    /// `DEFAULT_THEME`.
+    pub theme: String,
+
+    /// Drafts: If `true`, posts marked `draft` in their front matter are
+    /// converted like any other. If `false`, they are skipped. Defaults to
+    /// `false`.
+    pub drafts: bool,
+
+    /// Names of the transforms to run on every document before conversion,
+    /// in order. Defaults to none.
+    pub transforms: Vec<String>,
+
+    /// Output format. Anything other than `Format::Html` is rendered by cmark
+    /// itself, without theming, assets or special syntax. Defaults to
+    /// `Format::Html`.
+    pub format: Format,
+
+    /// Column at which cmark wraps lines in the LaTeX, man and CommonMark
+    /// outputs, with `0` meaning no wrapping. Defaults to `0`.
+    pub width: usize,
+
+    /// cmark options used for parsing and rendering every file, one flag per
+    /// option in `Options::NAMES` (e.g. `--smart`). Defaults to
+    /// `Options::DEFAULT`.
+    pub options: Options,
 }
 
 impl ProgramOptions {
@@ -55,7 +120,7 @@ impl ProgramOptions {
             .about("A static site generator for a certain interests")
             .arg(Arg::with_name("input-dir")
                  .value_name("INPUT-DIR")
-                 .help("sets the input directory")
+                 .help("sets the input directory, or - to convert standard input to standard output")
                  .required(true))
             .arg(Arg::with_name("output-dir")
                  .short("o")
@@ -74,15 +139,49 @@ impl ProgramOptions {
             .arg(Arg::with_name("no-persist")
                  .long("no-persist")
                  .help("do not persist after error"))
-            .arg(Arg::with_name("simple")
-                 .long("simple")
-                 .help("perform simple conversion"))
+            .arg(switch("simple", "perform simple conversion"))
+            .arg(switch("compliant", "with --simple, match cmark's reference HTML byte for byte"))
             .arg(Arg::with_name("custom-indent")
                  .long("custom-indent")
-                 .help("custom indent string, must be enclosed with double quotes"))
+                 .value_name("INDENT")
+                 .help("indents the HTML with this string instead of four spaces")
+                 .takes_value(true))
             .arg(Arg::with_name("single-file")
                  .long("single-file")
-                 .help("convert just one file"));
+                 .help("convert just one file"))
+            .arg(Arg::with_name("theme")
+                 .long("theme")
+                 .value_name("THEME")
+                 .help("sets the theme, by name or by path to its directory")
+                 .takes_value(true))
+            .arg(switch("drafts", "also convert posts marked as drafts"))
+            .arg(Arg::with_name("transform")
+                 .long("transform")
+                 .value_name("TRANSFORM")
+                 .help("runs a transform on every document before conversion, can be repeated")
+                 .takes_value(true)
+                 .multiple(true)
+                 .number_of_values(1)
+                 .possible_values(BUILTIN_NAMES))
+            .arg(Arg::with_name("format")
+                 .long("format")
+                 .value_name("FORMAT")
+                 .help("sets the output format")
+                 .takes_value(true)
+                 .possible_values(&["html", "latex", "man", "xml", "commonmark"])
+                 .default_value("html"))
+            .arg(Arg::with_name("width")
+                 .long("width")
+                 .value_name("WIDTH")
+                 .help("wraps lines at this column in latex, man and commonmark outputs")
+                 .takes_value(true))
+            .arg(switch("source-pos", "adds data-sourcepos attributes to block elements"))
+            .arg(switch("hard-breaks", "renders soft line breaks as hard line breaks"))
+            .arg(switch("safe", "omits raw HTML and links to dangerous URLs"))
+            .arg(switch("no-breaks", "renders soft line breaks as spaces"))
+            .arg(switch("normalize", "merges adjacent text nodes"))
+            .arg(switch("validate-utf8", "replaces invalid UTF-8 in the input with U+FFFD"))
+            .arg(switch("smart", "uses smart punctuation for quotes, dashes and ellipses"));
 
         let matches = app.get_matches();
 
@@ -93,9 +192,26 @@ impl ProgramOptions {
             }
         };
 
-        let output_dir = match matches.value_of("output-dir") {
-            Some(s) => PathBuf::from(s),
-            None => input_dir.clone(),
+        let stdin = input_dir == PathBuf::from("-");
+        let single_file = matches.is_present("single-file");
+
+        let site_dir = match (stdin, single_file) {
+            (true, _) => PathBuf::from("."),
+            (false, true) => input_dir.parent().map(PathBuf::from).unwrap_or_default(),
+            (false, false) => input_dir.clone(),
+        };
+
+        let config = match Config::load(&site_dir) {
+            Ok(res) => res,
+            Err(e) => {
+                return Err(ProgramOptionsErr::BadConfig(e));
+            },
+        };
+
+        let output_dir = match (matches.value_of("output-dir"), &config.output_dir) {
+            (Some(s), _) => PathBuf::from(s),
+            (None, &Some(ref dir)) => site_dir.join(dir),
+            (None, &None) => input_dir.clone(),
         };
 
         let verbosity = matches.occurrences_of("verbosity") as usize;
@@ -103,18 +219,109 @@ impl ProgramOptions {
             return Err(ProgramOptionsErr::BadVerbosity);
         }
 
+        let width = match matches.value_of("width") {
+            Some(s) => match s.parse::<usize>() {
+                Ok(res) => res,
+                Err(_) => {
+                    return Err(ProgramOptionsErr::BadWidth);
+                },
+            },
+            None => 0,
+        };
+
+        let options = Options::NAMES.iter()
+            .filter_map(|name| Options::from_name(name).map(|option| (option, switched(&matches, name))))
+            .fold(config.options, |acc, (option, on)| match on {
+                Some(true) => acc | option,
+                Some(false) => acc & !option,
+                None => acc,
+            });
+
+        let simple = switched(&matches, "simple")
+            .or(config.simple)
+            .unwrap_or(false);
+
+        let compliant = switched(&matches, "compliant")
+            .unwrap_or(false);
+
+        if compliant && !simple {
+            return Err(ProgramOptionsErr::CompliantWithoutSimple);
+        }
+
+        let transforms = match matches.values_of("transform") {
+            Some(values) => values.map(str::to_owned).collect(),
+            None => config.transforms.clone(),
+        };
+
         Ok(Self {
+            stdin: stdin,
             input_dir: input_dir,
             output_dir: output_dir,
             verbosity: verbosity,
             persist: !matches.is_present("no-persist"),
-            simple: matches.is_present("simple"),
+            simple: simple,
+            compliant: compliant,
             custom_indent: matches.value_of("custom-indent")
-                .filter(|s| s.starts_with('\"'))
-                .filter(|s| s.ends_with('\"'))
-                .map(str::to_owned),
-            single_file: matches.is_present("single-file"),
+                .map(str::to_owned)
+                .or_else(|| config.custom_indent.clone()),
+            single_file: single_file,
+            theme: matches.value_of("theme")
+                .map(str::to_owned)
+                .or_else(|| config.theme.clone())
+                .unwrap_or_else(|| DEFAULT_THEME.to_owned()),
+            drafts: switched(&matches, "drafts")
+                .or(config.drafts)
+                .unwrap_or(false),
+            transforms: transforms,
+            format: matches.value_of("format")
+                .and_then(Format::from_name)
+                .unwrap_or(Format::Html),
+            width: width,
+            options: options,
+            site_dir: site_dir,
+            config: config,
         })
     }
 
 }
+
+/// An on / off flag, e.g. `--smart`, which can also be given as `--smart=true`
+/// or `--smart=false`, the latter to turn off a setting of the site
+/// configuration.
+fn switch<'a>(name: &'a str, help: &'a str) -> Arg<'a, 'a> {
+    Arg::with_name(name)
+        .long(name)
+        .help(help)
+        .takes_value(true)
+        .min_values(0)
+        .require_equals(true)
+        .possible_values(&["true", "false"])
+}
+
+/// Whether a `switch` is on, or `None` if it was not given at all.
+fn switched(matches: &ArgMatches, name: &str) -> Option<bool> {
+    match matches.is_present(name) {
+        true => Some(matches.value_of(name) != Some("false")),
+        false => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn switches() {
+        let app = App::new("writer2")
+            .arg(Arg::with_name("input-dir").required(true))
+            .arg(switch("simple", ""))
+            .arg(switch("smart", ""))
+            .arg(switch("safe", ""));
+
+        let matches = app.get_matches_from(vec!["writer2", "--simple", "--smart=false", "notes"]);
+        assert_eq!(switched(&matches, "simple"), Some(true));
+        assert_eq!(switched(&matches, "smart"), Some(false));
+        assert_eq!(switched(&matches, "safe"), None);
+        assert_eq!(matches.value_of("input-dir"), Some("notes"));
+    }
+}
@@ -0,0 +1,278 @@
+/*
+ *  CommonMark spec conformance
+ *
+ *  Runs every example of the CommonMark spec through SimpleConverter and
+ *  compares the result. The pretty output is compared against the spec's
+ *  HTML after normalizing the whitespace that HTML does not care about. The
+ *  compliant output is compared byte for byte against cmark's own renderer,
+ *  which is the reference it promises to match; where cmark itself disagrees
+ *  with the spec, cmark wins.
+ *
+ *  test/commonmark/spec.json holds the 624 examples of the released spec
+ *  0.28 (dated 2017-08-01), dumped from its spec.txt with
+ *
+ *      python3 test/spec_tests.py --spec spec.txt --dump-tests
+ *
+ *  from the CommonMark repository. The vendored cmark 0.28.3 does not ship
+ *  a spec.txt of its own in this tree; upstream it was tested against a
+ *  later development copy of the spec, which is why a few examples differ.
+ */
+
+extern crate serde_json;
+
+use converters::Converter;
+use converters::simple::{ SimpleConverter, SimpleData };
+
+use cmark::{ markdown_to_html, Options };
+
+use transforms::Pipeline;
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+const SPEC: &str = include_str!("../test/commonmark/spec.json");
+
+/// Examples that are known to fail in the pretty output. A failure outside this
+/// list is a regression, and an example in this list that passes fails the
+/// test too, so that the list cannot go stale.
+///
+/// Last checked with rustc 1.95.0 against the vendored cmark 0.28.3:
+///
+/// *   273, 274 and 464: the development spec after 0.28 changed these, and
+///     cmark 0.28.3 already follows it, taking `- e` as a continuation line,
+///     `3. c` as an indented code block and `</my uri>` as a link
+///     destination. The compliant output matches cmark here, so only the
+///     pretty output fails.
+const KNOWN_FAILURES: &[u64] = &[
+    273, 274, 464,
+];
+
+/// Examples that are known to fail in the compliant output.
+const COMPLIANT_KNOWN_FAILURES: &[u64] = &[
+];
+
+/// Tags around which whitespace is insignificant.
+const BLOCK_TAGS: &[&str] = &[
+    "address", "article", "aside", "blockquote", "body", "center", "dd",
+    "details", "dialog", "dir", "div", "dl", "dt", "figcaption", "figure",
+    "footer", "form", "h1", "h2", "h3", "h4", "h5", "h6", "head", "header",
+    "hr", "html", "legend", "li", "link", "main", "menu", "nav", "ol", "p",
+    "pre", "section", "summary", "table", "tbody", "td", "tfoot", "th",
+    "thead", "title", "tr", "ul",
+];
+
+struct Example {
+    number: u64,
+    section: String,
+    markdown: String,
+    html: String,
+}
+
+fn load_examples() -> Vec<Example> {
+    let json: serde_json::Value = match serde_json::from_str(SPEC) {
+        Ok(res) => res,
+        Err(e) => panic!("Cannot parse spec.json: {}", e),
+    };
+
+    let string = |value: &serde_json::Value, key: &str| match value[key].as_str() {
+        Some(s) => s.to_owned(),
+        None => panic!("Spec example is missing {}", key),
+    };
+
+    match json.as_array() {
+        Some(examples) => examples.iter()
+            .map(|value| Example {
+                number: value["example"].as_u64().unwrap_or(0),
+                section: string(value, "section"),
+                markdown: string(value, "markdown"),
+                html: string(value, "html"),
+            })
+            .collect(),
+
+        None => panic!("spec.json is not an array of examples"),
+    }
+}
+
+fn convert(markdown: &str, compliant: bool) -> Result<String, String> {
+    let mut cvt = SimpleConverter::new(Options::DEFAULT);
+    cvt.set_compliant(compliant);
+    let mut pipeline = Pipeline::new();
+    let mut output: Vec<u8> = Vec::new();
+
+    match cvt.convert(
+        &mut markdown.as_bytes(),
+        &mut output,
+        &mut pipeline,
+        SimpleData::new(Path::new("spec.json"))
+    ) {
+        Ok(_) => String::from_utf8(output).map_err(|e| e.to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn is_block_tag(tag: &str) -> bool {
+    let name: String = tag.trim_start_matches('<')
+        .trim_start_matches('/')
+        .chars()
+        .take_while(|c| c.is_ascii_alphanumeric())
+        .collect::<String>()
+        .to_ascii_lowercase();
+
+    BLOCK_TAGS.contains(&name.as_str())
+}
+
+/// Normalizes an HTML fragment so that two renderings differing only in
+/// indentation and line breaks between blocks compare equal. Whitespace within
+/// text is collapsed to a single space, and dropped entirely next to a block
+/// tag. Everything inside `<pre>` is kept verbatim. The `data-delimiter`
+/// attribute on ordered lists is ours and not the spec's, so it goes too.
+fn normalize(html: &str) -> String {
+    let html: &str = &html
+        .replace(" data-delimiter=\"period\"", "")
+        .replace(" data-delimiter=\"paren\"", "");
+
+    //  split into tags and text
+    let mut pieces: Vec<(bool, &str)> = Vec::new();
+    let mut rest = html;
+    while !rest.is_empty() {
+        let end = match rest.starts_with('<') {
+            true => rest.find('>').map(|i| i + 1).unwrap_or(rest.len()),
+            false => rest.find('<').unwrap_or(rest.len()),
+        };
+
+        pieces.push((rest.starts_with('<'), &rest[.. end]));
+        rest = &rest[end ..];
+    }
+
+    let mut res = String::new();
+    let mut in_pre = false;
+    for (i, &(is_tag, piece)) in pieces.iter().enumerate() {
+        if is_tag {
+            if piece.starts_with("<pre") {
+                in_pre = true;
+            } else if piece.starts_with("</pre") {
+                in_pre = false;
+            }
+
+            res.push_str(piece);
+            continue;
+        }
+
+        if in_pre {
+            res.push_str(piece);
+            continue;
+        }
+
+        let mut text = piece.split_whitespace().collect::<Vec<_>>().join(" ");
+        if !text.is_empty() {
+            if piece.starts_with(char::is_whitespace) {
+                text.insert(0, ' ');
+            }
+            if piece.ends_with(char::is_whitespace) {
+                text.push(' ');
+            }
+        }
+
+        let after_block = i > 0 && pieces[i - 1].0 && is_block_tag(pieces[i - 1].1);
+        let before_block = i + 1 < pieces.len() && pieces[i + 1].0 && is_block_tag(pieces[i + 1].1);
+
+        let mut text: &str = &text;
+        if after_block || i == 0 {
+            text = text.trim_start();
+        }
+        if before_block || i + 1 == pieces.len() {
+            text = text.trim_end();
+        }
+
+        res.push_str(text);
+    }
+
+    res
+}
+
+fn expected(example: &Example, compliant: bool) -> String {
+    match compliant {
+        true => markdown_to_html(
+            &example.markdown,
+            example.markdown.len(),
+            Options::DEFAULT
+        ).unwrap_or_else(|e| e.to_string()),
+
+        false => example.html.clone(),
+    }
+}
+
+/// Runs all examples, prints a summary per section, and panics if an example
+/// outside `known_failures` fails or one in it passes.
+fn check_examples(compliant: bool, known_failures: &[u64]) {
+    let examples = load_examples();
+
+    //  section name to (passed, total); sections keeps the spec's order
+    let mut sections: Vec<String> = Vec::new();
+    let mut results: BTreeMap<String, (usize, usize)> = BTreeMap::new();
+
+    let mut regressions: Vec<u64> = Vec::new();
+    let mut fixed: Vec<u64> = Vec::new();
+
+    for example in &examples {
+        let expected = expected(example, compliant);
+        let passed = match (convert(&example.markdown, compliant), compliant) {
+            (Ok(actual), true) => actual == expected,
+            (Ok(actual), false) => normalize(&actual) == normalize(&expected),
+            (Err(_), _) => false,
+        };
+
+        if !results.contains_key(&example.section) {
+            sections.push(example.section.clone());
+        }
+        let entry = results.entry(example.section.clone()).or_insert((0, 0));
+        entry.1 += 1;
+        if passed {
+            entry.0 += 1;
+        }
+
+        match (passed, known_failures.contains(&example.number)) {
+            (true, true) => fixed.push(example.number),
+            (false, false) => regressions.push(example.number),
+            _ => (),
+        };
+    }
+
+    let mut total_passed = 0;
+    for section in &sections {
+        let (passed, total) = results[section];
+        total_passed += passed;
+        println!("{:>4}/{:<4} {}", passed, total, section);
+    }
+    println!("{:>4}/{:<4} total", total_passed, examples.len());
+
+    if !regressions.is_empty() {
+        for number in &regressions {
+            let example = examples.iter().find(|e| e.number == *number).unwrap();
+            println!(
+                "\nExample {} ({})\n--- markdown\n{}--- expected\n{}--- actual\n{}",
+                example.number,
+                example.section,
+                example.markdown,
+                expected(example, compliant),
+                convert(&example.markdown, compliant).unwrap_or_else(|e| e + "\n")
+            );
+        }
+
+        panic!("{} spec examples regressed: {:?}", regressions.len(), regressions);
+    }
+
+    if !fixed.is_empty() {
+        panic!("Known failures that now pass, remove them from the list: {:?}", fixed);
+    }
+}
+
+#[test]
+fn commonmark_spec() {
+    check_examples(false, KNOWN_FAILURES);
+}
+
+#[test]
+fn commonmark_spec_compliant() {
+    check_examples(true, COMPLIANT_KNOWN_FAILURES);
+}
@@ -0,0 +1,150 @@
+/*
+ *  Themes
+ *
+ *  A theme is a directory bundling the look of a site:
+ *
+ *      themes/NAME/
+ *          templates/      layouts, and partials in templates/partials/
+ *          assets/         CSS, JS and anything else, copied to the output
+ *
+ *  A site can override single files of its theme by putting files at the same
+ *  paths in `_theme/` in the input directory, e.g. `_theme/assets/main.css`.
+ */
+
+use std::env;
+use std::path::{ Component, Path, PathBuf };
+
+/// Theme used unless another one is asked for.
+pub const DEFAULT_THEME: &str = "default";
+
+/// Directory, in the input directory or the current one, holding themes.
+pub const THEMES_DIR: &str = "themes";
+
+/// Directory in the input directory overriding files of the theme.
+pub const OVERRIDES_DIR: &str = "_theme";
+
+/// The assets of the default theme, by their paths under `assets/`, in the
+/// order their `<link>` and `<script>` elements go in. Written to the output
+/// when the default theme is not installed.
+const BUILTIN_ASSETS: &[(&str, &[u8])] = &[
+    ("skeleton/normalize.css", include_bytes!("../themes/default/assets/skeleton/normalize.css")),
+    ("skeleton/skeleton.css", include_bytes!("../themes/default/assets/skeleton/skeleton.css")),
+    ("highlight/styles/github.css", include_bytes!("../themes/default/assets/highlight/styles/github.css")),
+    ("main.css", include_bytes!("../themes/default/assets/main.css")),
+    ("highlight/highlight.pack.js", include_bytes!("../themes/default/assets/highlight/highlight.pack.js")),
+];
+
+/// A theme and the site's overrides of it.
+pub struct Theme {
+    /// The theme's directory, if it was found at all.
+    dir: Option<PathBuf>,
+
+    /// The site's overrides, if there are any.
+    overrides: Option<PathBuf>,
+}
+
+impl Theme {
+
+    /// Finds a theme given either a path to its directory, or a bare name to
+    /// look up in `themes/` of the site directory and then of the current
+    /// directory. Returns `None` if there is no such theme, except for the
+    /// default theme, whose templates and assets are built in anyways.
+    pub fn find(name: &str, site_dir: &Path) -> Option<Self> {
+        let mut candidates: Vec<PathBuf> = Vec::new();
+
+        match is_bare(name) {
+            true => {
+                candidates.push(site_dir.join(THEMES_DIR).join(name));
+                if let Ok(curr_dir) = env::current_dir() {
+                    candidates.push(curr_dir.join(THEMES_DIR).join(name));
+                }
+            },
+
+            false => candidates.push(PathBuf::from(name)),
+        };
+
+        let overrides = Some(site_dir.join(OVERRIDES_DIR))
+            .filter(|dir| dir.is_dir());
+
+        match candidates.into_iter().find(|dir| dir.join("templates").is_dir() || dir.join("assets").is_dir()) {
+            Some(dir) => Some(Self {
+                dir: Some(dir),
+                overrides: overrides,
+            }),
+
+            None if name == DEFAULT_THEME => Some(Self {
+                dir: None,
+                overrides: overrides,
+            }),
+
+            None => None,
+        }
+    }
+
+    /// Where the theme lives, or `None` for the built-in default theme.
+    pub fn dir(&self) -> Option<&PathBuf> {
+        self.dir.as_ref()
+    }
+
+    /// The theme's directory followed by the overrides, for those that exist.
+    /// Later ones override files of earlier ones.
+    pub fn layers(&self) -> Vec<&PathBuf> {
+        self.dir.iter().chain(self.overrides.iter()).collect()
+    }
+
+    /// Assets to write out before those of `layers`, by their paths under
+    /// `assets/`: the built-in ones for the built-in default theme, and none
+    /// otherwise.
+    pub fn builtin_assets(&self) -> &'static [(&'static str, &'static [u8])] {
+        match self.dir {
+            Some(_) => &[],
+            None => BUILTIN_ASSETS,
+        }
+    }
+
+    /// The template directories, in the order they override each other.
+    pub fn template_dirs(&self) -> Vec<PathBuf> {
+        self.layers().into_iter()
+            .map(|dir| dir.join("templates"))
+            .filter(|dir| dir.is_dir())
+            .collect()
+    }
+
+}
+
+/// Whether a theme is given by name rather than by path.
+fn is_bare(name: &str) -> bool {
+    let mut components = Path::new(name).components();
+
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(_)), None) => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    #[test]
+    fn bare_names_and_builtin_assets() {
+        let site_dir = env::temp_dir().join(format!("writer2-theme-{}", std::process::id()));
+        fs::create_dir_all(site_dir.join(THEMES_DIR).join("plain").join("templates")).unwrap();
+
+        let theme = Theme::find("plain", &site_dir).unwrap();
+        assert_eq!(theme.dir(), Some(&site_dir.join(THEMES_DIR).join("plain")));
+        assert!(theme.builtin_assets().is_empty());
+
+        assert!(Theme::find("missing", &site_dir).is_none());
+        assert!(is_bare("plain"));
+        assert!(!is_bare("themes/plain"));
+        assert!(!is_bare("./plain"));
+
+        let theme = Theme { dir: None, overrides: None };
+        assert!(theme.builtin_assets().iter().any(|&(path, _)| path == "main.css"));
+
+        fs::remove_dir_all(&site_dir).unwrap();
+    }
+}
@@ -0,0 +1,140 @@
+use transforms::Transform;
+
+use cmark::*;
+
+/// Lets a paragraph consisting of nothing but `{...}` set HTML attributes on
+/// the block right after it, e.g.
+///
+/// ```markdown
+/// {reversed start=10 .exercises}
+///
+/// 10. Last one
+/// 9. Next to last
+/// ```
+///
+/// Inside the braces, `.name` adds a class, `#name` sets the id, `key=value`
+/// (or `key="some value"`) sets an attribute, and a bare `key` sets a boolean
+/// attribute. The attribute paragraph itself is removed. A paragraph that does
+/// not parse as attributes, or has no block after it, is left alone.
+pub struct BlockAttributes;
+
+impl BlockAttributes {
+    pub fn new() -> Self {
+        BlockAttributes
+    }
+
+    /// Parses the text of an attribute paragraph, or returns `None` if it is
+    /// not one.
+    fn parse(text: &str) -> Option<Vec<(String, String)>> {
+        let text = text.trim();
+        if !text.starts_with('{') || !text.ends_with('}') || text.len() < 2 {
+            return None;
+        }
+
+        let mut res: Vec<(String, String)> = Vec::new();
+        let mut classes: Vec<&str> = Vec::new();
+
+        for token in Self::tokenize(&text[1 .. text.len() - 1])? {
+            match (token.chars().next(), token.find('=')) {
+                (Some('.'), None) if token.len() > 1 => classes.push(&token[1..]),
+                (Some('#'), None) if token.len() > 1 =>
+                    res.push(("id".to_owned(), token[1..].to_owned())),
+
+                (_, Some(eq)) if Self::is_name(&token[.. eq]) => {
+                    let value = token[eq + 1 ..].trim_matches('"');
+                    res.push((token[.. eq].to_owned(), value.to_owned()));
+                },
+
+                (_, None) if Self::is_name(token) =>
+                    res.push((token.to_owned(), String::new())),
+
+                _ => return None,
+            };
+        }
+
+        if !classes.is_empty() {
+            res.push(("class".to_owned(), classes.join(" ")));
+        }
+
+        match res.is_empty() {
+            true => None,
+            false => Some(res),
+        }
+    }
+
+    /// Splits on whitespace, except within double quotes. Returns `None` on an
+    /// unterminated quote.
+    fn tokenize(text: &str) -> Option<Vec<&str>> {
+        let mut res = Vec::new();
+        let mut start = None;
+        let mut quoted = false;
+
+        for (i, ch) in text.char_indices() {
+            match (ch, quoted) {
+                ('"', _) => quoted = !quoted,
+                (_, false) if ch.is_whitespace() => {
+                    if let Some(s) = start.take() {
+                        res.push(&text[s .. i]);
+                    }
+                    continue;
+                },
+                _ => (),
+            };
+
+            if start.is_none() {
+                start = Some(i);
+            }
+        }
+
+        if let Some(s) = start {
+            res.push(&text[s ..]);
+        }
+
+        match quoted {
+            true => None,
+            false => Some(res),
+        }
+    }
+
+    fn is_name(name: &str) -> bool {
+        !name.is_empty() && name.chars()
+            .all(|ch| ch.is_ascii_alphanumeric() || ch == '-' || ch == '_')
+    }
+
+    /// Returns the attributes if `tree` is an attribute paragraph.
+    fn attributes_of(tree: &Tree) -> Option<Vec<(String, String)>> {
+        let only_text = tree.children.iter().all(|child| match child.node {
+            Node::Inline(Inline::Text(_)) => true,
+            _ => false,
+        });
+
+        match (&tree.node, only_text) {
+            (&Node::Block(Block::Paragraph), true) => Self::parse(&tree.text()),
+            _ => None,
+        }
+    }
+}
+
+impl Transform for BlockAttributes {
+    fn name(&self) -> &str {
+        "block-attributes"
+    }
+
+    fn transform(&mut self, document: &mut Document) -> Result<(), Error> {
+        document.root_mut().for_each_mut(|tree| {
+            let mut idx = 0;
+
+            while idx + 1 < tree.children.len() {
+                match Self::attributes_of(&tree.children[idx]) {
+                    Some(attributes) => {
+                        tree.children.remove(idx);
+                        tree.children[idx].attributes.extend(attributes);
+                    },
+                    None => idx += 1,
+                };
+            }
+        });
+
+        Ok(())
+    }
+}
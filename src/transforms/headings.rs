@@ -0,0 +1,63 @@
+use transforms::Transform;
+
+use cmark::*;
+
+/// Pushes every heading down by a fixed number of levels, e.g. so that a post
+/// can use `#` for its sections while the page template owns the `<h1>`.
+/// Headings never go below level 6.
+pub struct DemoteHeadings {
+    by: usize,
+}
+
+impl DemoteHeadings {
+    pub fn new(by: usize) -> Self {
+        Self {
+            by: by,
+        }
+    }
+}
+
+impl Transform for DemoteHeadings {
+    fn name(&self) -> &str {
+        "demote-headings"
+    }
+
+    fn transform(&mut self, document: &mut Document) -> Result<(), Error> {
+        let by = self.by;
+
+        document.root_mut().for_each_mut(|tree| {
+            let level = match tree.node {
+                Node::Block(Block::Heading(ref lvl)) => lvl.as_usize() + by,
+                _ => return,
+            };
+
+            tree.node.set_heading_level(
+                HeadingLevel::from_usize(level).unwrap_or(HeadingLevel::Six)
+            );
+        });
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn demote() {
+        let mut parser = Parser::new(Options::DEFAULT);
+        parser.feed(b"# One\n\n#### Four\n\n###### Six\n");
+        let mut document = Document::from_parser(parser).unwrap();
+
+        DemoteHeadings::new(2).transform(&mut document).unwrap();
+
+        let levels: Vec<usize> = document.root().children.iter()
+            .map(|tree| match tree.node {
+                Node::Block(Block::Heading(ref lvl)) => lvl.as_usize(),
+                _ => 0,
+            })
+            .collect();
+        assert_eq!(levels, vec![3, 6, 6]);
+    }
+}
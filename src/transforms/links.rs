@@ -0,0 +1,76 @@
+use transforms::Transform;
+
+use cmark::*;
+
+/// Rewrites relative links to other markdown files so that they point to the
+/// generated HTML instead, e.g. `other.md#usage` becomes `other.html#usage`.
+/// Links with a scheme, such as `https://...` or `mailto:...`, are left alone,
+/// and so are links to another host, such as `//example.com/...`.
+pub struct MarkdownLinks;
+
+impl MarkdownLinks {
+    pub fn new() -> Self {
+        MarkdownLinks
+    }
+
+    /// Returns the rewritten URL, or `None` if `url` should stay as it is.
+    fn rewrite(url: &str) -> Option<String> {
+        //  anything before the first '/', '?' or '#' that contains ':' is a
+        //  scheme, so this is not a relative link
+        let prefix_end = url.find(|ch| ch == '/' || ch == '?' || ch == '#')
+            .unwrap_or(url.len());
+        if url[..prefix_end].contains(':') || url.starts_with("//") {
+            return None;
+        }
+
+        let path_end = url.find(|ch| ch == '?' || ch == '#')
+            .unwrap_or(url.len());
+        let (path, rest) = url.split_at(path_end);
+
+        match path.ends_with(".md") {
+            true => Some(format!("{}.html{}", &path[..path.len() - 3], rest)),
+            false => None,
+        }
+    }
+}
+
+impl Transform for MarkdownLinks {
+    fn name(&self) -> &str {
+        "markdown-links"
+    }
+
+    fn transform(&mut self, document: &mut Document) -> Result<(), Error> {
+        document.root_mut().for_each_mut(|tree| {
+            let new_url = match tree.node {
+                Node::Inline(Inline::Link(ref url, _)) => Self::rewrite(url),
+                _ => None,
+            };
+
+            if let Some(new_url) = new_url {
+                tree.node.set_url(&new_url);
+            }
+        });
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rewrite() {
+        assert_eq!(MarkdownLinks::rewrite("other.md"), Some("other.html".to_owned()));
+        assert_eq!(MarkdownLinks::rewrite("../docs/a.md"), Some("../docs/a.html".to_owned()));
+        assert_eq!(MarkdownLinks::rewrite("/docs/a.md"), Some("/docs/a.html".to_owned()));
+        assert_eq!(MarkdownLinks::rewrite("a.md?raw=1#usage"), Some("a.html?raw=1#usage".to_owned()));
+        assert_eq!(MarkdownLinks::rewrite("a.md#x.md"), Some("a.html#x.md".to_owned()));
+
+        assert_eq!(MarkdownLinks::rewrite("https://example.com/a.md"), None);
+        assert_eq!(MarkdownLinks::rewrite("//example.com/a.md"), None);
+        assert_eq!(MarkdownLinks::rewrite("mailto:a.md"), None);
+        assert_eq!(MarkdownLinks::rewrite("a.txt"), None);
+        assert_eq!(MarkdownLinks::rewrite("#a.md"), None);
+    }
+}
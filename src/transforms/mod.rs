@@ -0,0 +1,100 @@
+pub mod attributes;
+pub mod headings;
+pub mod links;
+
+use cmark::{ Document, Error };
+
+/// Names of the built-in transforms, as accepted by `Pipeline::from_names`.
+pub const BUILTIN_NAMES: &[&str] = &[
+    "block-attributes",
+    "demote-headings",
+    "markdown-links",
+];
+
+/// A pass over a parsed document, run after parsing and before any
+/// `Converter` gets to see the document.
+pub trait Transform {
+    /// Short name of the transform, used to select it on the command line and
+    /// in log messages.
+    fn name(&self) -> &str;
+
+    fn transform(&mut self, document: &mut Document) -> Result<(), Error>;
+}
+
+/// Errors during pipeline construction.
+#[derive(Debug)]
+pub enum PipelineErr {
+    /// No transform goes by this name.
+    UnknownTransform(String),
+}
+
+/// An ordered list of transforms, run one after another on every document.
+pub struct Pipeline {
+    transforms: Vec<Box<dyn Transform>>,
+}
+
+impl Pipeline {
+    /// Creates an empty pipeline, which leaves documents untouched.
+    pub fn new() -> Self {
+        Self {
+            transforms: Vec::new(),
+        }
+    }
+
+    /// Creates a pipeline out of the built-in transforms with the given names,
+    /// in the given order.
+    pub fn from_names<S: AsRef<str>>(names: &[S]) -> Result<Self, PipelineErr> {
+        let mut res = Self::new();
+
+        for name in names {
+            res.push(match name.as_ref() {
+                "block-attributes" => Box::new(attributes::BlockAttributes::new()),
+                "demote-headings" => Box::new(headings::DemoteHeadings::new(1)),
+                "markdown-links" => Box::new(links::MarkdownLinks::new()),
+                other => {
+                    return Err(PipelineErr::UnknownTransform(other.to_owned()));
+                },
+            });
+        }
+
+        Ok(res)
+    }
+
+    /// Adds a transform at the end of the pipeline.
+    pub fn push(&mut self, transform: Box<dyn Transform>) {
+        self.transforms.push(transform);
+    }
+
+    /// Runs every transform on `document`, stopping at the first failure.
+    pub fn run(&mut self, document: &mut Document) -> Result<(), Error> {
+        for transform in self.transforms.iter_mut() {
+            info!("Running transform {}...", transform.name());
+            transform.transform(document)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for Pipeline {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_names() {
+        let pipeline = Pipeline::from_names(&["markdown-links", "demote-headings", "block-attributes"]).unwrap();
+        let names: Vec<&str> = pipeline.transforms.iter().map(|transform| transform.name()).collect();
+        assert_eq!(names, vec!["markdown-links", "demote-headings", "block-attributes"]);
+
+        match Pipeline::from_names(&["demote-headings", "smart-quotes"]) {
+            Err(PipelineErr::UnknownTransform(name)) => assert_eq!(name, "smart-quotes"),
+            Ok(_) => panic!("smart-quotes is not a transform"),
+        };
+    }
+}